@@ -1,11 +1,19 @@
 import os
 import argparse
 import psycopg2
+from zoneinfo import available_timezones
+
+
+# The notification service falls back to UTC for timezones it doesn't know, so they are rejected up front.
+def is_valid_timezone(timezone):
+    return timezone in available_timezones()
 
 
 def add_admin_data(cursor, admin):
     try:
-        if all(
+        if admin.get("timezone") and not is_valid_timezone(admin["timezone"]):
+            print(f"Error: Unknown timezone {admin['timezone']}, expected an IANA name, e.g. Europe/Warsaw.")
+        elif all(
             key in admin.keys()
             for key in [
                 "admin_id",
@@ -15,13 +23,15 @@ def add_admin_data(cursor, admin):
             ]
         ):
             cursor.execute(
-                "INSERT INTO admin (admin_id, telegram_contact_id, phone_number, email_address, is_removed) VALUES (%s, %s, %s, %s, %s) RETURNING admin_id",
+                "INSERT INTO admin (admin_id, telegram_contact_id, phone_number, email_address, is_removed, timezone, quiet_hours_action) VALUES (%s, %s, %s, %s, %s, %s, %s) RETURNING admin_id",
                 (
                     admin["admin_id"],
                     admin["telegram_contact_id"],
                     admin["phone_number"],
                     admin["email_address"],
-                    False
+                    False,
+                    admin.get("timezone") or "UTC",
+                    admin.get("quiet_hours_action") or "defer",
                 ),
             )
             admin_id = cursor.fetchone()[0]
@@ -43,7 +53,9 @@ def update_admin_data(cursor, admin):
             if key != "admin_id" and admin[key] is not None
         }
 
-        if admin_id and update_values:
+        if "timezone" in update_values and not is_valid_timezone(update_values["timezone"]):
+            print(f"Error: Unknown timezone {update_values['timezone']}, expected an IANA name, e.g. Europe/Warsaw.")
+        elif admin_id and update_values:
             # Check if admin_id exists
             cursor.execute(
                 "SELECT admin_id FROM admin WHERE admin_id = %s", (admin_id,)
@@ -73,10 +85,102 @@ def delete_admin_data(cursor, admin):
         if admin_id:
             cursor.execute("UPDATE admin SET is_removed = True WHERE admin_id = %s", (admin_id,))
             print(f"Admin with admin_id {admin_id} deleted successfully")
+            return warn_about_endpoints_with_removed_admins(cursor, admin_id)
         else:
             print("Error: Provide admin_id")
     except Exception as e:
         print(f"Error deleting admin: {e}")
+    return []
+
+
+# Endpoints still escalating to removed admins, of the given admin or of all of them.
+# The notification service skips a removed primary admin and pages FALLBACK_ADMIN instead of a removed secondary one.
+def warn_about_endpoints_with_removed_admins(cursor, admin_id=None):
+    cursor.execute(
+        """
+        SELECT e.endpoint_id, e.http_address, 'primary', a.admin_id
+        FROM endpoint_data e JOIN admin a ON a.admin_id = e.conf_primary_admin
+        WHERE a.is_removed AND NOT e.is_removed AND (%(admin_id)s IS NULL OR a.admin_id = %(admin_id)s)
+        UNION ALL
+        SELECT e.endpoint_id, e.http_address, 'secondary', a.admin_id
+        FROM endpoint_data e JOIN admin a ON a.admin_id = e.conf_secondary_admin
+        WHERE a.is_removed AND NOT e.is_removed AND (%(admin_id)s IS NULL OR a.admin_id = %(admin_id)s)
+        ORDER BY 1, 3
+        """,
+        {"admin_id": admin_id},
+    )
+    warnings = [
+        f"Endpoint {endpoint_id} ({http_address}) references removed {role} admin {removed_admin_id}"
+        for endpoint_id, http_address, role, removed_admin_id in cursor.fetchall()
+    ]
+    for warning in warnings:
+        print(f"Warning: {warning}")
+    return warnings
+
+
+def add_contact_method(cursor, contact_method):
+    try:
+        cursor.execute(
+            "INSERT INTO admin_contact_method (admin_id, channel, address, rank) VALUES (%s, %s, %s, %s) RETURNING contact_method_id",
+            (
+                contact_method["admin_id"],
+                contact_method["channel"],
+                contact_method["address"],
+                contact_method.get("rank") or 1,
+            ),
+        )
+        contact_method_id = cursor.fetchone()[0]
+        print(f"Contact method added successfully with contact_method_id: {contact_method_id}")
+    except Exception as e:
+        print(f"Error adding contact method: {e}")
+
+
+def delete_contact_method(cursor, contact_method):
+    try:
+        cursor.execute(
+            "DELETE FROM admin_contact_method WHERE admin_id = %s AND channel = %s AND address = %s",
+            (
+                contact_method["admin_id"],
+                contact_method["channel"],
+                contact_method["address"],
+            ),
+        )
+        print(f"Deleted {cursor.rowcount} contact method(s) of admin {contact_method['admin_id']}")
+    except Exception as e:
+        print(f"Error deleting contact method: {e}")
+
+
+def set_notification_rule(cursor, rule):
+    try:
+        cursor.execute(
+            """
+            INSERT INTO admin_notification_rule (admin_id, severity, channels) VALUES (%s, %s, %s)
+            ON CONFLICT (admin_id, severity) DO UPDATE SET channels = EXCLUDED.channels
+            """,
+            (rule["admin_id"], rule["severity"], rule["channels"]),
+        )
+        print(f"Notification rule for {rule['severity']} outages of admin {rule['admin_id']} set to {rule['channels']}")
+    except Exception as e:
+        print(f"Error setting notification rule: {e}")
+
+
+def add_quiet_hours(cursor, quiet_hours):
+    try:
+        cursor.execute(
+            "INSERT INTO admin_quiet_hours (admin_id, start_time, end_time) VALUES (%s, %s, %s) ON CONFLICT DO NOTHING",
+            (quiet_hours["admin_id"], quiet_hours["start_time"], quiet_hours["end_time"]),
+        )
+        print(f"Quiet hours {quiet_hours['start_time']}-{quiet_hours['end_time']} added to admin {quiet_hours['admin_id']}")
+    except Exception as e:
+        print(f"Error adding quiet hours: {e}")
+
+
+def clear_quiet_hours(cursor, quiet_hours):
+    try:
+        cursor.execute("DELETE FROM admin_quiet_hours WHERE admin_id = %s", (quiet_hours["admin_id"],))
+        print(f"Deleted {cursor.rowcount} quiet hours window(s) of admin {quiet_hours['admin_id']}")
+    except Exception as e:
+        print(f"Error clearing quiet hours: {e}")
 
 
 def get_db_params():
@@ -104,19 +208,54 @@ def main():
     group.add_argument("--add", action="store_true", help="Add a new admin")
     group.add_argument("--update", action="store_true", help="Update an existing admin")
     group.add_argument("--delete", action="store_true", help="Delete an admin")
-    parser.add_argument("--admin-id", required=True, help="Id of admin")
+    group.add_argument("--add-contact", action="store_true", help="Add a contact method of an admin")
+    group.add_argument("--delete-contact", action="store_true", help="Delete a contact method of an admin")
+    group.add_argument("--set-rule", action="store_true", help="Set channels used for outages of given severity")
+    group.add_argument("--add-quiet-hours", action="store_true", help="Add a quiet hours window of an admin")
+    group.add_argument("--clear-quiet-hours", action="store_true", help="Delete all quiet hours windows of an admin")
+    group.add_argument("--check-endpoints", action="store_true", help="List endpoints referencing removed admins, of --admin-id if given")
+    parser.add_argument("--admin-id", required=False, help="Id of admin")
     parser.add_argument(
         "--telegram-contact-id", required=False, help="telegram contact ID of the admin"
     )
     parser.add_argument("--phone", required=False, help="Phone number of the admin")
     parser.add_argument("--email", required=False, help="Email address of the admin")
+    parser.add_argument("--timezone", required=False, help="IANA timezone of the admin, e.g. Europe/Warsaw, defaults to UTC")
+    parser.add_argument("--quiet-hours-action", required=False, choices=["defer", "escalate"], help="Whether non-critical notifications are deferred or escalated during quiet hours, defaults to defer")
+    parser.add_argument("--start", required=False, help="Start of the quiet hours window in the admin's timezone, e.g. 22:00")
+    parser.add_argument("--end", required=False, help="End of the quiet hours window in the admin's timezone, e.g. 07:00")
+    parser.add_argument("--channel", required=False, choices=["telegram", "email", "sms"], help="Channel of the contact method")
+    parser.add_argument("--address", required=False, help="Telegram id, email address or phone number of the contact method")
+    parser.add_argument("--rank", type=int, required=False, default=1, help="Contact methods of lower rank are tried first, equal ranks are used together")
+    parser.add_argument("--severity", required=False, choices=["critical", "warning", "info"], help="Severity the rule applies to")
+    parser.add_argument("--channels", required=False, help="Comma separated channels used for outages of given severity, e.g. telegram,sms")
     args = parser.parse_args()
+    if not args.check_endpoints and not args.admin_id:
+        parser.error("--admin-id is required")
 
     admin_data = {
         "admin_id": args.admin_id,
         "telegram_contact_id": args.telegram_contact_id,
         "phone_number": args.phone,
         "email_address": args.email,
+        "timezone": args.timezone,
+        "quiet_hours_action": args.quiet_hours_action,
+    }
+    quiet_hours = {
+        "admin_id": args.admin_id,
+        "start_time": args.start,
+        "end_time": args.end,
+    }
+    contact_method = {
+        "admin_id": args.admin_id,
+        "channel": args.channel,
+        "address": args.address,
+        "rank": args.rank,
+    }
+    rule = {
+        "admin_id": args.admin_id,
+        "severity": args.severity,
+        "channels": args.channels,
     }
 
     db_connection, db_cursor = establish_db_connection()
@@ -128,6 +267,18 @@ def main():
             update_admin_data(db_cursor, admin_data)
         elif args.delete:
             delete_admin_data(db_cursor, admin_data)
+        elif args.add_contact:
+            add_contact_method(db_cursor, contact_method)
+        elif args.delete_contact:
+            delete_contact_method(db_cursor, contact_method)
+        elif args.set_rule:
+            set_notification_rule(db_cursor, rule)
+        elif args.add_quiet_hours:
+            add_quiet_hours(db_cursor, quiet_hours)
+        elif args.clear_quiet_hours:
+            clear_quiet_hours(db_cursor, quiet_hours)
+        elif args.check_endpoints:
+            warn_about_endpoints_with_removed_admins(db_cursor, args.admin_id)
 
         db_connection.commit()
 
@@ -2,8 +2,23 @@ import os
 import argparse
 import psycopg2
 
+# Admins of the endpoint that were removed, the endpoint is still added.
+def warn_about_removed_admins(cursor, endpoint_data):
+    warnings = []
+    for role in ['primary', 'secondary']:
+        admin_id = endpoint_data[f'conf_{role}_admin']
+        cursor.execute("SELECT is_removed FROM admin WHERE admin_id = %s", (admin_id,))
+        row = cursor.fetchone()
+        if row is not None and row[0]:
+            warnings.append(f"{role.capitalize()} admin {admin_id} of {endpoint_data['http_address']} is removed")
+    for warning in warnings:
+        print(f"Warning: {warning}")
+    return warnings
+
+
 def add_endpoint_data(cursor, endpoint_data):
     try:
+        warnings = warn_about_removed_admins(cursor, endpoint_data)
         cursor.execute("""
             INSERT INTO endpoint_data (
                 http_address,
@@ -21,8 +36,12 @@ def add_endpoint_data(cursor, endpoint_data):
                 ntf_first_responded,
                 is_removed,
                 frequency,
-                last_ping_time                       
-            ) VALUES (%s, %s, %s, %s, %s, %s, %s, %s, %s, %s, %s, %s, %s, %s, %s, %s)
+                last_ping_time,
+                conf_webhook_url,
+                conf_chat_channel,
+                conf_incident_routing_key,
+                conf_severity
+            ) VALUES (%s, %s, %s, %s, %s, %s, %s, %s, %s, %s, %s, %s, %s, %s, %s, %s, %s, %s, %s, %s)
             RETURNING endpoint_id
         """, (
             endpoint_data['http_address'],
@@ -40,15 +59,21 @@ def add_endpoint_data(cursor, endpoint_data):
             endpoint_data['ntf_first_responded'],
             endpoint_data['is_removed'],
             endpoint_data['frequency'],
-            endpoint_data['last_ping_time']
+            endpoint_data['last_ping_time'],
+            endpoint_data['conf_webhook_url'],
+            endpoint_data['conf_chat_channel'],
+            endpoint_data['conf_incident_routing_key'],
+            endpoint_data['conf_severity']
         ))
 
         endpoint_id = cursor.fetchone()[0]
 
         print(f"Endpoint {endpoint_id} added successfully!")
+        return warnings
 
     except Exception as e:
         print(f"Error adding endpoint: {e}")
+    return []
 
 def delete_endpoint_data(cursor, endpoint_id):
     try:
@@ -75,7 +100,11 @@ def get_endpoint_from_dict(args):
         'ntf_first_responded': False,
         'is_removed': False,
         'last_ping_time': None,
-        'frequency': args.frequency
+        'frequency': args.frequency,
+        'conf_webhook_url': args.webhook_url,
+        'conf_chat_channel': args.chat_channel,
+        'conf_incident_routing_key': args.incident_routing_key,
+        'conf_severity': args.severity
     }
 
 def main():
@@ -87,6 +116,10 @@ def main():
     parser.add_argument('--is-down', default=False)
     parser.add_argument('--outage-id', type=str, required=False, help='outage id', default=None)
     parser.add_argument('--frequency', type=str, required=True, help='frequency')
+    parser.add_argument('--webhook-url', type=str, required=False, help='webhook notified about outages of this endpoint', default=None)
+    parser.add_argument('--chat-channel', type=str, required=False, help='Slack/Mattermost incoming webhook url or channel id of the team owning the endpoint', default=None)
+    parser.add_argument('--incident-routing-key', type=str, required=False, help='PagerDuty integration key or Opsgenie API key of the team owning the endpoint', default=None)
+    parser.add_argument('--severity', type=str, required=False, choices=['critical', 'warning', 'info'], help='severity of outages of this endpoint', default='critical')
     
     args = parser.parse_args()
 
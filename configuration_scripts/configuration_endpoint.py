@@ -1,12 +1,29 @@
 from fastapi import FastAPI
 from add_admin import (
     add_admin_data,
+    add_contact_method,
+    add_quiet_hours,
+    clear_quiet_hours,
     delete_admin_data,
+    delete_contact_method,
     establish_db_connection,
+    set_notification_rule,
     update_admin_data,
+    warn_about_endpoints_with_removed_admins,
 )
 from add_endpoint import get_endpoint_from_dict, delete_endpoint_data, add_endpoint_data
-from configuration_types import AddAdminRequest, AddEndpointRequest, DeleteEndpointRequest, UpdateAdminRequest, DeleteAdminRequest
+from configuration_types import (
+    AddAdminRequest,
+    AddContactMethodRequest,
+    AddEndpointRequest,
+    AddQuietHoursRequest,
+    ClearQuietHoursRequest,
+    DeleteAdminRequest,
+    DeleteContactMethodRequest,
+    DeleteEndpointRequest,
+    SetNotificationRuleRequest,
+    UpdateAdminRequest,
+)
 
 app = FastAPI()
 db_connection, db_cursor = establish_db_connection()
@@ -42,18 +59,68 @@ def update_admin(body: UpdateAdminRequest):
 def delete_admin(body: DeleteAdminRequest):
     admin_data = get_dict_from_body(body)
     try:
-        delete_admin_data(db_cursor, admin_data)
+        warnings = delete_admin_data(db_cursor, admin_data)
         db_connection.commit()
+        return {"warnings": warnings}
     except Exception as e:
         db_connection.rollback()
         print(f"Error during admin data operation: {e}")
 
+@app.post("/add_contact_method/")
+def add_contact(body: AddContactMethodRequest):
+    try:
+        add_contact_method(db_cursor, body.dict())
+        db_connection.commit()
+    except Exception as e:
+        db_connection.rollback()
+        print(f"Error during contact method operation: {e}")
+
+
+@app.post("/delete_contact_method/")
+def delete_contact(body: DeleteContactMethodRequest):
+    try:
+        delete_contact_method(db_cursor, body.dict())
+        db_connection.commit()
+    except Exception as e:
+        db_connection.rollback()
+        print(f"Error during contact method operation: {e}")
+
+
+@app.post("/set_notification_rule/")
+def set_rule(body: SetNotificationRuleRequest):
+    try:
+        set_notification_rule(db_cursor, body.dict())
+        db_connection.commit()
+    except Exception as e:
+        db_connection.rollback()
+        print(f"Error during notification rule operation: {e}")
+
+@app.post("/add_quiet_hours/")
+def add_quiet_hours_window(body: AddQuietHoursRequest):
+    try:
+        add_quiet_hours(db_cursor, body.dict())
+        db_connection.commit()
+    except Exception as e:
+        db_connection.rollback()
+        print(f"Error during quiet hours operation: {e}")
+
+
+@app.post("/clear_quiet_hours/")
+def clear_quiet_hours_windows(body: ClearQuietHoursRequest):
+    try:
+        clear_quiet_hours(db_cursor, body.dict())
+        db_connection.commit()
+    except Exception as e:
+        db_connection.rollback()
+        print(f"Error during quiet hours operation: {e}")
+
 @app.post("/add_endpoint/")
 def add_endpoint(body: AddEndpointRequest):
     req_data = get_endpoint_from_dict(body)
     try:
-        add_endpoint_data(db_cursor, req_data)
+        warnings = add_endpoint_data(db_cursor, req_data)
         db_connection.commit()
+        return {"warnings": warnings}
     except Exception as e:
         db_connection.rollback()
         print(f"Error: during endpoint data operation: {e}")
@@ -69,6 +136,15 @@ def delete_endpoint(body: DeleteEndpointRequest):
         print(f"Error: during endpoint data operation: {e}")
     
 
+@app.get("/endpoints_with_removed_admins/")
+def endpoints_with_removed_admins():
+    try:
+        return {"warnings": warn_about_endpoints_with_removed_admins(db_cursor)}
+    except Exception as e:
+        db_connection.rollback()
+        print(f"Error during endpoint data operation: {e}")
+
+
 if __name__ == "__main__":
     import uvicorn
 
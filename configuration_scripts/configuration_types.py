@@ -1,4 +1,12 @@
-from pydantic import BaseModel
+from pydantic import BaseModel, field_validator
+
+from add_admin import is_valid_timezone
+
+
+def check_timezone(timezone):
+    if timezone is not None and not is_valid_timezone(timezone):
+        raise ValueError(f"unknown timezone {timezone}, expected an IANA name, e.g. Europe/Warsaw")
+    return timezone
 
 
 class AddAdminRequest(BaseModel):
@@ -6,6 +14,10 @@ class AddAdminRequest(BaseModel):
     telegram_contact_id: str
     phone_number: str
     email_address: str
+    timezone: str = "UTC"
+    quiet_hours_action: str = "defer"
+
+    _check_timezone = field_validator("timezone")(check_timezone)
 
 
 class UpdateAdminRequest(BaseModel):
@@ -13,6 +25,10 @@ class UpdateAdminRequest(BaseModel):
     telegram_contact_id: str = None
     phone_number: str = None
     email_address: str = None
+    timezone: str = None
+    quiet_hours_action: str = None
+
+    _check_timezone = field_validator("timezone")(check_timezone)
 
 
 class DeleteAdminRequest(BaseModel):
@@ -26,6 +42,39 @@ class AddEndpointRequest(BaseModel):
     secondary_admin: str
     response_duration: str
     frequency: str
+    webhook_url: str = None
+    chat_channel: str = None
+    incident_routing_key: str = None
+    severity: str = "critical"
 
 class DeleteEndpointRequest(BaseModel):
     endpoint_id: str
+
+
+class AddContactMethodRequest(BaseModel):
+    admin_id: str
+    channel: str
+    address: str
+    rank: int = 1
+
+
+class DeleteContactMethodRequest(BaseModel):
+    admin_id: str
+    channel: str
+    address: str
+
+
+class SetNotificationRuleRequest(BaseModel):
+    admin_id: str
+    severity: str
+    channels: str
+
+
+class AddQuietHoursRequest(BaseModel):
+    admin_id: str
+    start_time: str
+    end_time: str
+
+
+class ClearQuietHoursRequest(BaseModel):
+    admin_id: str
@@ -48,8 +48,10 @@ def acknowledge_alert(client: TelegramClient):
 def check_alert(client: TelegramClient, enpdoint_id, admin, http_address, is_first) -> bool:
     messages = client.get_messages('alertingPlatformTestBot', limit=2)
     print(messages[0].message)
-    tokens = messages[0].message.split(';')
-    return tokens[0] == 'endpoint=' + enpdoint_id and tokens[2] == "is_first=" + str(is_first).lower() and tokens[3] == "admin=" + admin and tokens[4] == "http_address=" + http_address
+    lines = messages[0].message.splitlines()
+    fields = dict(line.split(': ', 1) for line in lines[1:] if ': ' in line)
+    level = '1' if is_first else '2'
+    return http_address in lines[0] and fields.get('Endpoint') == enpdoint_id and fields.get('Level') == level and fields.get('Admin') == admin
 
 def turn_off_service():
     requests.post(f'{TEST_SERVICE_URL}/shutdown')
@@ -4,7 +4,9 @@ CREATE TABLE IF NOT EXISTS admin (
     telegram_contact_id VARCHAR(255) NOT NULL,
     phone_number VARCHAR(20) NOT NULL,
     email_address VARCHAR(255) NOT NULL,
-    is_removed BOOLEAN NOT NULL
+    is_removed BOOLEAN NOT NULL,
+    timezone VARCHAR(64) NOT NULL DEFAULT 'UTC',
+    quiet_hours_action VARCHAR(16) NOT NULL DEFAULT 'defer'
 );
 """
 
@@ -26,8 +28,180 @@ CREATE TABLE IF NOT EXISTS endpoint_data (
     ntf_first_responded BOOLEAN NOT NULL,
     is_removed BOOLEAN NOT NULL,
     last_ping_time TIMESTAMP,
-    frequency INTERVAL NOT NULL
+    frequency INTERVAL NOT NULL,
+    ntf_muted_until TIMESTAMP,
+    ntf_responded_by VARCHAR(255) REFERENCES admin(admin_id),
+    ntf_responded_timestamp TIMESTAMP,
+    conf_webhook_url VARCHAR(2048),
+    conf_chat_channel VARCHAR(2048),
+    outage_start_timestamp TIMESTAMP,
+    ntf_is_resolve_pending BOOLEAN NOT NULL DEFAULT FALSE,
+    conf_incident_routing_key VARCHAR(255),
+    outage_failure_reason TEXT,
+    conf_severity VARCHAR(16) NOT NULL DEFAULT 'critical',
+    ntf_claim_generation BIGINT NOT NULL DEFAULT 0,
+    ntf_resolve_claimed_timestamp TIMESTAMP,
+    ntf_reminders_sent INT NOT NULL DEFAULT 0,
+    ntf_last_notification_timestamp TIMESTAMP
+);
+"""
+
+CREATE_SMS_DELIVERY_DB_QUERY = """
+CREATE TABLE IF NOT EXISTS sms_delivery (
+    provider_message_id VARCHAR(255) PRIMARY KEY,
+    endpoint_id INTEGER REFERENCES endpoint_data(endpoint_id) NOT NULL,
+    outage_id UUID NOT NULL,
+    admin_id VARCHAR(255) REFERENCES admin(admin_id) NOT NULL,
+    status VARCHAR(32) NOT NULL,
+    error_code VARCHAR(32),
+    sent_timestamp TIMESTAMP NOT NULL,
+    status_timestamp TIMESTAMP NOT NULL
+);
+"""
+
+CREATE_CHAT_THREAD_DB_QUERY = """
+CREATE TABLE IF NOT EXISTS chat_thread (
+    outage_id UUID NOT NULL,
+    channel VARCHAR(2048) NOT NULL,
+    thread_id VARCHAR(255) NOT NULL,
+    created_timestamp TIMESTAMP NOT NULL,
+    PRIMARY KEY (outage_id, channel)
+);
+"""
+
+CREATE_TELEGRAM_MESSAGE_DB_QUERY = """
+CREATE TABLE IF NOT EXISTS telegram_message (
+    chat_id VARCHAR(255) NOT NULL,
+    message_id INTEGER NOT NULL,
+    outage_id UUID NOT NULL,
+    ack_token TEXT NOT NULL,
+    sent_timestamp TIMESTAMP NOT NULL,
+    PRIMARY KEY (chat_id, message_id)
+);
+"""
+
+CREATE_NOTIFICATION_LOG_DB_QUERY = """
+CREATE TABLE IF NOT EXISTS notification_log (
+    log_id SERIAL PRIMARY KEY,
+    outage_id UUID NOT NULL,
+    endpoint_id INTEGER REFERENCES endpoint_data(endpoint_id) NOT NULL,
+    admin_id VARCHAR(255) REFERENCES admin(admin_id) NOT NULL,
+    escalation_level INTEGER NOT NULL,
+    channel VARCHAR(32) NOT NULL,
+    target VARCHAR(2048) NOT NULL,
+    status VARCHAR(32) NOT NULL,
+    error TEXT,
+    attempt_timestamp TIMESTAMP NOT NULL
+);
+"""
+
+CREATE_ADMIN_CONTACT_METHOD_DB_QUERY = """
+CREATE TABLE IF NOT EXISTS admin_contact_method (
+    contact_method_id SERIAL PRIMARY KEY,
+    admin_id VARCHAR(255) REFERENCES admin(admin_id) NOT NULL,
+    channel VARCHAR(32) NOT NULL,
+    address VARCHAR(255) NOT NULL,
+    rank INTEGER NOT NULL,
+    UNIQUE (admin_id, channel, address)
+);
+"""
+
+CREATE_ADMIN_NOTIFICATION_RULE_DB_QUERY = """
+CREATE TABLE IF NOT EXISTS admin_notification_rule (
+    admin_id VARCHAR(255) REFERENCES admin(admin_id) NOT NULL,
+    severity VARCHAR(16) NOT NULL,
+    channels VARCHAR(255) NOT NULL,
+    PRIMARY KEY (admin_id, severity)
 );
 """
 
-DATABASE_SETUP_QUERIES = [CREATE_ADMIN_TABLE_DB_QUERY, CREATE_ENDPOINT_DATA_DB_QUERY]
+CREATE_ADMIN_QUIET_HOURS_DB_QUERY = """
+CREATE TABLE IF NOT EXISTS admin_quiet_hours (
+    admin_id VARCHAR(255) REFERENCES admin(admin_id) NOT NULL,
+    start_time TIME NOT NULL,
+    end_time TIME NOT NULL,
+    PRIMARY KEY (admin_id, start_time, end_time)
+);
+"""
+
+CREATE_NOTIFICATION_GROUP_DB_QUERY = """
+CREATE TABLE IF NOT EXISTS notification_group (
+    group_id UUID PRIMARY KEY,
+    admin_id VARCHAR(255) REFERENCES admin(admin_id) NOT NULL,
+    sent_timestamp TIMESTAMP NOT NULL
+);
+"""
+
+CREATE_NOTIFICATION_GROUP_MEMBER_DB_QUERY = """
+CREATE TABLE IF NOT EXISTS notification_group_member (
+    group_id UUID REFERENCES notification_group(group_id) ON DELETE CASCADE NOT NULL,
+    endpoint_id INTEGER REFERENCES endpoint_data(endpoint_id) NOT NULL,
+    outage_id UUID NOT NULL,
+    is_first BOOLEAN NOT NULL,
+    PRIMARY KEY (group_id, outage_id)
+);
+"""
+
+CREATE_NOTIFICATION_OUTBOX_DB_QUERY = """
+CREATE TABLE IF NOT EXISTS notification_outbox (
+    idempotency_key UUID PRIMARY KEY,
+    outage_id UUID NOT NULL,
+    escalation_level INTEGER NOT NULL,
+    admin_id VARCHAR(255) REFERENCES admin(admin_id) NOT NULL,
+    channel VARCHAR(32) NOT NULL,
+    status VARCHAR(32) NOT NULL,
+    created_timestamp TIMESTAMP NOT NULL,
+    completed_timestamp TIMESTAMP
+);
+"""
+
+CREATE_NOTIFICATION_DEAD_LETTER_DB_QUERY = """
+CREATE TABLE IF NOT EXISTS notification_dead_letter (
+    dead_letter_id SERIAL PRIMARY KEY,
+    endpoint_id INTEGER REFERENCES endpoint_data(endpoint_id) NOT NULL,
+    outage_id UUID,
+    reason TEXT NOT NULL,
+    created_timestamp TIMESTAMP NOT NULL
+);
+"""
+
+# Keep databases created before a column was introduced up to date.
+ALTER_ADMIN_DB_QUERIES = [
+    "ALTER TABLE admin ADD COLUMN IF NOT EXISTS timezone VARCHAR(64) NOT NULL DEFAULT 'UTC';",
+    "ALTER TABLE admin ADD COLUMN IF NOT EXISTS quiet_hours_action VARCHAR(16) NOT NULL DEFAULT 'defer';",
+]
+
+ALTER_ENDPOINT_DATA_DB_QUERIES = [
+    "ALTER TABLE endpoint_data ADD COLUMN IF NOT EXISTS ntf_muted_until TIMESTAMP;",
+    "ALTER TABLE endpoint_data ADD COLUMN IF NOT EXISTS ntf_responded_by VARCHAR(255) REFERENCES admin(admin_id);",
+    "ALTER TABLE endpoint_data ADD COLUMN IF NOT EXISTS ntf_responded_timestamp TIMESTAMP;",
+    "ALTER TABLE endpoint_data ADD COLUMN IF NOT EXISTS conf_webhook_url VARCHAR(2048);",
+    "ALTER TABLE endpoint_data ADD COLUMN IF NOT EXISTS conf_chat_channel VARCHAR(2048);",
+    "ALTER TABLE endpoint_data ADD COLUMN IF NOT EXISTS outage_start_timestamp TIMESTAMP;",
+    "ALTER TABLE endpoint_data ADD COLUMN IF NOT EXISTS ntf_is_resolve_pending BOOLEAN NOT NULL DEFAULT FALSE;",
+    "ALTER TABLE endpoint_data ADD COLUMN IF NOT EXISTS conf_incident_routing_key VARCHAR(255);",
+    "ALTER TABLE endpoint_data ADD COLUMN IF NOT EXISTS outage_failure_reason TEXT;",
+    "ALTER TABLE endpoint_data ADD COLUMN IF NOT EXISTS conf_severity VARCHAR(16) NOT NULL DEFAULT 'critical';",
+    "ALTER TABLE endpoint_data ADD COLUMN IF NOT EXISTS ntf_claim_generation BIGINT NOT NULL DEFAULT 0;",
+    "ALTER TABLE endpoint_data ADD COLUMN IF NOT EXISTS ntf_resolve_claimed_timestamp TIMESTAMP;",
+    "ALTER TABLE endpoint_data ADD COLUMN IF NOT EXISTS ntf_reminders_sent INT NOT NULL DEFAULT 0;",
+    "ALTER TABLE endpoint_data ADD COLUMN IF NOT EXISTS ntf_last_notification_timestamp TIMESTAMP;",
+]
+
+DATABASE_SETUP_QUERIES = [
+    CREATE_ADMIN_TABLE_DB_QUERY,
+] + ALTER_ADMIN_DB_QUERIES + [
+    CREATE_ENDPOINT_DATA_DB_QUERY,
+] + ALTER_ENDPOINT_DATA_DB_QUERIES + [
+    CREATE_SMS_DELIVERY_DB_QUERY,
+    CREATE_CHAT_THREAD_DB_QUERY,
+    CREATE_TELEGRAM_MESSAGE_DB_QUERY,
+    CREATE_NOTIFICATION_LOG_DB_QUERY,
+    CREATE_ADMIN_CONTACT_METHOD_DB_QUERY,
+    CREATE_ADMIN_NOTIFICATION_RULE_DB_QUERY,
+    CREATE_ADMIN_QUIET_HOURS_DB_QUERY,
+    CREATE_NOTIFICATION_GROUP_DB_QUERY,
+    CREATE_NOTIFICATION_GROUP_MEMBER_DB_QUERY,
+    CREATE_NOTIFICATION_OUTBOX_DB_QUERY,
+    CREATE_NOTIFICATION_DEAD_LETTER_DB_QUERY,
+]
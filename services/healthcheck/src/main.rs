@@ -1,5 +1,4 @@
 use log::{debug, error, info, LevelFilter};
-use reqwest;
 use sqlx::{query, Pool, Postgres};
 use std::collections::HashMap;
 use std::io::Write;
@@ -36,6 +35,7 @@ async fn update_endpoint(
     pool: &Pool<Postgres>,
     endpoint: &Endpoint,
     outage_id: Option<Uuid>,
+    failure_reason: Option<String>,
 ) -> Result<(), sqlx::Error> {
     if outage_id.is_none() {
         query!(
@@ -52,12 +52,21 @@ async fn update_endpoint(
             ntf_is_being_handled = False,
             ntf_is_first_notification_sent = False,
             ntf_is_second_notification_sent = False,
-            ntf_first_responded = False
+            ntf_first_responded = False,
+            ntf_responded_by = NULL,
+            ntf_responded_timestamp = NULL,
+            ntf_is_resolve_pending = False,
+            ntf_resolve_claimed_timestamp = NULL,
+            ntf_reminders_sent = 0,
+            ntf_last_notification_timestamp = NULL,
+            outage_start_timestamp = NOW(),
+            outage_failure_reason = $4
          WHERE http_address = $3",
         )
         .bind(endpoint.is_down)
         .bind(outage_id)
         .bind(endpoint.url.clone())
+        .bind(failure_reason)
         .execute(pool)
         .await?;
     }
@@ -76,6 +85,7 @@ async fn health_check(
         let mut endpoint_data = endpoint_data.lock().await;
         let endpoint = endpoint_data.get_mut(&address).unwrap();
         let mut outage_id: Option<Uuid> = None;
+        let mut failure_reason: Option<String> = None;
         match response {
             Ok(resp) if resp.status().is_success() => {
                 if endpoint.is_down {
@@ -95,12 +105,17 @@ async fn health_check(
                     info!("{} is down", endpoint.url);
                     endpoint.is_down = true;
                     outage_id = Some(uuid::Uuid::new_v4());
+                    // Shown to admins in notifications about the outage.
+                    failure_reason = Some(match &response {
+                        Ok(resp) => format!("HTTP {}", resp.status()),
+                        Err(e) => e.to_string(),
+                    });
                 } else {
                     debug!("{} is still down", endpoint.url);
                 }
             }
         }
-        update_endpoint(pool, endpoint, outage_id).await?;
+        update_endpoint(pool, endpoint, outage_id, failure_reason).await?;
         tokio::time::sleep(endpoint.frequency).await;
     }
 }
@@ -116,7 +131,7 @@ async fn poll_for_new_endpoint_data(
         .expect("MAX_endpoint_data must be a valid integer");
     loop {
         let mut endpoint_data = endpoint_data_mut.lock().await;
-        if endpoint_data.len() > 0 {
+        if !endpoint_data.is_empty() {
             let recs = sqlx::query!(
                 "SELECT http_address, is_removed, frequency FROM endpoint_data WHERE http_address = ANY($1)",
                 &endpoint_data.values().map(|e| e.url.clone()).collect::<Vec<String>>()
@@ -164,7 +179,7 @@ async fn poll_for_new_endpoint_data(
         .await?;
         transaction.commit().await?;
         info!("Found {} endpoint_data", recs.len());
-        if recs.len() == 0 {
+        if recs.is_empty() {
             tokio::time::sleep(freq).await;
             continue;
         }
@@ -182,7 +197,7 @@ async fn poll_for_new_endpoint_data(
             tasks.push(task);
         }
         let mut endpoint_data = endpoint_data_mut.lock().await;
-        for (task, rec) in tasks.into_iter().zip(recs.into_iter()) {
+        for (task, rec) in tasks.into_iter().zip(recs) {
             let address = rec.http_address.clone();
             let freq = rec.frequency.clone();
             let endpoint = Endpoint::new(
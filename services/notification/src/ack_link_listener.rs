@@ -0,0 +1,359 @@
+use std::{env, time::Duration};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::Html,
+    routing::get,
+    Form, Router,
+};
+use serde::Deserialize;
+use tokio::sync::mpsc::Sender;
+
+use crate::{
+    ack_token::AckTokenSigner,
+    error::Result,
+    notification_service::{ResponseAction, ResponseData, ResponseListener},
+};
+
+/*
+    Links embedded in notifications of channels that cannot carry responses themselves (email, webhooks).
+    Every link carries the signed acknowledgement token of the notification.
+    Opening a link only shows a confirmation form, the action is taken when the form is posted,
+    so that link scanners and prefetchers don't respond on behalf of the admin.
+*/
+#[derive(Debug, Clone)]
+pub struct AckLinks {
+    pub acknowledge: String,
+    pub escalate: String,
+    pub snooze: String,
+}
+
+impl AckLinks {
+    pub fn new(base_url: &str, ack_token: &str) -> AckLinks {
+        let base_url = base_url.trim_end_matches('/');
+        AckLinks {
+            acknowledge: format!("{}/ack?token={}", base_url, ack_token),
+            escalate: format!("{}/escalate?token={}", base_url, ack_token),
+            snooze: format!(
+                "{}/snooze?token={}&minutes={}",
+                base_url,
+                ack_token,
+                AckLinkResponseListener::DEFAULT_SNOOZE_MINUTES
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AckLinkConfig {
+    pub listen_address: String,
+    // Publicly reachable address of the listener, used to build links.
+    pub base_url: String,
+}
+
+impl AckLinkConfig {
+    // Listener is enabled only when ACK_HTTP_BASE_URL is set.
+    pub fn from_env() -> Option<AckLinkConfig> {
+        let base_url = env::var("ACK_HTTP_BASE_URL").ok()?;
+        Some(AckLinkConfig {
+            listen_address: env::var("ACK_HTTP_LISTEN_ADDRESS")
+                .unwrap_or_else(|_| "0.0.0.0:8080".to_string()),
+            base_url,
+        })
+    }
+}
+
+#[derive(Clone)]
+struct AckLinkState {
+    sender: Sender<ResponseData>,
+    token_signer: AckTokenSigner,
+}
+
+#[derive(Deserialize)]
+struct ActionParams {
+    token: String,
+    minutes: Option<u64>,
+}
+
+pub struct AckLinkResponseListener {
+    config: AckLinkConfig,
+    state: AckLinkState,
+    // Additional routes served by the listener, e.g. provider delivery callbacks.
+    extra_routes: Router,
+}
+
+impl AckLinkResponseListener {
+    pub const DEFAULT_SNOOZE_MINUTES: u64 = 30;
+    pub const MAX_SNOOZE_MINUTES: u64 = 24 * 60;
+
+    pub fn new(
+        config: AckLinkConfig,
+        sender: Sender<ResponseData>,
+        token_signer: AckTokenSigner,
+    ) -> AckLinkResponseListener {
+        AckLinkResponseListener {
+            config,
+            state: AckLinkState {
+                sender,
+                token_signer,
+            },
+            extra_routes: Router::new(),
+        }
+    }
+
+    pub fn merge(mut self, router: Router) -> AckLinkResponseListener {
+        self.extra_routes = self.extra_routes.merge(router);
+        self
+    }
+
+    fn router(&self) -> Router {
+        Router::new()
+            .route(
+                "/ack",
+                get(Self::confirm_acknowledge).post(Self::acknowledge),
+            )
+            .route(
+                "/escalate",
+                get(Self::confirm_escalate).post(Self::escalate),
+            )
+            .route("/snooze", get(Self::confirm_snooze).post(Self::snooze))
+            .with_state(self.state.clone())
+            .merge(self.extra_routes.clone())
+    }
+
+    fn page(status: StatusCode, text: &str) -> (StatusCode, Html<String>) {
+        (
+            status,
+            Html(format!(
+                "<html><head><title>irio</title></head><body><p>{}</p></body></html>",
+                text
+            )),
+        )
+    }
+
+    // Form posting the token back to the same path, the token is verified before it is embedded in the page.
+    fn confirmation_page(
+        state: &AckLinkState,
+        path: &str,
+        label: &str,
+        params: &ActionParams,
+    ) -> (StatusCode, Html<String>) {
+        let Some(token) = state.token_signer.verify(&params.token) else {
+            return Self::page(StatusCode::FORBIDDEN, "This link is not valid.");
+        };
+        let minutes = params
+            .minutes
+            .map(|m| format!(r#"<input type="hidden" name="minutes" value="{}">"#, m))
+            .unwrap_or_default();
+        (
+            StatusCode::OK,
+            Html(format!(
+                r#"<html><head><title>irio</title></head><body>
+<p>{} outage {} of endpoint {}?</p>
+<form method="post" action="{}">
+<input type="hidden" name="token" value="{}">
+{}
+<button type="submit">{}</button>
+</form>
+</body></html>"#,
+                label, token.outage_id, token.endpoint, path, params.token, minutes, label
+            )),
+        )
+    }
+
+    // Identity of the responder is the admin the link was sent to, as stated by the signed token.
+    async fn handle_action(
+        state: AckLinkState,
+        params: ActionParams,
+        action: ResponseAction,
+    ) -> (StatusCode, Html<String>) {
+        let Some(token) = state.token_signer.verify(&params.token) else {
+            log::warn!(
+                "Rejected link response with invalid token {}",
+                AckTokenSigner::fingerprint(&params.token)
+            );
+            return Self::page(StatusCode::FORBIDDEN, "This link is not valid.");
+        };
+        log::info!(
+            "Received {:?} link response of admin {} to outage {}",
+            action,
+            token.admin,
+            token.outage_id
+        );
+        let response = ResponseData {
+            admin: token.admin,
+            outage_id: token.outage_id,
+            endpoint: token.endpoint,
+            is_first: token.is_first,
+            action,
+            group: token.group,
+        };
+        if let Err(e) = state.sender.send(response).await {
+            log::error!("Failed to forward link response: {}", e);
+            return Self::page(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Failed to process the request, try again later.",
+            );
+        }
+        Self::page(StatusCode::OK, "Your response was received.")
+    }
+
+    async fn confirm_acknowledge(
+        State(state): State<AckLinkState>,
+        Query(params): Query<ActionParams>,
+    ) -> (StatusCode, Html<String>) {
+        Self::confirmation_page(&state, "ack", "Acknowledge", &params)
+    }
+
+    async fn confirm_escalate(
+        State(state): State<AckLinkState>,
+        Query(params): Query<ActionParams>,
+    ) -> (StatusCode, Html<String>) {
+        Self::confirmation_page(&state, "escalate", "Escalate", &params)
+    }
+
+    async fn confirm_snooze(
+        State(state): State<AckLinkState>,
+        Query(params): Query<ActionParams>,
+    ) -> (StatusCode, Html<String>) {
+        Self::confirmation_page(&state, "snooze", "Snooze", &params)
+    }
+
+    async fn acknowledge(
+        State(state): State<AckLinkState>,
+        Form(params): Form<ActionParams>,
+    ) -> (StatusCode, Html<String>) {
+        Self::handle_action(state, params, ResponseAction::Acknowledge).await
+    }
+
+    async fn escalate(
+        State(state): State<AckLinkState>,
+        Form(params): Form<ActionParams>,
+    ) -> (StatusCode, Html<String>) {
+        Self::handle_action(state, params, ResponseAction::Escalate).await
+    }
+
+    async fn snooze(
+        State(state): State<AckLinkState>,
+        Form(params): Form<ActionParams>,
+    ) -> (StatusCode, Html<String>) {
+        let minutes = params
+            .minutes
+            .unwrap_or(Self::DEFAULT_SNOOZE_MINUTES)
+            .clamp(1, Self::MAX_SNOOZE_MINUTES);
+        let action = ResponseAction::Snooze(Duration::from_secs(minutes * 60));
+        Self::handle_action(state, params, action).await
+    }
+}
+
+#[async_trait::async_trait]
+impl ResponseListener for AckLinkResponseListener {
+    async fn listen_for_responses(&self) -> Result<()> {
+        let app = self.router();
+
+        let listener = tokio::net::TcpListener::bind(&self.config.listen_address)
+            .await
+            .inspect_err(|e| {
+                log::error!(
+                    "Failed to bind acknowledgement listener to {}: {}",
+                    self.config.listen_address,
+                    e
+                )
+            })?;
+        log::info!(
+            "Listening for acknowledgement links on {}",
+            self.config.listen_address
+        );
+        axum::serve(listener, app).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::{channel, Receiver};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::ack_token::AckToken;
+
+    // Serves the listener on a free port, returns its base url.
+    async fn serve() -> (String, AckTokenSigner, Receiver<ResponseData>) {
+        let (sender, receiver) = channel(8);
+        let token_signer = AckTokenSigner::new(b"secret".to_vec());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let ack_link_listener = AckLinkResponseListener::new(
+            AckLinkConfig {
+                listen_address: String::new(),
+                base_url: base_url.clone(),
+            },
+            sender,
+            token_signer.clone(),
+        );
+        let app = ack_link_listener.router();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (base_url, token_signer, receiver)
+    }
+
+    fn token(token_signer: &AckTokenSigner) -> String {
+        token_signer.sign(&AckToken {
+            endpoint: 1,
+            outage_id: Uuid::new_v4(),
+            is_first: true,
+            admin: "admin".to_string(),
+            group: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn opening_link_only_shows_confirmation() {
+        let (base_url, token_signer, mut receiver) = serve().await;
+        let links = AckLinks::new(&base_url, &token(&token_signer));
+
+        let response = reqwest::get(&links.acknowledge).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert!(response.text().await.unwrap().contains(r#"method="post""#));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn posted_form_responds() {
+        let (base_url, token_signer, mut receiver) = serve().await;
+        let token = token(&token_signer);
+
+        let response = reqwest::Client::new()
+            .post(format!("{}/snooze", base_url))
+            .form(&[("token", token.as_str()), ("minutes", "10")])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let response = receiver.recv().await.unwrap();
+        assert_eq!(response.admin, "admin");
+        assert_eq!(
+            response.action,
+            ResponseAction::Snooze(Duration::from_secs(600))
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_token_is_rejected() {
+        let (base_url, _, mut receiver) = serve().await;
+        let other = AckTokenSigner::new(b"other".to_vec());
+
+        let response = reqwest::Client::new()
+            .post(format!("{}/ack", base_url))
+            .form(&[("token", token(&other))])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+        let response = reqwest::get(format!("{}/ack?token={}", base_url, token(&other)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+        assert!(receiver.try_recv().is_err());
+    }
+}
@@ -0,0 +1,240 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    config::read_secret,
+    domain::{AdminId, EndpointId, OutageId},
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/*
+    Acknowledgement token embedded in every notification.
+    Token is opaque to the recipient: it is a versioned binary payload followed by
+    an HMAC of that payload, both base64url encoded and joined with a dot.
+    Only tokens signed with the server secret are accepted by the response listeners.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct AckToken {
+    pub endpoint: EndpointId,
+    pub outage_id: OutageId,
+    pub is_first: bool,
+    // Admin the notification was sent to.
+    pub admin: AdminId,
+    // Present in tokens of summaries, responses apply to every outage of the group.
+    pub group: Option<Uuid>,
+}
+
+#[derive(Clone)]
+pub struct AckTokenSigner {
+    key: Arc<Vec<u8>>,
+}
+
+impl AckTokenSigner {
+    const VERSION: u8 = 1;
+    // Version of tokens carrying a group.
+    const GROUP_VERSION: u8 = 2;
+    const SECRET_ENV: &'static str = "ACK_TOKEN_SECRET";
+
+    pub fn new(key: Vec<u8>) -> AckTokenSigner {
+        AckTokenSigner { key: Arc::new(key) }
+    }
+
+    // Secret is required, a generated one would break tokens across instances and restarts.
+    pub fn from_env() -> anyhow::Result<AckTokenSigner> {
+        match read_secret(Self::SECRET_ENV) {
+            Some(secret) if !secret.is_empty() => Ok(Self::new(secret.into_bytes())),
+            _ => Err(anyhow::anyhow!("{} must be set", Self::SECRET_ENV)),
+        }
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.key).expect("HMAC accepts keys of any length")
+    }
+
+    fn encode_payload(token: &AckToken) -> Vec<u8> {
+        let version = match token.group {
+            Some(_) => Self::GROUP_VERSION,
+            None => Self::VERSION,
+        };
+        let mut payload = vec![version];
+        payload.extend_from_slice(&token.endpoint.to_be_bytes());
+        payload.extend_from_slice(token.outage_id.as_bytes());
+        payload.push(if token.is_first { 1 } else { 2 });
+        if let Some(group) = token.group {
+            payload.extend_from_slice(group.as_bytes());
+        }
+        payload.extend_from_slice(token.admin.as_bytes());
+        payload
+    }
+
+    fn decode_payload(payload: &[u8]) -> Option<AckToken> {
+        let (&version, rest) = payload.split_first()?;
+        let group_len = match version {
+            Self::VERSION => 0,
+            Self::GROUP_VERSION => 16,
+            _ => return None,
+        };
+        if rest.len() < 4 + 16 + 1 + group_len {
+            return None;
+        }
+        let (endpoint, rest) = rest.split_at(4);
+        let (outage_id, rest) = rest.split_at(16);
+        let (level, rest) = rest.split_first()?;
+        let (group, admin) = rest.split_at(group_len);
+        Some(AckToken {
+            endpoint: EndpointId::from_be_bytes(endpoint.try_into().ok()?),
+            outage_id: Uuid::from_slice(outage_id).ok()?,
+            is_first: match level {
+                1 => true,
+                2 => false,
+                _ => return None,
+            },
+            admin: String::from_utf8(admin.to_vec()).ok()?,
+            group: match group_len {
+                0 => None,
+                _ => Some(Uuid::from_slice(group).ok()?),
+            },
+        })
+    }
+
+    pub fn sign(&self, token: &AckToken) -> String {
+        let payload = Self::encode_payload(token);
+        let mut mac = self.mac();
+        mac.update(&payload);
+        format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(&payload),
+            URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+        )
+    }
+
+    // Short hash identifying a token in logs and error messages, which never contain the token itself.
+    pub fn fingerprint(encoded: &str) -> String {
+        hex::encode(&Sha256::digest(encoded.trim().as_bytes())[..6])
+    }
+
+    // Returns None if the token is malformed or was not signed with our secret.
+    pub fn verify(&self, encoded: &str) -> Option<AckToken> {
+        let (payload, signature) = encoded.trim().split_once('.')?;
+        let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        let mut mac = self.mac();
+        mac.update(&payload);
+        mac.verify_slice(&signature).ok()?;
+        Self::decode_payload(&payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(group: Option<Uuid>) -> AckToken {
+        AckToken {
+            endpoint: 7,
+            outage_id: Uuid::new_v4(),
+            is_first: false,
+            admin: "admin".to_string(),
+            group,
+        }
+    }
+
+    fn signer() -> AckTokenSigner {
+        AckTokenSigner::new(b"secret".to_vec())
+    }
+
+    // Correctly signed token with an arbitrary payload.
+    fn sign_payload(signer: &AckTokenSigner, payload: &[u8]) -> String {
+        let mut mac = signer.mac();
+        mac.update(payload);
+        format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(payload),
+            URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+        )
+    }
+
+    #[test]
+    fn token_round_trips() {
+        let token = token(None);
+        assert_eq!(signer().verify(&signer().sign(&token)), Some(token));
+    }
+
+    #[test]
+    fn group_token_round_trips() {
+        let token = token(Some(Uuid::new_v4()));
+        let encoded = signer().sign(&token);
+        let payload = URL_SAFE_NO_PAD
+            .decode(encoded.split_once('.').unwrap().0)
+            .unwrap();
+        assert_eq!(payload[0], AckTokenSigner::GROUP_VERSION);
+        assert_eq!(signer().verify(&encoded), Some(token));
+    }
+
+    #[test]
+    fn tampered_mac_is_rejected() {
+        let encoded = signer().sign(&token(None));
+        let (payload, signature) = encoded.split_once('.').unwrap();
+        let mut signature = URL_SAFE_NO_PAD.decode(signature).unwrap();
+        signature[0] ^= 1;
+        let tampered = format!("{}.{}", payload, URL_SAFE_NO_PAD.encode(signature));
+        assert_eq!(signer().verify(&tampered), None);
+    }
+
+    #[test]
+    fn tampered_payload_is_rejected() {
+        let encoded = signer().sign(&token(None));
+        let (payload, signature) = encoded.split_once('.').unwrap();
+        let mut payload = URL_SAFE_NO_PAD.decode(payload).unwrap();
+        payload[4] ^= 1;
+        let tampered = format!("{}.{}", URL_SAFE_NO_PAD.encode(payload), signature);
+        assert_eq!(signer().verify(&tampered), None);
+    }
+
+    #[test]
+    fn token_of_another_key_is_rejected() {
+        let encoded = AckTokenSigner::new(b"other".to_vec()).sign(&token(None));
+        assert_eq!(signer().verify(&encoded), None);
+    }
+
+    #[test]
+    fn truncated_payload_is_rejected() {
+        let payload = AckTokenSigner::encode_payload(&token(Some(Uuid::new_v4())));
+        // Group version without the whole group id.
+        let truncated = sign_payload(&signer(), &payload[..1 + 4 + 16 + 1 + 8]);
+        assert_eq!(signer().verify(&truncated), None);
+        assert_eq!(signer().verify(&sign_payload(&signer(), &[])), None);
+    }
+
+    #[test]
+    fn unknown_version_is_rejected() {
+        let mut payload = AckTokenSigner::encode_payload(&token(None));
+        payload[0] = 3;
+        assert_eq!(signer().verify(&sign_payload(&signer(), &payload)), None);
+    }
+
+    #[test]
+    fn malformed_token_is_rejected() {
+        assert_eq!(signer().verify(""), None);
+        assert_eq!(signer().verify("no-separator"), None);
+        assert_eq!(signer().verify("!!.!!"), None);
+    }
+
+    #[test]
+    fn fingerprint_does_not_reveal_token() {
+        let encoded = signer().sign(&token(None));
+        let fingerprint = AckTokenSigner::fingerprint(&encoded);
+
+        assert_eq!(fingerprint.len(), 12);
+        assert_eq!(fingerprint, AckTokenSigner::fingerprint(&encoded));
+        assert_ne!(
+            fingerprint,
+            AckTokenSigner::fingerprint(&signer().sign(&token(None)))
+        );
+        assert!(!encoded.contains(&fingerprint));
+    }
+}
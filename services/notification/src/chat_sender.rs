@@ -0,0 +1,617 @@
+use std::{env, str::FromStr, time::Duration};
+
+use serde_json::{json, Value};
+
+use crate::{
+    config::read_secret,
+    db_executor::MyDBQueryExecutor,
+    domain::OutageId,
+    error::{NotificationError, Result},
+    notification_service::{
+        Channel, DBQueryExecutor, DeliveryResult, NotificationData, NotificationSender,
+        OutageEvent, OutageUpdate,
+    },
+    templates::{format_duration, TemplateContext, Templates},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChatFlavor {
+    Slack,
+    Mattermost,
+}
+
+impl FromStr for ChatFlavor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "slack" => Ok(ChatFlavor::Slack),
+            "mattermost" => Ok(ChatFlavor::Mattermost),
+            _ => Err(anyhow::anyhow!(
+                "unknown chat provider {}, expected slack or mattermost",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChatConfig {
+    pub flavor: ChatFlavor,
+    // Used for endpoints without a channel of their own.
+    pub default_channel: Option<String>,
+    // Needed to post to channel ids through the API, which is what allows threading follow-ups.
+    pub bot_token: Option<String>,
+    pub api_url: Option<String>,
+}
+
+impl ChatConfig {
+    const SLACK_API_URL: &'static str = "https://slack.com/api";
+
+    // Chat channel is enabled only when CHAT_PROVIDER is set to slack or mattermost.
+    pub fn from_env() -> anyhow::Result<Option<ChatConfig>> {
+        let Ok(flavor) = env::var("CHAT_PROVIDER") else {
+            return Ok(None);
+        };
+        let flavor: ChatFlavor = flavor.parse()?;
+        let bot_token = read_secret("CHAT_BOT_TOKEN");
+        let api_url = match (env::var("CHAT_API_URL"), flavor) {
+            (Ok(api_url), _) => Some(api_url),
+            (Err(_), ChatFlavor::Slack) => Some(Self::SLACK_API_URL.to_string()),
+            (Err(_), ChatFlavor::Mattermost) if bot_token.is_some() => {
+                return Err(anyhow::anyhow!(
+                    "CHAT_API_URL must be set when posting to Mattermost with a bot token"
+                ))
+            }
+            (Err(_), ChatFlavor::Mattermost) => None,
+        };
+        Ok(Some(ChatConfig {
+            flavor,
+            default_channel: env::var("CHAT_CHANNEL").ok(),
+            bot_token,
+            api_url,
+        }))
+    }
+}
+
+// Channel is either an incoming webhook url or a channel id posted to through the API.
+#[derive(Debug, Clone, PartialEq)]
+enum ChatTarget<'a> {
+    Webhook(&'a str),
+    Channel(&'a str),
+}
+
+impl<'a> ChatTarget<'a> {
+    fn new(channel: &'a str) -> ChatTarget<'a> {
+        if channel.starts_with("http://") || channel.starts_with("https://") {
+            ChatTarget::Webhook(channel)
+        } else {
+            ChatTarget::Channel(channel)
+        }
+    }
+
+    // Incoming webhook URLs are credentials, only their host is logged and written to the notification log.
+    fn redacted(&self) -> String {
+        match self {
+            ChatTarget::Channel(channel) => channel.to_string(),
+            ChatTarget::Webhook(url) => format!(
+                "incoming webhook on {}",
+                reqwest::Url::parse(url)
+                    .ok()
+                    .and_then(|url| url.host_str().map(str::to_string))
+                    .unwrap_or_default()
+            ),
+        }
+    }
+}
+
+struct ChatMessage<'a> {
+    title: String,
+    color: &'static str,
+    fields: Vec<(&'static str, String)>,
+    links: Vec<(&'static str, &'a str)>,
+}
+
+#[derive(Clone)]
+pub struct ChatNotificationSender<D = MyDBQueryExecutor> {
+    client: reqwest::Client,
+    config: ChatConfig,
+    db_executor: D,
+    templates: Templates,
+}
+
+impl<D: DBQueryExecutor + Clone> ChatNotificationSender<D> {
+    const OUTAGE_COLOR: &'static str = "#d50200";
+    const ESCALATED_COLOR: &'static str = "#8b0000";
+    const ACKNOWLEDGED_COLOR: &'static str = "#f2c744";
+    const RESOLVED_COLOR: &'static str = "#2eb67d";
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn new(
+        config: ChatConfig,
+        db_executor: D,
+        templates: Templates,
+    ) -> ChatNotificationSender<D> {
+        ChatNotificationSender {
+            client: reqwest::Client::builder()
+                .timeout(Self::REQUEST_TIMEOUT)
+                .build()
+                .expect("Failed to create HTTP client"),
+            config,
+            db_executor,
+            templates,
+        }
+    }
+
+    fn notification_message<'a>(&self, x: &'a NotificationData) -> Result<ChatMessage<'a>> {
+        let title = self
+            .templates
+            .render(Templates::CHAT_TITLE, &TemplateContext::notification(x))?;
+        let color = if x.is_first {
+            Self::OUTAGE_COLOR
+        } else {
+            Self::ESCALATED_COLOR
+        };
+        let links = x
+            .ack_links
+            .as_ref()
+            .map(|l| {
+                vec![
+                    ("Acknowledge", l.acknowledge.as_str()),
+                    ("Escalate", l.escalate.as_str()),
+                    ("Snooze 30 min", l.snooze.as_str()),
+                ]
+            })
+            .unwrap_or_default();
+        let mut fields = vec![
+            ("Endpoint", x.endpoint.to_string()),
+            ("Down for", format_duration(x.outage_start)),
+            (
+                "Escalation level",
+                if x.is_first {
+                    "1 (primary admin)"
+                } else {
+                    "2 (secondary admin)"
+                }
+                .to_string(),
+            ),
+            ("Admin", x.admin.clone()),
+            ("Outage", x.outage_id.to_string()),
+        ];
+        if let Some(reason) = &x.failure_reason {
+            fields.push(("Failure", reason.clone()));
+        }
+        Ok(ChatMessage {
+            title,
+            color,
+            fields,
+            links,
+        })
+    }
+
+    fn update_message(&self, x: &OutageUpdate) -> Result<ChatMessage<'static>> {
+        let title = self
+            .templates
+            .render(Templates::CHAT_TITLE, &TemplateContext::update(x))?;
+        Ok(match &x.event {
+            OutageEvent::Acknowledged(_) => ChatMessage {
+                title,
+                color: Self::ACKNOWLEDGED_COLOR,
+                fields: vec![("Down for", format_duration(x.outage_start))],
+                links: vec![],
+            },
+            OutageEvent::Resolved => ChatMessage {
+                title,
+                color: Self::RESOLVED_COLOR,
+                fields: vec![("Outage lasted", format_duration(x.outage_start))],
+                links: vec![],
+            },
+        })
+    }
+
+    // Payload accepted by incoming webhooks, Slack's chat.postMessage takes the same shape.
+    fn render(&self, message: &ChatMessage) -> Value {
+        match self.config.flavor {
+            ChatFlavor::Slack => {
+                let fields: Vec<Value> = message
+                    .fields
+                    .iter()
+                    .map(|(name, value)| json!({"type": "mrkdwn", "text": format!("*{}*\n{}", name, value)}))
+                    .collect();
+                let mut blocks = vec![
+                    json!({"type": "header", "text": {"type": "plain_text", "text": message.title}}),
+                    json!({"type": "section", "fields": fields}),
+                ];
+                if !message.links.is_empty() {
+                    let buttons: Vec<Value> = message
+                        .links
+                        .iter()
+                        .map(|(name, url)| json!({"type": "button", "text": {"type": "plain_text", "text": name}, "url": url}))
+                        .collect();
+                    blocks.push(json!({"type": "actions", "elements": buttons}));
+                }
+                json!({"text": message.title, "blocks": blocks})
+            }
+            ChatFlavor::Mattermost => {
+                let fields: Vec<Value> = message
+                    .fields
+                    .iter()
+                    .map(|(name, value)| json!({"short": true, "title": name, "value": value}))
+                    .collect();
+                let links: Vec<String> = message
+                    .links
+                    .iter()
+                    .map(|(name, url)| format!("[{}]({})", name, url))
+                    .collect();
+                json!({"attachments": [{
+                    "fallback": message.title,
+                    "color": message.color,
+                    "title": message.title,
+                    "fields": fields,
+                    "text": links.join(" | "),
+                }]})
+            }
+        }
+    }
+
+    fn bot_token(&self) -> Result<&str> {
+        self.config.bot_token.as_deref().ok_or_else(|| {
+            anyhow::anyhow!("CHAT_BOT_TOKEN must be set to post to channel ids").into()
+        })
+    }
+
+    // Returns id of the posted message when it can be replied to.
+    async fn post(
+        &self,
+        target: &ChatTarget<'_>,
+        thread: Option<&str>,
+        mut payload: Value,
+    ) -> Result<Option<String>> {
+        match (target, self.config.flavor) {
+            (ChatTarget::Webhook(url), _) => {
+                // Errors would carry the url otherwise.
+                let response = self
+                    .client
+                    .post(*url)
+                    .json(&payload)
+                    .send()
+                    .await
+                    .map_err(reqwest::Error::without_url)?;
+                if !response.status().is_success() {
+                    return Err(NotificationError::Provider(format!(
+                        "webhook responded with {}",
+                        response.status()
+                    )));
+                }
+                Ok(None)
+            }
+            (ChatTarget::Channel(channel), ChatFlavor::Slack) => {
+                payload["channel"] = json!(channel);
+                if let Some(thread) = thread {
+                    payload["thread_ts"] = json!(thread);
+                }
+                let url = format!(
+                    "{}/chat.postMessage",
+                    self.config.api_url.as_deref().unwrap_or_default()
+                );
+                let body: Value = self
+                    .client
+                    .post(url)
+                    .bearer_auth(self.bot_token()?)
+                    .json(&payload)
+                    .send()
+                    .await?
+                    .json()
+                    .await?;
+                if body["ok"].as_bool() != Some(true) {
+                    return Err(NotificationError::Provider(format!(
+                        "Slack responded with {}",
+                        body["error"]
+                    )));
+                }
+                Ok(body["ts"].as_str().map(str::to_string))
+            }
+            (ChatTarget::Channel(channel), ChatFlavor::Mattermost) => {
+                let post = json!({
+                    "channel_id": channel,
+                    "message": "",
+                    "root_id": thread.unwrap_or_default(),
+                    "props": {"attachments": payload["attachments"].take()},
+                });
+                let url = format!(
+                    "{}/api/v4/posts",
+                    self.config
+                        .api_url
+                        .as_deref()
+                        .unwrap_or_default()
+                        .trim_end_matches('/')
+                );
+                let response = self
+                    .client
+                    .post(url)
+                    .bearer_auth(self.bot_token()?)
+                    .json(&post)
+                    .send()
+                    .await?;
+                let status = response.status();
+                let body: Value = response.json().await?;
+                if !status.is_success() {
+                    return Err(NotificationError::Provider(format!(
+                        "Mattermost responded with {}: {}",
+                        status, body
+                    )));
+                }
+                Ok(body["id"].as_str().map(str::to_string))
+            }
+        }
+    }
+
+    async fn get_thread(&self, outage_id: OutageId, channel: &str) -> Option<String> {
+        match self
+            .db_executor
+            .get_chat_thread(outage_id, channel.to_string())
+            .await
+        {
+            Ok(thread) => thread,
+            Err(e) => {
+                log::error!("Failed to read chat thread of outage {}: {}", outage_id, e);
+                None
+            }
+        }
+    }
+
+    fn channel<'a>(&'a self, endpoint_channel: &'a Option<String>) -> Option<&'a str> {
+        endpoint_channel
+            .as_deref()
+            .or(self.config.default_channel.as_deref())
+    }
+}
+
+#[async_trait::async_trait]
+impl<D: DBQueryExecutor + Clone> NotificationSender for ChatNotificationSender<D> {
+    /*
+        First notification starts a thread, escalation is posted into it.
+        Threads are only possible when posting through the API.
+    */
+    async fn send_notification(&self, x: NotificationData) -> Vec<DeliveryResult> {
+        let Some(channel) = self.channel(&x.chat_channel) else {
+            return vec![];
+        };
+        let target = ChatTarget::new(channel);
+        let logged_target = target.redacted();
+        log::info!(
+            "Attempting to contact {} by chat {}",
+            x.admin,
+            logged_target
+        );
+        let thread = match (&target, x.is_first) {
+            (ChatTarget::Channel(_), false) => self.get_thread(x.outage_id, channel).await,
+            _ => None,
+        };
+        let message = match self.notification_message(&x) {
+            Ok(message) => message,
+            Err(e) => {
+                log::error!("Failed to render chat message: {}", e);
+                return vec![DeliveryResult::failed(Channel::Chat, logged_target, e)];
+            }
+        };
+        let mut payload = self.render(&message);
+        if thread.is_some() && self.config.flavor == ChatFlavor::Slack {
+            // Escalation should be visible in the channel, not only in the thread.
+            payload["reply_broadcast"] = json!(true);
+        }
+        match self.post(&target, thread.as_deref(), payload).await {
+            Ok(Some(message_id)) if thread.is_none() => {
+                log::info!("Message sent successfully");
+                if let Err(e) = self
+                    .db_executor
+                    .record_chat_thread(x.outage_id, channel.to_string(), message_id)
+                    .await
+                {
+                    log::error!(
+                        "Failed to store chat thread of outage {}: {}",
+                        x.outage_id,
+                        e
+                    );
+                }
+                vec![DeliveryResult::delivered(Channel::Chat, logged_target)]
+            }
+            Ok(_) => {
+                log::info!("Message sent successfully");
+                vec![DeliveryResult::delivered(Channel::Chat, logged_target)]
+            }
+            Err(e) => {
+                log::error!("Failed to send message: {}", e);
+                vec![DeliveryResult::failed(Channel::Chat, logged_target, e)]
+            }
+        }
+    }
+
+    async fn send_outage_update(&self, x: OutageUpdate) {
+        let Some(channel) = self.channel(&x.chat_channel) else {
+            return;
+        };
+        let target = ChatTarget::new(channel);
+        let thread = match target {
+            ChatTarget::Channel(_) => self.get_thread(x.outage_id, channel).await,
+            ChatTarget::Webhook(_) => None,
+        };
+        let message = match self.update_message(&x) {
+            Ok(message) => message,
+            Err(e) => {
+                log::error!("Failed to render chat message: {}", e);
+                return;
+            }
+        };
+        let payload = self.render(&message);
+        match self.post(&target, thread.as_deref(), payload).await {
+            Ok(_) => log::info!(
+                "Update of outage {} posted to {}",
+                x.outage_id,
+                target.redacted()
+            ),
+            Err(e) => log::error!("Failed to post update of outage {}: {}", x.outage_id, e),
+        }
+    }
+
+    fn channels(&self, x: &NotificationData) -> Vec<Channel> {
+        match self.channel(&x.chat_channel) {
+            Some(_) => vec![Channel::Chat],
+            None => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{extract::State, http::Uri, Json, Router};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        ack_link_listener::AckLinks, clock::SystemClock, memory_executor::InMemoryDBQueryExecutor,
+        notification_service::DeliveryStatus,
+    };
+
+    type Executor = InMemoryDBQueryExecutor<SystemClock>;
+    // Request received by the stub: path and body.
+    type Request = (String, Value);
+
+    // Serves a stub of the Slack API answering every post with the same ts, returns its url and the posted paths and bodies.
+    async fn serve() -> (String, Arc<Mutex<Vec<Request>>>) {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let app = Router::new()
+            .fallback(
+                |State(requests): State<Arc<Mutex<Vec<Request>>>>,
+                 uri: Uri,
+                 Json(body): Json<Value>| async move {
+                    requests.lock().unwrap().push((uri.to_string(), body));
+                    Json(json!({"ok": true, "ts": "1700000000.000100"}))
+                },
+            )
+            .with_state(requests.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, requests)
+    }
+
+    fn sender(flavor: ChatFlavor, api_url: Option<String>) -> ChatNotificationSender<Executor> {
+        ChatNotificationSender::new(
+            ChatConfig {
+                flavor,
+                default_channel: Some("C123".to_string()),
+                bot_token: Some("bot-token".to_string()),
+                api_url,
+            },
+            InMemoryDBQueryExecutor::new(SystemClock, 40, 2, Uuid::new_v4()),
+            Templates::built_in(),
+        )
+    }
+
+    fn notification() -> NotificationData {
+        let mut x = NotificationData::example("admin", 1);
+        x.ack_links = Some(AckLinks::new("https://irio.example.com", "token"));
+        x
+    }
+
+    #[test]
+    fn slack_message_is_rendered_as_blocks() {
+        let sender = sender(ChatFlavor::Slack, None);
+        let x = notification();
+
+        let payload = sender.render(&sender.notification_message(&x).unwrap());
+
+        let blocks = payload["blocks"].as_array().unwrap();
+        assert_eq!(blocks[0]["type"], "header");
+        assert_eq!(
+            blocks[0]["text"]["text"],
+            "Outage of http://endpoint-1.example.com"
+        );
+        assert_eq!(payload["text"], blocks[0]["text"]["text"]);
+        assert_eq!(blocks[1]["type"], "section");
+        assert_eq!(blocks[1]["fields"][0]["text"], "*Endpoint*\n1");
+        assert_eq!(blocks[2]["type"], "actions");
+        let buttons = blocks[2]["elements"].as_array().unwrap();
+        assert_eq!(buttons.len(), 3);
+        assert_eq!(buttons[0]["text"]["text"], "Acknowledge");
+        assert_eq!(
+            buttons[0]["url"],
+            x.ack_links.as_ref().unwrap().acknowledge.as_str()
+        );
+    }
+
+    #[test]
+    fn mattermost_message_is_rendered_as_attachment() {
+        let sender = sender(ChatFlavor::Mattermost, None);
+        let mut x = notification();
+        x.is_first = false;
+
+        let payload = sender.render(&sender.notification_message(&x).unwrap());
+
+        let attachment = &payload["attachments"][0];
+        assert_eq!(
+            attachment["color"],
+            ChatNotificationSender::<Executor>::ESCALATED_COLOR
+        );
+        assert_eq!(
+            attachment["title"],
+            "Escalated: outage of http://endpoint-1.example.com"
+        );
+        assert_eq!(attachment["fields"][2]["title"], "Escalation level");
+        assert_eq!(attachment["fields"][2]["value"], "2 (secondary admin)");
+        assert!(attachment["text"]
+            .as_str()
+            .unwrap()
+            .starts_with("[Acknowledge](https://irio.example.com/ack?token=token) | "));
+    }
+
+    #[tokio::test]
+    async fn escalation_and_updates_are_posted_into_thread_of_outage() {
+        let (api_url, requests) = serve().await;
+        let sender = sender(ChatFlavor::Slack, Some(api_url));
+        let first = notification();
+        let mut second = first.clone();
+        second.is_first = false;
+
+        sender.send_notification(first.clone()).await;
+        sender.send_notification(second).await;
+        sender
+            .send_outage_update(OutageUpdate {
+                outage_id: first.outage_id,
+                endpoint: first.endpoint,
+                http_address: first.http_address.clone(),
+                outage_start: None,
+                chat_channel: None,
+                incident_routing_key: None,
+                failure_reason: None,
+                event: OutageEvent::Resolved,
+            })
+            .await;
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(requests.iter().all(|(path, _)| path == "/chat.postMessage"));
+        assert_eq!(requests[0].1["channel"], "C123");
+        assert_eq!(requests[0].1.get("thread_ts"), None);
+        assert_eq!(requests[1].1["thread_ts"], "1700000000.000100");
+        assert_eq!(requests[1].1["reply_broadcast"], true);
+        assert_eq!(requests[2].1["thread_ts"], "1700000000.000100");
+    }
+
+    #[tokio::test]
+    async fn incoming_webhook_url_is_not_logged() {
+        let (api_url, requests) = serve().await;
+        let sender = sender(ChatFlavor::Slack, None);
+        let mut x = notification();
+        x.chat_channel = Some(format!("{}/services/T000/B000/secret", api_url));
+
+        let results = sender.send_notification(x).await;
+
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        assert_eq!(results[0].status, DeliveryStatus::Delivered);
+        assert_eq!(results[0].target, "incoming webhook on 127.0.0.1");
+    }
+}
@@ -0,0 +1,45 @@
+#[cfg(test)]
+use std::sync::{Arc, Mutex};
+
+use crate::domain::MyTime;
+
+// Source of the current time, so that time dependent logic can be driven by tests.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> MyTime;
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> MyTime {
+        chrono::Utc::now().naive_utc()
+    }
+}
+
+// Time moves only when advanced. Clones share the time.
+#[cfg(test)]
+#[derive(Clone, Debug)]
+pub struct VirtualClock {
+    now: Arc<Mutex<MyTime>>,
+}
+
+#[cfg(test)]
+impl VirtualClock {
+    pub fn new(start: MyTime) -> VirtualClock {
+        VirtualClock {
+            now: Arc::new(Mutex::new(start)),
+        }
+    }
+
+    pub fn advance(&self, duration: chrono::Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+#[cfg(test)]
+impl Clock for VirtualClock {
+    fn now(&self) -> MyTime {
+        *self.now.lock().unwrap()
+    }
+}
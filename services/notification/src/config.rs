@@ -0,0 +1,17 @@
+use std::{env, fs};
+
+// Reads a secret either directly from the `name` environment variable
+// or from the file pointed to by `<name>_FILE` (e.g. a mounted kubernetes secret).
+pub fn read_secret(name: &str) -> Option<String> {
+    if let Ok(value) = env::var(name) {
+        return Some(value);
+    }
+    let path = env::var(format!("{}_FILE", name)).ok()?;
+    match fs::read_to_string(&path) {
+        Ok(value) => Some(value.trim_end_matches(['\n', '\r']).to_string()),
+        Err(e) => {
+            log::error!("Failed to read {} from {}: {}", name, path, e);
+            None
+        }
+    }
+}
@@ -1,13 +1,15 @@
-use anyhow::{Ok, Result};
-
 use sqlx::{Pool, Postgres};
 use std::sync::Arc;
 use uuid::Uuid;
 
 use crate::{
-    db::get_postgres_connection,
-    domain::{Admin, AdminId, EndpointData, EndpointId, OutageId},
-    notification_service::DBQueryExecutor,
+    domain::{
+        Admin, AdminId, ContactId, ContactMethod, EndpointData, EndpointId, EndpointStatus,
+        MyDuration, MyTime, NotificationGroupMember, NotificationRule, OutageId, OutboxEntry,
+        QuietHours, Severity,
+    },
+    error::{NotificationError, Result},
+    notification_service::{DBQueryExecutor, DeliveryResult, DeliveryStatus},
 };
 
 #[derive(Clone, Debug)]
@@ -15,191 +17,920 @@ pub struct MyDBQueryExecutor {
     postgres: Arc<Pool<Postgres>>,
     secs_wait_when_handled: u32,
     service_id: Uuid,
-    _n_endpoints_to_select: u32,
+    // Outages claimed per poll, so that peer instances get their share.
+    n_endpoints_to_select: u32,
+    // First notifications wait this long after the outage started, so that outages opening together are grouped.
+    group_wait_secs: u32,
+    // Unacknowledged outages are reminded to the secondary admin this often after the second notification.
+    reminder_interval_secs: Option<u32>,
 }
 
 impl MyDBQueryExecutor {
-    const ENDPOINT_DB_LAYOUT: &'static str = "
-    endpoint_id,
-    http_address,
-    is_down,
-    outage_id,
-    ntf_is_being_handled,
-    ntf_is_being_handled_timestamp,
-    ntf_is_being_handled_service_id,
-    ntf_is_first_notification_sent,
-    ntf_first_notification_sent_timestamp,
-    ntf_is_second_notification_sent,
-    conf_primary_admin,
-    conf_secondary_admin,
-    conf_allowed_response_duration,
-    ntf_first_responded";
-
-    const ADMIN_DB_LAYOUT: &'static str = "
-    admin_id,
-    telegram_contact_id,
-    phone_number,
-    email_address
-    ";
-    const ENDPOINTS_TABLE_NAME: &'static str = "endpoint_data";
-    const ADMINS_TABLE_NAME: &'static str = "admin";
-    const CURRENT_TIMESTAMP: &'static str = "CURRENT_TIMESTAMP";
-
-    pub async fn new(
+    pub fn new(
+        postgres: Arc<Pool<Postgres>>,
         secs_wait_while_handled: u32,
         n_endpoints_to_select: u32,
         service_id: Uuid,
     ) -> MyDBQueryExecutor {
-        let postgres = get_postgres_connection().await.unwrap();
-
         MyDBQueryExecutor {
-            postgres: postgres,
+            postgres,
             secs_wait_when_handled: secs_wait_while_handled,
-            service_id: service_id,
-            _n_endpoints_to_select: n_endpoints_to_select,
+            service_id,
+            n_endpoints_to_select,
+            group_wait_secs: 0,
+            reminder_interval_secs: None,
         }
     }
 
-    fn sql_condition_should_row_be_handled(&self) -> String {
-        let notification_needs_to_be_sent_condition: String = format!(
-            "(NOT ntf_first_responded) 
-                AND (
-                    (NOT ntf_is_first_notification_sent) 
-                    OR (
-                        (ntf_first_notification_sent_timestamp + conf_allowed_response_duration < {})
-                        AND
-                        (NOT ntf_is_second_notification_sent)
-                    )
-                )",
-            Self::CURRENT_TIMESTAMP
-        );
+    pub fn with_group_wait(mut self, group_wait_secs: u32) -> MyDBQueryExecutor {
+        self.group_wait_secs = group_wait_secs;
+        self
+    }
 
-        let is_not_handled: String = format!("(NOT ntf_is_being_handled) OR (ntf_is_being_handled_timestamp + INTERVAL '{} seconds' < {})", self.secs_wait_when_handled, Self::CURRENT_TIMESTAMP);
-        format!(
-            "(NOT is_removed) AND is_down AND ({}) AND ({})",
-            is_not_handled, notification_needs_to_be_sent_condition
-        )
+    pub fn with_reminder_interval(
+        mut self,
+        reminder_interval_secs: Option<u32>,
+    ) -> MyDBQueryExecutor {
+        self.reminder_interval_secs = reminder_interval_secs;
+        self
     }
 
-    fn sql_update_row_is_handled_by_me(&self) -> String {
-        format!(
-            "ntf_is_being_handled = true, ntf_is_being_handled_timestamp = {}, ntf_is_being_handled_service_id = '{}'", Self::CURRENT_TIMESTAMP, self.service_id
-        )
+    fn seconds(secs: u32) -> MyDuration {
+        MyDuration {
+            months: 0,
+            days: 0,
+            microseconds: i64::from(secs) * 1_000_000,
+        }
     }
 
-    fn sql_update_and_select_endpoints_str(&self) -> String {
-        let select_endpoints_str: String = {
-            format!(
-                "UPDATE {} SET {} where ({}) RETURNING {}",
-                Self::ENDPOINTS_TABLE_NAME,
-                self.sql_update_row_is_handled_by_me(),
-                self.sql_condition_should_row_be_handled(),
-                Self::ENDPOINT_DB_LAYOUT
+    /*
+        Claims endpoints whose notification needs to be sent, see get_endpoints_to_process.
+        Outage is claimed only if it isn't claimed already or its claim is older than SECS_WAIT_WHEN_HANDLED.
+        Dead-lettered outages are never claimed again.
+        At most n_endpoints_to_select outages are claimed, oldest first.
+        Rows locked by a concurrent claim of another instance are skipped instead of waited for.
+    */
+    async fn sql_update_and_select_endpoints(&self) -> Result<Vec<EndpointData>> {
+        sqlx::query_as!(
+            EndpointData,
+            "UPDATE endpoint_data
+            SET
+                ntf_is_being_handled = true,
+                ntf_is_being_handled_timestamp = CURRENT_TIMESTAMP,
+                ntf_is_being_handled_service_id = $1,
+                ntf_claim_generation = ntf_claim_generation + 1
+            WHERE endpoint_id IN (
+                SELECT endpoint_id
+                FROM endpoint_data
+                WHERE
+                    (NOT is_removed) AND is_down
+                    AND ((NOT ntf_is_being_handled) OR (ntf_is_being_handled_timestamp + $2::interval < CURRENT_TIMESTAMP))
+                    AND ((ntf_muted_until IS NULL) OR (ntf_muted_until < CURRENT_TIMESTAMP))
+                    AND (NOT ntf_first_responded)
+                    AND NOT EXISTS (
+                        SELECT 1 FROM notification_dead_letter d
+                        WHERE d.endpoint_id = endpoint_data.endpoint_id
+                            AND d.outage_id IS NOT DISTINCT FROM endpoint_data.outage_id
+                    )
+                    AND (
+                        (
+                            (NOT ntf_is_first_notification_sent)
+                            AND
+                            (outage_start_timestamp IS NULL OR outage_start_timestamp + $3::interval < CURRENT_TIMESTAMP)
+                        )
+                        OR (
+                            (ntf_first_notification_sent_timestamp + conf_allowed_response_duration < CURRENT_TIMESTAMP)
+                            AND
+                            (NOT ntf_is_second_notification_sent)
+                        )
+                        OR (
+                            ntf_is_second_notification_sent
+                            AND
+                            (ntf_last_notification_timestamp + $5::interval < CURRENT_TIMESTAMP)
+                        )
+                    )
+                ORDER BY outage_start_timestamp NULLS FIRST, endpoint_id
+                LIMIT $4
+                FOR UPDATE SKIP LOCKED
             )
-        };
-        select_endpoints_str
+            RETURNING
+                endpoint_id,
+                http_address,
+                is_down,
+                outage_id,
+                ntf_is_being_handled,
+                ntf_is_being_handled_timestamp,
+                ntf_is_being_handled_service_id,
+                ntf_is_first_notification_sent,
+                ntf_first_notification_sent_timestamp,
+                ntf_is_second_notification_sent,
+                ntf_reminders_sent,
+                conf_primary_admin,
+                conf_secondary_admin,
+                conf_allowed_response_duration,
+                ntf_first_responded,
+                conf_webhook_url,
+                conf_chat_channel,
+                outage_start_timestamp,
+                conf_incident_routing_key,
+                outage_failure_reason,
+                conf_severity,
+                ntf_claim_generation",
+            self.service_id,
+            Self::seconds(self.secs_wait_when_handled),
+            Self::seconds(self.group_wait_secs),
+            i64::from(self.n_endpoints_to_select),
+            self.reminder_interval_secs.map(Self::seconds),
+        )
+        .fetch_all(self.postgres.as_ref())
+        .await
+        .map_err(NotificationError::from)
     }
 
     async fn sql_get_admin_id(&self, admin_id: AdminId) -> Result<Admin> {
-        let get_admin_id_str = format!(
-            "SELECT {} 
-            FROM {}
-            WHERE 
+        sqlx::query_as!(
+            Admin,
+            "SELECT admin_id, telegram_contact_id, phone_number, email_address, timezone, quiet_hours_action
+            FROM admin
+            WHERE
                 admin_id = $1 AND is_removed = false",
-            Self::ADMIN_DB_LAYOUT,
-            Self::ADMINS_TABLE_NAME
-        );
-        let ret = sqlx::query_as::<Postgres, Admin>(&get_admin_id_str)
-            .bind(admin_id)
-            .fetch_all(self.postgres.as_ref())
-            .await
-            .map_err(anyhow::Error::msg)?;
-        assert!(ret.len() == 1);
-        Ok(ret[0].clone())
+            admin_id
+        )
+        .fetch_optional(self.postgres.as_ref())
+        .await?
+        .ok_or(NotificationError::AdminNotFound(admin_id))
     }
 
-    async fn execute_statement_returning_endpoints(
+    async fn sql_get_admin_by_telegram_contact_id(
         &self,
-        query: &str,
-    ) -> Result<Vec<EndpointData>> {
-        sqlx::query_as::<Postgres, EndpointData>(query)
-            .fetch_all(self.postgres.as_ref())
-            .await
-            .map_err(anyhow::Error::msg)
+        telegram_contact_id: ContactId,
+    ) -> Result<Option<Admin>> {
+        sqlx::query_as!(
+            Admin,
+            "SELECT admin_id, telegram_contact_id, phone_number, email_address, timezone, quiet_hours_action
+            FROM admin
+            WHERE
+                (telegram_contact_id = $1 OR admin_id IN (
+                    SELECT admin_id FROM admin_contact_method WHERE channel = 'telegram' AND address = $1
+                )) AND is_removed = false",
+            telegram_contact_id
+        )
+        .fetch_optional(self.postgres.as_ref())
+        .await
+        .map_err(NotificationError::from)
     }
 
-    async fn set_endpoint_responded(
+    async fn sql_get_current_outages(&self) -> Result<Vec<EndpointStatus>> {
+        sqlx::query_as!(
+            EndpointStatus,
+            "SELECT endpoint_id, http_address, is_down, outage_id, last_ping_time, ntf_first_responded, ntf_muted_until
+            FROM endpoint_data
+            WHERE
+                is_down AND (NOT is_removed)
+            ORDER BY endpoint_id"
+        )
+        .fetch_all(self.postgres.as_ref())
+        .await
+        .map_err(NotificationError::from)
+    }
+
+    async fn sql_get_endpoint_status(&self, endpoint: &str) -> Result<Option<EndpointStatus>> {
+        sqlx::query_as!(
+            EndpointStatus,
+            "SELECT endpoint_id, http_address, is_down, outage_id, last_ping_time, ntf_first_responded, ntf_muted_until
+            FROM endpoint_data
+            WHERE
+                (endpoint_id::text = $1 OR http_address = $1) AND (NOT is_removed)
+            ORDER BY endpoint_id
+            LIMIT 1",
+            endpoint
+        )
+        .fetch_optional(self.postgres.as_ref())
+        .await
+        .map_err(NotificationError::from)
+    }
+
+    async fn sql_get_endpoint_by_outage(
+        &self,
+        outage_id: OutageId,
+    ) -> Result<Option<EndpointData>> {
+        sqlx::query_as!(
+            EndpointData,
+            "SELECT
+                endpoint_id,
+                http_address,
+                is_down,
+                outage_id,
+                ntf_is_being_handled,
+                ntf_is_being_handled_timestamp,
+                ntf_is_being_handled_service_id,
+                ntf_is_first_notification_sent,
+                ntf_first_notification_sent_timestamp,
+                ntf_is_second_notification_sent,
+                ntf_reminders_sent,
+                conf_primary_admin,
+                conf_secondary_admin,
+                conf_allowed_response_duration,
+                ntf_first_responded,
+                conf_webhook_url,
+                conf_chat_channel,
+                outage_start_timestamp,
+                conf_incident_routing_key,
+                outage_failure_reason,
+                conf_severity,
+                ntf_claim_generation
+            FROM endpoint_data
+            WHERE
+                outage_id = $1 AND (NOT is_removed)",
+            outage_id
+        )
+        .fetch_optional(self.postgres.as_ref())
+        .await
+        .map_err(NotificationError::from)
+    }
+
+    async fn set_endpoint_muted(
+        &self,
+        endpoint_id: EndpointId,
+        duration: MyDuration,
+    ) -> Result<bool> {
+        let ret = sqlx::query!(
+            "UPDATE endpoint_data
+            SET
+                ntf_muted_until = CURRENT_TIMESTAMP + $2::interval
+            WHERE
+                endpoint_id = $1 AND (NOT is_removed)",
+            endpoint_id,
+            duration
+        )
+        .execute(self.postgres.as_ref())
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        Ok(ret.rows_affected() > 0)
+    }
+
+    // Moves the response deadline of an outage waiting for a response to the first notification.
+    async fn set_first_response_deadline_passed(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+    ) -> Result<bool> {
+        let ret = sqlx::query!(
+            "UPDATE endpoint_data
+            SET
+                ntf_first_notification_sent_timestamp = CURRENT_TIMESTAMP - conf_allowed_response_duration
+            WHERE
+                endpoint_id = $1 AND outage_id = $2 AND is_down
+                AND ntf_is_first_notification_sent
+                AND (NOT ntf_is_second_notification_sent)
+                AND (NOT ntf_first_responded)",
+            endpoint_id,
+            outage_id
+        )
+        .execute(self.postgres.as_ref())
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        Ok(ret.rows_affected() > 0)
+    }
+
+    async fn set_first_response_deadline_postponed(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        duration: MyDuration,
+    ) -> Result<bool> {
+        let ret = sqlx::query!(
+            "UPDATE endpoint_data
+            SET
+                ntf_first_notification_sent_timestamp = CURRENT_TIMESTAMP + $3::interval
+            WHERE
+                endpoint_id = $1 AND outage_id = $2 AND is_down
+                AND ntf_is_first_notification_sent
+                AND (NOT ntf_is_second_notification_sent)
+                AND (NOT ntf_first_responded)",
+            endpoint_id,
+            outage_id,
+            duration
+        )
+        .execute(self.postgres.as_ref())
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        Ok(ret.rows_affected() > 0)
+    }
+
+    async fn insert_sms_delivery(
         &self,
+        provider_message_id: String,
         endpoint_id: EndpointId,
         outage_id: OutageId,
+        admin_id: AdminId,
     ) -> Result<()> {
-        let format = format!(
-            "UPDATE {} 
-            SET 
-                ntf_first_responded = true 
-            WHERE 
-                endpoint_id = $1 AND outage_id = $2",
-            Self::ENDPOINTS_TABLE_NAME
-        );
-        let ret = sqlx::query(&format)
-            .bind(endpoint_id)
-            .bind(outage_id)
-            .execute(self.postgres.as_ref())
-            .await
-            .map_err(anyhow::Error::msg)?;
+        let ret = sqlx::query!(
+            "INSERT INTO sms_delivery
+                (provider_message_id, endpoint_id, outage_id, admin_id, status, sent_timestamp, status_timestamp)
+            VALUES
+                ($1, $2, $3, $4, 'sent', CURRENT_TIMESTAMP, CURRENT_TIMESTAMP)",
+            provider_message_id,
+            endpoint_id,
+            outage_id,
+            admin_id
+        )
+        .execute(self.postgres.as_ref())
+        .await?;
         log::info!("Pgquery result = {:?}", ret);
         Ok(())
     }
 
+    async fn set_sms_delivery_status(
+        &self,
+        provider_message_id: String,
+        status: String,
+        error_code: Option<String>,
+    ) -> Result<bool> {
+        let ret = sqlx::query!(
+            "UPDATE sms_delivery
+            SET
+                status = $2,
+                error_code = $3,
+                status_timestamp = CURRENT_TIMESTAMP
+            WHERE
+                provider_message_id = $1",
+            provider_message_id,
+            status,
+            error_code
+        )
+        .execute(self.postgres.as_ref())
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        Ok(ret.rows_affected() > 0)
+    }
+
+    async fn set_endpoint_responded(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        admin_id: AdminId,
+    ) -> Result<bool> {
+        let ret = sqlx::query!(
+            "UPDATE endpoint_data
+            SET
+                ntf_first_responded = true,
+                ntf_responded_by = $3,
+                ntf_responded_timestamp = CURRENT_TIMESTAMP
+            WHERE
+                endpoint_id = $1 AND outage_id = $2 AND (NOT ntf_first_responded)",
+            endpoint_id,
+            outage_id,
+            admin_id
+        )
+        .execute(self.postgres.as_ref())
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        Ok(ret.rows_affected() > 0)
+    }
+
     async fn set_first_notification_sent(
         &self,
         endpoint_id: EndpointId,
         outage_id: OutageId,
-    ) -> Result<()> {
-        let format = format!(
-            "UPDATE {} 
-            SET 
-                ntf_is_being_handled=false, 
-                ntf_is_being_handled_timestamp=null, 
+        claim_generation: i64,
+    ) -> Result<bool> {
+        let ret = sqlx::query!(
+            "UPDATE endpoint_data
+            SET
+                ntf_is_being_handled=false,
+                ntf_is_being_handled_timestamp=null,
                 ntf_is_being_handled_service_id=null,
                 ntf_is_first_notification_sent=true,
-                ntf_first_notification_sent_timestamp=CURRENT_TIMESTAMP
-            WHERE 
-                endpoint_id = $1 AND outage_id = $2",
-            Self::ENDPOINTS_TABLE_NAME
-        );
-        let ret = sqlx::query(&format)
-            .bind(endpoint_id)
-            .bind(outage_id)
-            .execute(self.postgres.as_ref())
-            .await
-            .map_err(anyhow::Error::msg)?;
+                ntf_first_notification_sent_timestamp=CURRENT_TIMESTAMP,
+                ntf_is_resolve_pending=true
+            WHERE
+                endpoint_id = $1 AND outage_id = $2
+                AND ntf_is_being_handled_service_id = $3 AND ntf_claim_generation = $4",
+            endpoint_id,
+            outage_id,
+            self.service_id,
+            claim_generation
+        )
+        .execute(self.postgres.as_ref())
+        .await?;
         log::info!("Pgquery result = {:?}", ret);
-        Ok(())
+        Ok(ret.rows_affected() > 0)
     }
 
     async fn set_second_notification_sent(
         &self,
         endpoint_id: EndpointId,
         outage_id: OutageId,
-    ) -> Result<()> {
-        let format = format!(
-            "UPDATE {} 
-            SET 
-                ntf_is_being_handled=false, 
-                ntf_is_being_handled_timestamp=null, 
+        claim_generation: i64,
+    ) -> Result<bool> {
+        let ret = sqlx::query!(
+            "UPDATE endpoint_data
+            SET
+                ntf_is_being_handled=false,
+                ntf_is_being_handled_timestamp=null,
+                ntf_is_being_handled_service_id=null,
+                ntf_is_second_notification_sent=true,
+                ntf_last_notification_timestamp=CURRENT_TIMESTAMP
+            WHERE
+                endpoint_id = $1 AND outage_id = $2
+                AND ntf_is_being_handled_service_id = $3 AND ntf_claim_generation = $4",
+            endpoint_id,
+            outage_id,
+            self.service_id,
+            claim_generation
+        )
+        .execute(self.postgres.as_ref())
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        Ok(ret.rows_affected() > 0)
+    }
+
+    async fn set_reminder_sent(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        claim_generation: i64,
+    ) -> Result<bool> {
+        let ret = sqlx::query!(
+            "UPDATE endpoint_data
+            SET
+                ntf_is_being_handled=false,
+                ntf_is_being_handled_timestamp=null,
                 ntf_is_being_handled_service_id=null,
-                ntf_is_second_notification_sent=true
+                ntf_reminders_sent=ntf_reminders_sent + 1,
+                ntf_last_notification_timestamp=CURRENT_TIMESTAMP
+            WHERE
+                endpoint_id = $1 AND outage_id = $2
+                AND ntf_is_being_handled_service_id = $3 AND ntf_claim_generation = $4",
+            endpoint_id,
+            outage_id,
+            self.service_id,
+            claim_generation
+        )
+        .execute(self.postgres.as_ref())
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        Ok(ret.rows_affected() > 0)
+    }
+
+    // Resolutions are claimed, like outages, so that they are reported again if this instance dies before it's done.
+    async fn sql_update_and_select_resolved(&self) -> Result<Vec<EndpointData>> {
+        sqlx::query_as!(
+            EndpointData,
+            "UPDATE endpoint_data
+            SET
+                ntf_resolve_claimed_timestamp = CURRENT_TIMESTAMP
+            WHERE
+                (NOT is_down) AND ntf_is_resolve_pending
+                AND (
+                    ntf_resolve_claimed_timestamp IS NULL
+                    OR ntf_resolve_claimed_timestamp < CURRENT_TIMESTAMP - $1::interval
+                )
+            RETURNING
+                endpoint_id,
+                http_address,
+                is_down,
+                outage_id,
+                ntf_is_being_handled,
+                ntf_is_being_handled_timestamp,
+                ntf_is_being_handled_service_id,
+                ntf_is_first_notification_sent,
+                ntf_first_notification_sent_timestamp,
+                ntf_is_second_notification_sent,
+                ntf_reminders_sent,
+                conf_primary_admin,
+                conf_secondary_admin,
+                conf_allowed_response_duration,
+                ntf_first_responded,
+                conf_webhook_url,
+                conf_chat_channel,
+                outage_start_timestamp,
+                conf_incident_routing_key,
+                outage_failure_reason,
+                conf_severity,
+                ntf_claim_generation",
+            Self::seconds(self.secs_wait_when_handled)
+        )
+        .fetch_all(self.postgres.as_ref())
+        .await
+        .map_err(NotificationError::from)
+    }
+
+    async fn insert_chat_thread(
+        &self,
+        outage_id: OutageId,
+        channel: String,
+        thread_id: String,
+    ) -> Result<()> {
+        let ret = sqlx::query!(
+            "INSERT INTO chat_thread
+                (outage_id, channel, thread_id, created_timestamp)
+            VALUES
+                ($1, $2, $3, CURRENT_TIMESTAMP)
+            ON CONFLICT DO NOTHING",
+            outage_id,
+            channel,
+            thread_id
+        )
+        .execute(self.postgres.as_ref())
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        Ok(())
+    }
+
+    async fn sql_get_chat_thread(
+        &self,
+        outage_id: OutageId,
+        channel: String,
+    ) -> Result<Option<String>> {
+        let ret = sqlx::query_scalar!(
+            "SELECT thread_id FROM chat_thread WHERE outage_id = $1 AND channel = $2",
+            outage_id,
+            channel
+        )
+        .fetch_optional(self.postgres.as_ref())
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        Ok(ret)
+    }
+
+    async fn insert_telegram_message(
+        &self,
+        chat_id: ContactId,
+        message_id: i32,
+        outage_id: OutageId,
+        ack_token: String,
+    ) -> Result<()> {
+        let ret = sqlx::query!(
+            "INSERT INTO telegram_message
+                (chat_id, message_id, outage_id, ack_token, sent_timestamp)
+            VALUES
+                ($1, $2, $3, $4, CURRENT_TIMESTAMP)
+            ON CONFLICT DO NOTHING",
+            chat_id,
+            message_id,
+            outage_id,
+            ack_token
+        )
+        .execute(self.postgres.as_ref())
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        Ok(())
+    }
+
+    async fn sql_get_telegram_message_token(
+        &self,
+        chat_id: ContactId,
+        message_id: i32,
+    ) -> Result<Option<String>> {
+        let ret = sqlx::query_scalar!(
+            "SELECT ack_token FROM telegram_message WHERE chat_id = $1 AND message_id = $2",
+            chat_id,
+            message_id
+        )
+        .fetch_optional(self.postgres.as_ref())
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        Ok(ret)
+    }
+
+    async fn sql_get_telegram_messages(
+        &self,
+        outage_id: OutageId,
+    ) -> Result<Vec<(ContactId, i32)>> {
+        let ret = sqlx::query!(
+            "SELECT chat_id, message_id FROM telegram_message WHERE outage_id = $1 ORDER BY sent_timestamp",
+            outage_id
+        )
+        .fetch_all(self.postgres.as_ref())
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        Ok(ret.into_iter().map(|r| (r.chat_id, r.message_id)).collect())
+    }
+
+    async fn insert_notification_log(
+        &self,
+        outage_id: OutageId,
+        endpoint_id: EndpointId,
+        admin_id: AdminId,
+        escalation_level: i32,
+        results: &[DeliveryResult],
+    ) -> Result<()> {
+        let mut transaction = self.postgres.begin().await?;
+        for result in results {
+            let error = match &result.status {
+                DeliveryStatus::Failed(error) => Some(error.as_str()),
+                _ => None,
+            };
+            let ret = sqlx::query!(
+                "INSERT INTO notification_log
+                    (outage_id, endpoint_id, admin_id, escalation_level, channel, target, status, error, attempt_timestamp)
+                VALUES
+                    ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+                outage_id,
+                endpoint_id,
+                &admin_id,
+                escalation_level,
+                result.channel.as_str(),
+                &result.target,
+                result.status.as_str(),
+                error,
+                result.attempted_at
+            )
+            .execute(&mut *transaction)
+            .await?;
+            log::info!("Pgquery result = {:?}", ret);
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn sql_get_contact_methods(&self, admin_id: AdminId) -> Result<Vec<ContactMethod>> {
+        let ret = sqlx::query_as!(
+            ContactMethod,
+            "SELECT admin_id, channel, address, rank FROM admin_contact_method WHERE admin_id = $1 ORDER BY rank",
+            admin_id
+        )
+        .fetch_all(self.postgres.as_ref())
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        Ok(ret)
+    }
+
+    async fn sql_get_notification_rule(
+        &self,
+        admin_id: AdminId,
+        severity: Severity,
+    ) -> Result<Option<NotificationRule>> {
+        let ret = sqlx::query_as!(
+            NotificationRule,
+            "SELECT admin_id, severity, channels FROM admin_notification_rule WHERE admin_id = $1 AND severity = $2",
+            admin_id,
+            severity.as_str()
+        )
+        .fetch_optional(self.postgres.as_ref())
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        Ok(ret)
+    }
+
+    async fn sql_get_quiet_hours(&self, admin_id: AdminId) -> Result<Vec<QuietHours>> {
+        let ret = sqlx::query_as!(
+            QuietHours,
+            "SELECT admin_id, start_time, end_time FROM admin_quiet_hours WHERE admin_id = $1",
+            admin_id
+        )
+        .fetch_all(self.postgres.as_ref())
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        Ok(ret)
+    }
+
+    async fn set_resolution_reported(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+    ) -> Result<()> {
+        let ret = sqlx::query!(
+            "UPDATE endpoint_data
+            SET
+                ntf_is_resolve_pending = false,
+                ntf_resolve_claimed_timestamp = null
             WHERE
                 endpoint_id = $1 AND outage_id = $2",
-            Self::ENDPOINTS_TABLE_NAME
-        );
-        let ret = sqlx::query(&format)
-            .bind(endpoint_id)
-            .bind(outage_id)
-            .execute(self.postgres.as_ref())
-            .await
-            .map_err(anyhow::Error::msg)?;
+            endpoint_id,
+            outage_id
+        )
+        .execute(self.postgres.as_ref())
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        Ok(())
+    }
+
+    // First notification counts as sent and unanswered, so the outage is escalated on the next poll.
+    async fn set_first_notification_skipped(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        claim_generation: i64,
+    ) -> Result<bool> {
+        let ret = sqlx::query!(
+            "UPDATE endpoint_data
+            SET
+                ntf_is_being_handled=false,
+                ntf_is_being_handled_timestamp=null,
+                ntf_is_being_handled_service_id=null,
+                ntf_is_first_notification_sent=true,
+                ntf_first_notification_sent_timestamp=CURRENT_TIMESTAMP - conf_allowed_response_duration,
+                ntf_is_resolve_pending=true
+            WHERE
+                endpoint_id = $1 AND outage_id = $2 AND (NOT ntf_is_first_notification_sent)
+                AND ntf_is_being_handled_service_id = $3 AND ntf_claim_generation = $4",
+            endpoint_id,
+            outage_id,
+            self.service_id,
+            claim_generation
+        )
+        .execute(self.postgres.as_ref())
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        Ok(ret.rows_affected() > 0)
+    }
+
+    async fn insert_notification_group(
+        &self,
+        group_id: Uuid,
+        admin_id: AdminId,
+        members: &[NotificationGroupMember],
+    ) -> Result<()> {
+        let mut transaction = self.postgres.begin().await?;
+        let ret = sqlx::query!(
+            "INSERT INTO notification_group (group_id, admin_id, sent_timestamp) VALUES ($1, $2, CURRENT_TIMESTAMP)",
+            group_id,
+            admin_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        for member in members {
+            let ret = sqlx::query!(
+                "INSERT INTO notification_group_member (group_id, endpoint_id, outage_id, is_first) VALUES ($1, $2, $3, $4)",
+                group_id,
+                member.endpoint_id,
+                member.outage_id,
+                member.is_first
+            )
+            .execute(&mut *transaction)
+            .await?;
+            log::info!("Pgquery result = {:?}", ret);
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn sql_delete_notification_group(&self, group_id: Uuid) -> Result<()> {
+        let ret = sqlx::query!(
+            "DELETE FROM notification_group WHERE group_id = $1",
+            group_id
+        )
+        .execute(self.postgres.as_ref())
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        Ok(())
+    }
+
+    async fn sql_get_notification_group_members(
+        &self,
+        group_id: Uuid,
+    ) -> Result<Vec<NotificationGroupMember>> {
+        let ret = sqlx::query_as!(
+            NotificationGroupMember,
+            "SELECT endpoint_id, outage_id, is_first FROM notification_group_member WHERE group_id = $1",
+            group_id
+        )
+        .fetch_all(self.postgres.as_ref())
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        Ok(ret)
+    }
+
+    async fn sql_get_notification_times(
+        &self,
+        admin_id: AdminId,
+        period: MyDuration,
+    ) -> Result<Vec<MyTime>> {
+        let ret = sqlx::query_scalar!(
+            "SELECT sent_timestamp FROM notification_group
+            WHERE admin_id = $1 AND sent_timestamp > CURRENT_TIMESTAMP - $2::interval
+            ORDER BY sent_timestamp",
+            admin_id,
+            period
+        )
+        .fetch_all(self.postgres.as_ref())
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        Ok(ret)
+    }
+
+    // Claim of this instance is moved so that it expires at the given time.
+    async fn set_notification_deferred(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        claim_generation: i64,
+        until: MyTime,
+    ) -> Result<bool> {
+        let ret = sqlx::query!(
+            "UPDATE endpoint_data
+            SET
+                ntf_is_being_handled_timestamp = $3::timestamp - $4::interval
+            WHERE
+                endpoint_id = $1 AND outage_id = $2
+                AND ntf_is_being_handled_service_id = $5 AND ntf_claim_generation = $6",
+            endpoint_id,
+            outage_id,
+            until,
+            Self::seconds(self.secs_wait_when_handled),
+            self.service_id,
+            claim_generation
+        )
+        .execute(self.postgres.as_ref())
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        Ok(ret.rows_affected() > 0)
+    }
+
+    // Entries already in the outbox keep their status.
+    async fn insert_outbox_entries(&self, entries: &[OutboxEntry]) -> Result<Vec<Uuid>> {
+        let mut transaction = self.postgres.begin().await?;
+        for entry in entries {
+            let ret = sqlx::query!(
+                "INSERT INTO notification_outbox
+                    (idempotency_key, outage_id, escalation_level, admin_id, channel, status, created_timestamp)
+                VALUES
+                    ($1, $2, $3, $4, $5, 'pending', CURRENT_TIMESTAMP)
+                ON CONFLICT (idempotency_key) DO NOTHING",
+                entry.idempotency_key,
+                entry.outage_id,
+                entry.escalation_level,
+                &entry.admin_id,
+                &entry.channel
+            )
+            .execute(&mut *transaction)
+            .await?;
+            log::info!("Pgquery result = {:?}", ret);
+        }
+        let keys: Vec<Uuid> = entries.iter().map(|e| e.idempotency_key).collect();
+        let ret = sqlx::query_scalar!(
+            "SELECT idempotency_key FROM notification_outbox
+            WHERE idempotency_key = ANY($1) AND status = 'completed'",
+            &keys
+        )
+        .fetch_all(&mut *transaction)
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        transaction.commit().await?;
+        Ok(ret)
+    }
+
+    // Claim is released only together with recording the dead letter, so the outage isn't claimed in between.
+    async fn insert_dead_letter(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: Option<OutageId>,
+        claim_generation: i64,
+        reason: &str,
+    ) -> Result<bool> {
+        let mut transaction = self.postgres.begin().await?;
+        let ret = sqlx::query!(
+            "UPDATE endpoint_data
+            SET
+                ntf_is_being_handled=false,
+                ntf_is_being_handled_timestamp=null,
+                ntf_is_being_handled_service_id=null
+            WHERE
+                endpoint_id = $1 AND outage_id IS NOT DISTINCT FROM $2
+                AND ntf_is_being_handled_service_id = $3 AND ntf_claim_generation = $4",
+            endpoint_id,
+            outage_id,
+            self.service_id,
+            claim_generation
+        )
+        .execute(&mut *transaction)
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        if ret.rows_affected() == 0 {
+            return Ok(false);
+        }
+        let ret = sqlx::query!(
+            "INSERT INTO notification_dead_letter
+                (endpoint_id, outage_id, reason, created_timestamp)
+            VALUES
+                ($1, $2, $3, CURRENT_TIMESTAMP)",
+            endpoint_id,
+            outage_id,
+            reason
+        )
+        .execute(&mut *transaction)
+        .await?;
+        log::info!("Pgquery result = {:?}", ret);
+        transaction.commit().await?;
+        Ok(true)
+    }
+
+    async fn set_outbox_entries_completed(&self, keys: &[Uuid]) -> Result<()> {
+        let ret = sqlx::query!(
+            "UPDATE notification_outbox
+            SET
+                status = 'completed',
+                completed_timestamp = CURRENT_TIMESTAMP
+            WHERE
+                idempotency_key = ANY($1) AND status = 'pending'",
+            keys
+        )
+        .execute(self.postgres.as_ref())
+        .await?;
         log::info!("Pgquery result = {:?}", ret);
         Ok(())
     }
@@ -208,40 +939,258 @@ impl MyDBQueryExecutor {
 #[async_trait::async_trait]
 impl DBQueryExecutor for MyDBQueryExecutor {
     async fn get_endpoints_to_process(&self) -> Result<Vec<EndpointData>> {
-        let sql_query = self.sql_update_and_select_endpoints_str();
-        log::debug!("sql_query to select all endpoints {}", sql_query.clone());
-        let ret = self
-            .execute_statement_returning_endpoints(sql_query.as_str())
-            .await;
-        ret
+        self.sql_update_and_select_endpoints().await
     }
 
     async fn mark_endpoint_responded(
         &self,
         endpoint_id: EndpointId,
         outage_id: OutageId,
-    ) -> Result<()> {
-        self.set_endpoint_responded(endpoint_id, outage_id).await
+        admin_id: AdminId,
+    ) -> Result<bool> {
+        self.set_endpoint_responded(endpoint_id, outage_id, admin_id)
+            .await
     }
 
     async fn mark_first_notification_sent(
         &self,
         endpoint_id: EndpointId,
         outage_id: OutageId,
-    ) -> Result<()> {
-        self.set_first_notification_sent(endpoint_id, outage_id)
+        claim_generation: i64,
+    ) -> Result<bool> {
+        self.set_first_notification_sent(endpoint_id, outage_id, claim_generation)
             .await
     }
     async fn mark_second_notification_sent(
         &self,
         endpoint_id: EndpointId,
         outage_id: OutageId,
-    ) -> Result<()> {
-        self.set_second_notification_sent(endpoint_id, outage_id)
+        claim_generation: i64,
+    ) -> Result<bool> {
+        self.set_second_notification_sent(endpoint_id, outage_id, claim_generation)
+            .await
+    }
+    async fn mark_reminder_sent(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        claim_generation: i64,
+    ) -> Result<bool> {
+        self.set_reminder_sent(endpoint_id, outage_id, claim_generation)
             .await
     }
 
     async fn get_admin_data(&self, admin_id: AdminId) -> Result<Admin> {
         self.sql_get_admin_id(admin_id).await
     }
+
+    async fn get_admin_by_telegram_contact_id(
+        &self,
+        telegram_contact_id: ContactId,
+    ) -> Result<Option<Admin>> {
+        self.sql_get_admin_by_telegram_contact_id(telegram_contact_id)
+            .await
+    }
+
+    async fn get_current_outages(&self) -> Result<Vec<EndpointStatus>> {
+        self.sql_get_current_outages().await
+    }
+
+    async fn get_endpoint_status(&self, endpoint: &str) -> Result<Option<EndpointStatus>> {
+        self.sql_get_endpoint_status(endpoint).await
+    }
+
+    async fn get_endpoint_by_outage(&self, outage_id: OutageId) -> Result<Option<EndpointData>> {
+        self.sql_get_endpoint_by_outage(outage_id).await
+    }
+
+    async fn mute_endpoint(&self, endpoint_id: EndpointId, duration: MyDuration) -> Result<bool> {
+        self.set_endpoint_muted(endpoint_id, duration).await
+    }
+
+    async fn escalate_outage(&self, endpoint_id: EndpointId, outage_id: OutageId) -> Result<bool> {
+        self.set_first_response_deadline_passed(endpoint_id, outage_id)
+            .await
+    }
+
+    async fn snooze_outage(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        duration: MyDuration,
+    ) -> Result<bool> {
+        self.set_first_response_deadline_postponed(endpoint_id, outage_id, duration)
+            .await
+    }
+
+    async fn record_sms_sent(
+        &self,
+        provider_message_id: String,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        admin_id: AdminId,
+    ) -> Result<()> {
+        self.insert_sms_delivery(provider_message_id, endpoint_id, outage_id, admin_id)
+            .await
+    }
+
+    async fn update_sms_delivery_status(
+        &self,
+        provider_message_id: String,
+        status: String,
+        error_code: Option<String>,
+    ) -> Result<bool> {
+        self.set_sms_delivery_status(provider_message_id, status, error_code)
+            .await
+    }
+
+    async fn get_resolved_outages_to_process(&self) -> Result<Vec<EndpointData>> {
+        self.sql_update_and_select_resolved().await
+    }
+
+    async fn mark_resolution_reported(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+    ) -> Result<()> {
+        self.set_resolution_reported(endpoint_id, outage_id).await
+    }
+
+    async fn record_chat_thread(
+        &self,
+        outage_id: OutageId,
+        channel: String,
+        thread_id: String,
+    ) -> Result<()> {
+        self.insert_chat_thread(outage_id, channel, thread_id).await
+    }
+
+    async fn get_chat_thread(
+        &self,
+        outage_id: OutageId,
+        channel: String,
+    ) -> Result<Option<String>> {
+        self.sql_get_chat_thread(outage_id, channel).await
+    }
+
+    async fn record_telegram_message(
+        &self,
+        chat_id: ContactId,
+        message_id: i32,
+        outage_id: OutageId,
+        ack_token: String,
+    ) -> Result<()> {
+        self.insert_telegram_message(chat_id, message_id, outage_id, ack_token)
+            .await
+    }
+
+    async fn get_telegram_message_token(
+        &self,
+        chat_id: ContactId,
+        message_id: i32,
+    ) -> Result<Option<String>> {
+        self.sql_get_telegram_message_token(chat_id, message_id)
+            .await
+    }
+
+    async fn get_telegram_messages(&self, outage_id: OutageId) -> Result<Vec<(ContactId, i32)>> {
+        self.sql_get_telegram_messages(outage_id).await
+    }
+
+    async fn record_notification_attempts(
+        &self,
+        outage_id: OutageId,
+        endpoint_id: EndpointId,
+        admin_id: AdminId,
+        escalation_level: i32,
+        results: &[DeliveryResult],
+    ) -> Result<()> {
+        self.insert_notification_log(outage_id, endpoint_id, admin_id, escalation_level, results)
+            .await
+    }
+
+    async fn get_contact_methods(&self, admin_id: AdminId) -> Result<Vec<ContactMethod>> {
+        self.sql_get_contact_methods(admin_id).await
+    }
+
+    async fn record_notification_group(
+        &self,
+        group_id: Uuid,
+        admin_id: AdminId,
+        members: &[NotificationGroupMember],
+    ) -> Result<()> {
+        self.insert_notification_group(group_id, admin_id, members)
+            .await
+    }
+
+    async fn delete_notification_group(&self, group_id: Uuid) -> Result<()> {
+        self.sql_delete_notification_group(group_id).await
+    }
+
+    async fn get_notification_group_members(
+        &self,
+        group_id: Uuid,
+    ) -> Result<Vec<NotificationGroupMember>> {
+        self.sql_get_notification_group_members(group_id).await
+    }
+
+    async fn get_notification_times(
+        &self,
+        admin_id: AdminId,
+        period: MyDuration,
+    ) -> Result<Vec<MyTime>> {
+        self.sql_get_notification_times(admin_id, period).await
+    }
+
+    async fn defer_notification(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        claim_generation: i64,
+        until: MyTime,
+    ) -> Result<bool> {
+        self.set_notification_deferred(endpoint_id, outage_id, claim_generation, until)
+            .await
+    }
+
+    async fn get_quiet_hours(&self, admin_id: AdminId) -> Result<Vec<QuietHours>> {
+        self.sql_get_quiet_hours(admin_id).await
+    }
+
+    async fn skip_first_notification(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        claim_generation: i64,
+    ) -> Result<bool> {
+        self.set_first_notification_skipped(endpoint_id, outage_id, claim_generation)
+            .await
+    }
+
+    async fn record_pending_deliveries(&self, entries: &[OutboxEntry]) -> Result<Vec<Uuid>> {
+        self.insert_outbox_entries(entries).await
+    }
+
+    async fn complete_deliveries(&self, keys: &[Uuid]) -> Result<()> {
+        self.set_outbox_entries_completed(keys).await
+    }
+
+    async fn dead_letter_outage(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: Option<OutageId>,
+        claim_generation: i64,
+        reason: &str,
+    ) -> Result<bool> {
+        self.insert_dead_letter(endpoint_id, outage_id, claim_generation, reason)
+            .await
+    }
+
+    async fn get_notification_rule(
+        &self,
+        admin_id: AdminId,
+        severity: Severity,
+    ) -> Result<Option<NotificationRule>> {
+        self.sql_get_notification_rule(admin_id, severity).await
+    }
 }
@@ -1,4 +1,7 @@
-use chrono::NaiveDateTime;
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
 use sqlx::{postgres::types::PgInterval, FromRow};
 use uuid::Uuid;
 
@@ -16,16 +19,39 @@ pub struct EndpointData {
     pub http_address: String,
     pub is_down: bool,
     pub outage_id: Option<OutageId>,
+    // Columns of the row the service doesn't read, only the in-memory executor of tests does.
+    #[allow(dead_code)]
     pub ntf_is_being_handled: bool,
+    #[allow(dead_code)]
     pub ntf_is_being_handled_timestamp: Option<MyTime>,
+    #[allow(dead_code)]
     pub ntf_is_being_handled_service_id: Option<ServiceInstanceId>,
     pub ntf_is_first_notification_sent: bool,
     pub ntf_first_notification_sent_timestamp: Option<MyTime>,
     pub ntf_is_second_notification_sent: bool,
+    // Reminders sent to the secondary admin after the second notification.
+    pub ntf_reminders_sent: i32,
     pub conf_primary_admin: AdminId,
     pub conf_secondary_admin: AdminId,
+    #[allow(dead_code)]
     pub conf_allowed_response_duration: MyDuration,
+    #[allow(dead_code)]
     pub ntf_first_responded: bool,
+    pub conf_webhook_url: Option<String>,
+    pub conf_chat_channel: Option<String>,
+    pub outage_start_timestamp: Option<MyTime>,
+    pub conf_incident_routing_key: Option<String>,
+    pub outage_failure_reason: Option<String>,
+    pub conf_severity: String,
+    // Incremented by every claim. Writes under an older claim, taken over by another instance, are rejected.
+    pub ntf_claim_generation: i64,
+}
+
+impl EndpointData {
+    // Admins that are allowed to respond to outages of this endpoint.
+    pub fn is_on_escalation_path(&self, admin_id: &AdminId) -> bool {
+        self.conf_primary_admin == *admin_id || self.conf_secondary_admin == *admin_id
+    }
 }
 
 #[derive(Debug, FromRow, Clone)]
@@ -34,4 +60,285 @@ pub struct Admin {
     pub telegram_contact_id: ContactId,
     pub phone_number: String,
     pub email_address: String,
+    // IANA name, e.g. Europe/Warsaw.
+    pub timezone: String,
+    pub quiet_hours_action: String,
+}
+
+impl Admin {
+    fn tz(&self) -> Tz {
+        Tz::from_str(&self.timezone).unwrap_or_else(|e| {
+            log::warn!(
+                "Invalid timezone of admin {}: {}, using UTC",
+                self.admin_id,
+                e
+            );
+            Tz::UTC
+        })
+    }
+
+    /*
+        When the quiet hours the admin is in at the given time end, None if the admin isn't in quiet hours.
+        Windows that overlap or follow each other are treated as one.
+    */
+    pub fn quiet_hours_end(
+        &self,
+        quiet_hours: &[QuietHours],
+        now: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        let tz = self.tz();
+        let local = now.with_timezone(&tz).naive_local();
+        let mut end = local;
+        // Bounded, windows covering the whole day would extend the end forever.
+        for _ in 0..quiet_hours.len() {
+            match quiet_hours.iter().filter_map(|q| q.end_after(end)).max() {
+                Some(later) => end = later,
+                None => break,
+            }
+        }
+        if end == local {
+            return None;
+        }
+        // End skipped by a change to summer time is taken as the same wall clock duration from now.
+        Some(
+            tz.from_local_datetime(&end)
+                .earliest()
+                .map_or(now + (end - local), |end| end.with_timezone(&Utc)),
+        )
+    }
+}
+
+// Window of the day, in the admin's timezone, when the admin doesn't want to be paged about non-critical outages.
+#[derive(Debug, FromRow, Clone)]
+pub struct QuietHours {
+    #[allow(dead_code)]
+    pub admin_id: AdminId,
+    pub start_time: NaiveTime,
+    // Exclusive. Windows with end before start span midnight.
+    pub end_time: NaiveTime,
+}
+
+impl QuietHours {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start_time <= self.end_time {
+            self.start_time <= time && time < self.end_time
+        } else {
+            self.start_time <= time || time < self.end_time
+        }
+    }
+
+    // First end of the window after the given local time, None if the window doesn't contain it.
+    fn end_after(&self, local: NaiveDateTime) -> Option<NaiveDateTime> {
+        if !self.contains(local.time()) {
+            return None;
+        }
+        let end = local.date().and_time(self.end_time);
+        Some(if end > local {
+            end
+        } else {
+            end + chrono::Duration::days(1)
+        })
+    }
+}
+
+// Outage notified about in a summary, see notification_group.
+#[derive(Debug, FromRow, Clone)]
+pub struct NotificationGroupMember {
+    pub endpoint_id: EndpointId,
+    pub outage_id: OutageId,
+    pub is_first: bool,
+}
+
+// Delivery of one escalation step of an outage to an admin by one channel, see notification_outbox.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutboxEntry {
+    // Same for every attempt of the delivery, so that channels can recognize a retry.
+    pub idempotency_key: Uuid,
+    pub outage_id: OutageId,
+    pub escalation_level: i32,
+    pub admin_id: AdminId,
+    pub channel: String,
+}
+
+// What happens to non-critical notifications for an admin in quiet hours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuietHoursAction {
+    // Notification is sent when quiet hours end.
+    Defer,
+    // Notification goes to the secondary admin right away. Secondary admins defer.
+    Escalate,
+}
+
+impl FromStr for QuietHoursAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<QuietHoursAction> {
+        match s.trim().to_lowercase().as_str() {
+            "defer" => Ok(QuietHoursAction::Defer),
+            "escalate" => Ok(QuietHoursAction::Escalate),
+            _ => Err(anyhow::anyhow!(
+                "unknown quiet hours action {}, expected defer or escalate",
+                s
+            )),
+        }
+    }
+}
+
+// Way of reaching an admin, admins can have several per channel.
+#[derive(Debug, FromRow, Clone)]
+pub struct ContactMethod {
+    pub admin_id: AdminId,
+    pub channel: String,
+    pub address: String,
+    // Methods with lower rank are used first, methods of equal rank together.
+    pub rank: i32,
+}
+
+// Channels an admin wants to be notified by about outages of the given severity.
+#[derive(Debug, FromRow, Clone)]
+pub struct NotificationRule {
+    pub admin_id: AdminId,
+    pub severity: String,
+    // Comma separated channel names.
+    pub channels: String,
+}
+
+// Ordered from the most severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Critical,
+    Warning,
+    Info,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Critical => "critical",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        }
+    }
+}
+
+impl FromStr for Severity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Severity> {
+        match s.trim().to_lowercase().as_str() {
+            "critical" => Ok(Severity::Critical),
+            "warning" => Ok(Severity::Warning),
+            "info" => Ok(Severity::Info),
+            _ => Err(anyhow::anyhow!(
+                "unknown severity {}, expected one of critical, warning, info",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, FromRow, Clone)]
+pub struct EndpointStatus {
+    pub endpoint_id: EndpointId,
+    pub http_address: String,
+    pub is_down: bool,
+    pub outage_id: Option<OutageId>,
+    pub last_ping_time: Option<MyTime>,
+    pub ntf_first_responded: bool,
+    pub ntf_muted_until: Option<MyTime>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn quiet_hours(start: NaiveTime, end: NaiveTime) -> QuietHours {
+        QuietHours {
+            admin_id: "admin".to_string(),
+            start_time: start,
+            end_time: end,
+        }
+    }
+
+    fn admin_in(timezone: &str) -> Admin {
+        Admin {
+            admin_id: "admin".to_string(),
+            telegram_contact_id: "42".to_string(),
+            phone_number: "+48123456789".to_string(),
+            email_address: "admin@example.com".to_string(),
+            timezone: timezone.to_string(),
+            quiet_hours_action: "defer".to_string(),
+        }
+    }
+
+    #[test]
+    fn quiet_hours_within_day_exclude_end() {
+        let window = quiet_hours(time(12, 0), time(14, 0));
+
+        assert!(!window.contains(time(11, 59)));
+        assert!(window.contains(time(12, 0)));
+        assert!(window.contains(time(13, 30)));
+        assert!(!window.contains(time(14, 0)));
+    }
+
+    #[test]
+    fn quiet_hours_span_midnight_when_end_is_before_start() {
+        let window = quiet_hours(time(22, 0), time(7, 0));
+
+        assert!(window.contains(time(22, 0)));
+        assert!(window.contains(time(23, 59)));
+        assert!(window.contains(time(0, 0)));
+        assert!(window.contains(time(6, 59)));
+        assert!(!window.contains(time(7, 0)));
+        assert!(!window.contains(time(12, 0)));
+        assert!(!window.contains(time(21, 59)));
+    }
+
+    #[test]
+    fn quiet_hours_end_in_admin_timezone() {
+        let windows = [quiet_hours(time(22, 0), time(7, 0))];
+        // 23:30 in Warsaw in summer time, 17:30 in New York.
+        let now = Utc.with_ymd_and_hms(2024, 7, 1, 21, 30, 0).unwrap();
+
+        assert_eq!(
+            admin_in("Europe/Warsaw").quiet_hours_end(&windows, now),
+            Some(Utc.with_ymd_and_hms(2024, 7, 2, 5, 0, 0).unwrap())
+        );
+        assert_eq!(
+            admin_in("America/New_York").quiet_hours_end(&windows, now),
+            None
+        );
+    }
+
+    #[test]
+    fn following_quiet_hours_are_joined() {
+        let windows = [
+            quiet_hours(time(12, 0), time(14, 0)),
+            quiet_hours(time(22, 0), time(7, 0)),
+            quiet_hours(time(6, 0), time(8, 30)),
+        ];
+        let now = Utc.with_ymd_and_hms(2024, 7, 1, 23, 0, 0).unwrap();
+
+        assert_eq!(
+            admin_in("UTC").quiet_hours_end(&windows, now),
+            Some(Utc.with_ymd_and_hms(2024, 7, 2, 8, 30, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn invalid_timezone_falls_back_to_utc() {
+        let windows = [quiet_hours(time(21, 0), time(22, 0))];
+        let now = Utc.with_ymd_and_hms(2024, 7, 1, 21, 30, 0).unwrap();
+        let end = Some(Utc.with_ymd_and_hms(2024, 7, 1, 22, 0, 0).unwrap());
+
+        assert_eq!(
+            admin_in("Mars/Olympus_Mons").quiet_hours_end(&windows, now),
+            end
+        );
+        assert_eq!(admin_in("").quiet_hours_end(&windows, now), end);
+    }
 }
@@ -0,0 +1,39 @@
+use crate::domain::{AdminId, EndpointId};
+
+// Errors of the notification pipeline. Configuration read at startup keeps using anyhow.
+#[derive(Debug, thiserror::Error)]
+pub enum NotificationError {
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("admin {0} does not exist or was removed")]
+    AdminNotFound(AdminId),
+    #[error("endpoint {0} is down without an outage")]
+    MissingOutage(EndpointId),
+    #[error("invalid response: {0}")]
+    InvalidResponse(String),
+    #[error("response channel is closed, the service is shutting down")]
+    ResponseChannelClosed,
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("provider rejected the request: {0}")]
+    Provider(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl NotificationError {
+    /*
+        Errors caused by the configuration of the endpoint rather than by a failing dependency.
+        Retrying doesn't help, the outage is dead-lettered instead.
+    */
+    pub fn is_misconfiguration(&self) -> bool {
+        matches!(
+            self,
+            NotificationError::AdminNotFound(_) | NotificationError::MissingOutage(_)
+        )
+    }
+}
+
+pub type Result<T, E = NotificationError> = std::result::Result<T, E>;
@@ -0,0 +1,584 @@
+use std::env;
+
+use serde_json::{json, Value};
+
+use crate::{
+    config::read_secret,
+    domain::{AdminId, OutageId, Severity},
+    error::{NotificationError, Result},
+    notification_service::{
+        Channel, DeliveryResult, NotificationData, NotificationSender, OutageEvent, OutageUpdate,
+    },
+};
+
+#[async_trait::async_trait]
+pub trait IncidentProvider: Send + Sync {
+    async fn trigger(&self, routing_key: &str, x: &NotificationData) -> Result<()>;
+    async fn acknowledge(&self, routing_key: &str, x: &OutageUpdate, admin: &AdminId)
+        -> Result<()>;
+    async fn resolve(&self, routing_key: &str, x: &OutageUpdate) -> Result<()>;
+}
+
+/*
+    Incidents are deduplicated by a key derived from the outage, so all events of an outage land in one incident.
+    A repeated trigger of an open incident is merged into it, which also covers retries of a notification.
+*/
+fn dedup_key(outage_id: OutageId) -> String {
+    format!("irio-{}", outage_id)
+}
+
+fn summary(http_address: &str) -> String {
+    format!("{} is down", http_address)
+}
+
+async fn check_response(response: reqwest::Response) -> Result<()> {
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(NotificationError::Provider(format!(
+            "responded with {}: {}",
+            status, body
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Clone)]
+pub struct PagerDutyProvider {
+    client: reqwest::Client,
+    api_url: String,
+}
+
+impl PagerDutyProvider {
+    pub const DEFAULT_API_URL: &'static str = "https://events.pagerduty.com";
+
+    pub fn new(api_url: String) -> PagerDutyProvider {
+        PagerDutyProvider {
+            client: reqwest::Client::new(),
+            api_url,
+        }
+    }
+
+    async fn enqueue(&self, event: Value) -> Result<()> {
+        let url = format!("{}/v2/enqueue", self.api_url.trim_end_matches('/'));
+        let response = self.client.post(url).json(&event).send().await?;
+        check_response(response).await
+    }
+}
+
+#[async_trait::async_trait]
+impl IncidentProvider for PagerDutyProvider {
+    async fn trigger(&self, routing_key: &str, x: &NotificationData) -> Result<()> {
+        let links: Vec<Value> = x
+            .ack_links
+            .iter()
+            .flat_map(|l| {
+                [
+                    json!({"href": l.acknowledge, "text": "Acknowledge in irio"}),
+                    json!({"href": l.escalate, "text": "Escalate in irio"}),
+                ]
+            })
+            .collect();
+        self.enqueue(json!({
+            "routing_key": routing_key,
+            "event_action": "trigger",
+            "dedup_key": dedup_key(x.outage_id),
+            "payload": {
+                "summary": summary(&x.http_address),
+                "source": x.http_address,
+                "severity": x.severity.as_str(),
+                "component": x.endpoint.to_string(),
+                "custom_details": {
+                    "endpoint": x.endpoint,
+                    "outage_id": x.outage_id.to_string(),
+                    "escalation_level": x.escalation_level(),
+                    "admin": x.admin,
+                    "idempotency_key": x.idempotency_key(Channel::Incident).to_string(),
+                },
+            },
+            "client": "irio",
+            "links": links,
+        }))
+        .await
+    }
+
+    async fn acknowledge(
+        &self,
+        routing_key: &str,
+        x: &OutageUpdate,
+        _admin: &AdminId,
+    ) -> Result<()> {
+        self.enqueue(json!({
+            "routing_key": routing_key,
+            "event_action": "acknowledge",
+            "dedup_key": dedup_key(x.outage_id),
+        }))
+        .await
+    }
+
+    async fn resolve(&self, routing_key: &str, x: &OutageUpdate) -> Result<()> {
+        self.enqueue(json!({
+            "routing_key": routing_key,
+            "event_action": "resolve",
+            "dedup_key": dedup_key(x.outage_id),
+        }))
+        .await
+    }
+}
+
+fn opsgenie_priority(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Critical => "P1",
+        Severity::Warning => "P3",
+        Severity::Info => "P5",
+    }
+}
+
+#[derive(Clone)]
+pub struct OpsgenieProvider {
+    client: reqwest::Client,
+    api_url: String,
+}
+
+impl OpsgenieProvider {
+    pub const DEFAULT_API_URL: &'static str = "https://api.opsgenie.com";
+
+    pub fn new(api_url: String) -> OpsgenieProvider {
+        OpsgenieProvider {
+            client: reqwest::Client::new(),
+            api_url,
+        }
+    }
+
+    async fn post(&self, api_key: &str, path: &str, body: Value) -> Result<()> {
+        let url = format!("{}{}", self.api_url.trim_end_matches('/'), path);
+        let response = self
+            .client
+            .post(url)
+            .header(
+                reqwest::header::AUTHORIZATION,
+                format!("GenieKey {}", api_key),
+            )
+            .json(&body)
+            .send()
+            .await?;
+        check_response(response).await
+    }
+}
+
+#[async_trait::async_trait]
+impl IncidentProvider for OpsgenieProvider {
+    async fn trigger(&self, api_key: &str, x: &NotificationData) -> Result<()> {
+        let mut details = json!({
+            "endpoint": x.endpoint.to_string(),
+            "outage_id": x.outage_id.to_string(),
+            "escalation_level": x.escalation_level().to_string(),
+            "admin": x.admin,
+            "idempotency_key": x.idempotency_key(Channel::Incident).to_string(),
+        });
+        if let Some(links) = &x.ack_links {
+            details["acknowledge_url"] = json!(links.acknowledge);
+            details["escalate_url"] = json!(links.escalate);
+        }
+        self.post(
+            api_key,
+            "/v2/alerts",
+            json!({
+                "message": summary(&x.http_address),
+                "alias": dedup_key(x.outage_id),
+                "entity": x.http_address,
+                "source": "irio",
+                "priority": opsgenie_priority(x.severity),
+                "details": details,
+            }),
+        )
+        .await
+    }
+
+    async fn acknowledge(&self, api_key: &str, x: &OutageUpdate, admin: &AdminId) -> Result<()> {
+        self.post(
+            api_key,
+            &format!(
+                "/v2/alerts/{}/acknowledge?identifierType=alias",
+                dedup_key(x.outage_id)
+            ),
+            json!({"source": "irio", "note": format!("Acknowledged by {} in irio", admin)}),
+        )
+        .await
+    }
+
+    async fn resolve(&self, api_key: &str, x: &OutageUpdate) -> Result<()> {
+        self.post(
+            api_key,
+            &format!(
+                "/v2/alerts/{}/close?identifierType=alias",
+                dedup_key(x.outage_id)
+            ),
+            json!({"source": "irio", "note": format!("{} is back up", x.http_address)}),
+        )
+        .await
+    }
+}
+
+#[derive(Clone)]
+pub enum ImplementedIncidentProvider {
+    PagerDuty(PagerDutyProvider),
+    Opsgenie(OpsgenieProvider),
+}
+
+#[async_trait::async_trait]
+impl IncidentProvider for ImplementedIncidentProvider {
+    async fn trigger(&self, routing_key: &str, x: &NotificationData) -> Result<()> {
+        match &self {
+            ImplementedIncidentProvider::PagerDuty(p) => p.trigger(routing_key, x).await,
+            ImplementedIncidentProvider::Opsgenie(p) => p.trigger(routing_key, x).await,
+        }
+    }
+
+    async fn acknowledge(
+        &self,
+        routing_key: &str,
+        x: &OutageUpdate,
+        admin: &AdminId,
+    ) -> Result<()> {
+        match &self {
+            ImplementedIncidentProvider::PagerDuty(p) => p.acknowledge(routing_key, x, admin).await,
+            ImplementedIncidentProvider::Opsgenie(p) => p.acknowledge(routing_key, x, admin).await,
+        }
+    }
+
+    async fn resolve(&self, routing_key: &str, x: &OutageUpdate) -> Result<()> {
+        match &self {
+            ImplementedIncidentProvider::PagerDuty(p) => p.resolve(routing_key, x).await,
+            ImplementedIncidentProvider::Opsgenie(p) => p.resolve(routing_key, x).await,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IncidentConfig {
+    pub provider: String,
+    pub api_url: Option<String>,
+    // PagerDuty integration key or Opsgenie API key used for endpoints without their own.
+    pub default_routing_key: Option<String>,
+}
+
+impl IncidentConfig {
+    // Incident channel is enabled only when INCIDENT_PROVIDER is set to pagerduty or opsgenie.
+    pub fn from_env() -> Option<IncidentConfig> {
+        let provider = env::var("INCIDENT_PROVIDER").ok()?;
+        Some(IncidentConfig {
+            provider,
+            api_url: env::var("INCIDENT_API_URL").ok(),
+            default_routing_key: read_secret("INCIDENT_ROUTING_KEY"),
+        })
+    }
+
+    pub fn create_provider(&self) -> anyhow::Result<ImplementedIncidentProvider> {
+        let api_url = |default: &str| self.api_url.clone().unwrap_or_else(|| default.to_string());
+        match self.provider.as_str() {
+            "pagerduty" => Ok(ImplementedIncidentProvider::PagerDuty(
+                PagerDutyProvider::new(api_url(PagerDutyProvider::DEFAULT_API_URL)),
+            )),
+            "opsgenie" => Ok(ImplementedIncidentProvider::Opsgenie(
+                OpsgenieProvider::new(api_url(OpsgenieProvider::DEFAULT_API_URL)),
+            )),
+            provider => Err(anyhow::anyhow!(
+                "unknown incident provider {}, expected pagerduty or opsgenie",
+                provider
+            )),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct IncidentNotificationSender {
+    provider: ImplementedIncidentProvider,
+    default_routing_key: Option<String>,
+}
+
+impl IncidentNotificationSender {
+    pub fn new(
+        provider: ImplementedIncidentProvider,
+        default_routing_key: Option<String>,
+    ) -> IncidentNotificationSender {
+        IncidentNotificationSender {
+            provider,
+            default_routing_key,
+        }
+    }
+
+    fn routing_key<'a>(&'a self, endpoint_routing_key: &'a Option<String>) -> Option<&'a str> {
+        endpoint_routing_key
+            .as_deref()
+            .or(self.default_routing_key.as_deref())
+    }
+}
+
+#[async_trait::async_trait]
+impl NotificationSender for IncidentNotificationSender {
+    async fn send_notification(&self, x: NotificationData) -> Vec<DeliveryResult> {
+        let Some(routing_key) = self.routing_key(&x.incident_routing_key) else {
+            return vec![];
+        };
+        log::info!(
+            "Triggering incident {} of outage {}",
+            dedup_key(x.outage_id),
+            x.outage_id
+        );
+        // Routing key is a secret, the incident is identified by its dedup key instead.
+        let target = dedup_key(x.outage_id);
+        vec![match self.provider.trigger(routing_key, &x).await {
+            Ok(()) => {
+                log::info!("Incident triggered successfully");
+                DeliveryResult::delivered(Channel::Incident, target)
+            }
+            Err(e) => {
+                log::error!("Failed to trigger incident: {}", e);
+                DeliveryResult::failed(Channel::Incident, target, e)
+            }
+        }]
+    }
+
+    async fn send_outage_update(&self, x: OutageUpdate) {
+        let Some(routing_key) = self.routing_key(&x.incident_routing_key) else {
+            return;
+        };
+        let result = match &x.event {
+            OutageEvent::Acknowledged(admin) => {
+                self.provider.acknowledge(routing_key, &x, admin).await
+            }
+            OutageEvent::Resolved => self.provider.resolve(routing_key, &x).await,
+        };
+        match result {
+            Ok(()) => log::info!(
+                "Incident of outage {} updated with {:?}",
+                x.outage_id,
+                x.event
+            ),
+            Err(e) => log::error!("Failed to update incident of outage {}: {}", x.outage_id, e),
+        }
+    }
+
+    fn channels(&self, x: &NotificationData) -> Vec<Channel> {
+        match self.routing_key(&x.incident_routing_key) {
+            Some(_) => vec![Channel::Incident],
+            None => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{
+        extract::State,
+        http::{HeaderMap, StatusCode, Uri},
+        Json, Router,
+    };
+
+    use super::*;
+    use crate::{ack_link_listener::AckLinks, notification_service::DeliveryStatus};
+
+    // Request received by the stub: path with query, authorization header and body.
+    type Request = (String, Option<String>, Value);
+
+    // Serves a stub of the provider's API on a free port, returns its url and the requests it received.
+    async fn serve(status: StatusCode) -> (String, Arc<Mutex<Vec<Request>>>) {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let app = Router::new()
+            .fallback(
+                move |State(requests): State<Arc<Mutex<Vec<Request>>>>,
+                      uri: Uri,
+                      headers: HeaderMap,
+                      Json(body): Json<Value>| async move {
+                    let authorization = headers
+                        .get("authorization")
+                        .map(|h| h.to_str().unwrap().to_string());
+                    requests
+                        .lock()
+                        .unwrap()
+                        .push((uri.to_string(), authorization, body));
+                    status
+                },
+            )
+            .with_state(requests.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (api_url, requests)
+    }
+
+    fn sender(provider: ImplementedIncidentProvider) -> IncidentNotificationSender {
+        IncidentNotificationSender::new(provider, Some("default-key".to_string()))
+    }
+
+    fn notification() -> NotificationData {
+        let mut x = NotificationData::example("admin", 1);
+        x.ack_links = Some(AckLinks::new("https://irio.example.com", "token"));
+        x
+    }
+
+    fn update(x: &NotificationData, event: OutageEvent) -> OutageUpdate {
+        OutageUpdate {
+            outage_id: x.outage_id,
+            endpoint: x.endpoint,
+            http_address: x.http_address.clone(),
+            outage_start: None,
+            chat_channel: None,
+            incident_routing_key: None,
+            failure_reason: None,
+            event,
+        }
+    }
+
+    #[tokio::test]
+    async fn pagerduty_incident_is_triggered() {
+        let (api_url, requests) = serve(StatusCode::ACCEPTED).await;
+        let x = notification();
+
+        let results = sender(ImplementedIncidentProvider::PagerDuty(
+            PagerDutyProvider::new(api_url),
+        ))
+        .send_notification(x.clone())
+        .await;
+
+        let dedup_key = format!("irio-{}", x.outage_id);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].target, dedup_key);
+        assert_eq!(results[0].status, DeliveryStatus::Delivered);
+        let requests = requests.lock().unwrap();
+        let (path, _, body) = &requests[0];
+        assert_eq!(path, "/v2/enqueue");
+        assert_eq!(body["routing_key"], "default-key");
+        assert_eq!(body["event_action"], "trigger");
+        assert_eq!(body["dedup_key"], dedup_key);
+        assert_eq!(
+            body["payload"]["summary"],
+            format!("{} is down", x.http_address)
+        );
+        assert_eq!(body["payload"]["severity"], "critical");
+        assert_eq!(
+            body["payload"]["custom_details"]["idempotency_key"],
+            x.idempotency_key(Channel::Incident).to_string()
+        );
+        assert_eq!(
+            body["links"][0]["href"],
+            "https://irio.example.com/ack?token=token"
+        );
+    }
+
+    #[tokio::test]
+    async fn pagerduty_incident_is_acknowledged_and_resolved() {
+        let (api_url, requests) = serve(StatusCode::ACCEPTED).await;
+        let sender = sender(ImplementedIncidentProvider::PagerDuty(
+            PagerDutyProvider::new(api_url),
+        ));
+        let x = notification();
+
+        sender
+            .send_outage_update(update(&x, OutageEvent::Acknowledged("admin".to_string())))
+            .await;
+        sender
+            .send_outage_update(update(&x, OutageEvent::Resolved))
+            .await;
+
+        let dedup_key = format!("irio-{}", x.outage_id);
+        let requests = requests.lock().unwrap();
+        let actions: Vec<_> = requests
+            .iter()
+            .map(|(path, _, body)| {
+                assert_eq!(path, "/v2/enqueue");
+                assert_eq!(body["dedup_key"], dedup_key);
+                body["event_action"].as_str().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(actions, vec!["acknowledge", "resolve"]);
+    }
+
+    #[tokio::test]
+    async fn opsgenie_alert_is_created() {
+        let (api_url, requests) = serve(StatusCode::ACCEPTED).await;
+        let mut x = notification();
+        x.incident_routing_key = Some("team-key".to_string());
+
+        let results = sender(ImplementedIncidentProvider::Opsgenie(
+            OpsgenieProvider::new(api_url),
+        ))
+        .send_notification(x.clone())
+        .await;
+
+        assert_eq!(results[0].status, DeliveryStatus::Delivered);
+        let requests = requests.lock().unwrap();
+        let (path, authorization, body) = &requests[0];
+        assert_eq!(path, "/v2/alerts");
+        assert_eq!(authorization.as_deref(), Some("GenieKey team-key"));
+        assert_eq!(body["alias"], format!("irio-{}", x.outage_id));
+        assert_eq!(body["message"], format!("{} is down", x.http_address));
+        assert_eq!(body["priority"], "P1");
+        assert_eq!(
+            body["details"]["acknowledge_url"],
+            "https://irio.example.com/ack?token=token"
+        );
+    }
+
+    #[tokio::test]
+    async fn opsgenie_alert_is_acknowledged_and_closed() {
+        let (api_url, requests) = serve(StatusCode::ACCEPTED).await;
+        let sender = sender(ImplementedIncidentProvider::Opsgenie(
+            OpsgenieProvider::new(api_url),
+        ));
+        let x = notification();
+
+        sender
+            .send_outage_update(update(&x, OutageEvent::Acknowledged("admin".to_string())))
+            .await;
+        sender
+            .send_outage_update(update(&x, OutageEvent::Resolved))
+            .await;
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(
+            requests[0].0,
+            format!(
+                "/v2/alerts/irio-{}/acknowledge?identifierType=alias",
+                x.outage_id
+            )
+        );
+        assert_eq!(requests[0].2["note"], "Acknowledged by admin in irio");
+        assert_eq!(
+            requests[1].0,
+            format!("/v2/alerts/irio-{}/close?identifierType=alias", x.outage_id)
+        );
+        assert_eq!(requests[1].1.as_deref(), Some("GenieKey default-key"));
+    }
+
+    #[tokio::test]
+    async fn rejected_trigger_fails_delivery() {
+        let (api_url, _) = serve(StatusCode::BAD_REQUEST).await;
+
+        let results = sender(ImplementedIncidentProvider::PagerDuty(
+            PagerDutyProvider::new(api_url),
+        ))
+        .send_notification(notification())
+        .await;
+
+        assert!(matches!(results[0].status, DeliveryStatus::Failed(_)));
+    }
+
+    #[tokio::test]
+    async fn nothing_is_sent_without_routing_key() {
+        let (api_url, requests) = serve(StatusCode::ACCEPTED).await;
+        let sender = IncidentNotificationSender::new(
+            ImplementedIncidentProvider::PagerDuty(PagerDutyProvider::new(api_url)),
+            None,
+        );
+
+        let results = sender.send_notification(notification()).await;
+
+        assert!(results.is_empty());
+        assert!(requests.lock().unwrap().is_empty());
+        assert!(sender.channels(&notification()).is_empty());
+    }
+}
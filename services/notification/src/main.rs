@@ -1,9 +1,23 @@
+mod ack_link_listener;
+mod ack_token;
+mod chat_sender;
+mod clock;
+mod config;
 mod db;
 mod db_executor;
 mod domain;
+mod error;
+mod incident_sender;
+#[cfg(test)]
+mod memory_executor;
+mod metrics;
 mod notification_sender;
 mod notification_service;
-use clap::{arg, command, Parser};
+mod sms_sender;
+mod tcp_sender;
+mod templates;
+mod webhook_sender;
+use clap::Parser;
 use log::LevelFilter;
 use std::{env, io::Write, time::Duration};
 
@@ -0,0 +1,857 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use uuid::Uuid;
+
+use crate::{
+    clock::Clock,
+    domain::{
+        Admin, AdminId, ContactId, ContactMethod, EndpointData, EndpointId, EndpointStatus,
+        MyDuration, MyTime, NotificationGroupMember, NotificationRule, OutageId, OutboxEntry,
+        QuietHours, Severity,
+    },
+    error::{NotificationError, Result},
+    notification_service::{DBQueryExecutor, DeliveryResult},
+};
+
+// Columns of endpoint_data that are not part of EndpointData.
+#[derive(Debug, Clone)]
+struct EndpointRow {
+    data: EndpointData,
+    is_removed: bool,
+    last_ping_time: Option<MyTime>,
+    ntf_muted_until: Option<MyTime>,
+    ntf_is_resolve_pending: bool,
+    ntf_resolve_claimed_timestamp: Option<MyTime>,
+    ntf_last_notification_timestamp: Option<MyTime>,
+    ntf_responded_by: Option<AdminId>,
+    ntf_responded_timestamp: Option<MyTime>,
+}
+
+impl EndpointRow {
+    fn status(&self) -> EndpointStatus {
+        EndpointStatus {
+            endpoint_id: self.data.endpoint_id,
+            http_address: self.data.http_address.clone(),
+            is_down: self.data.is_down,
+            outage_id: self.data.outage_id,
+            last_ping_time: self.last_ping_time,
+            ntf_first_responded: self.data.ntf_first_responded,
+            ntf_muted_until: self.ntf_muted_until,
+        }
+    }
+
+    fn release_claim(&mut self) {
+        self.data.ntf_is_being_handled = false;
+        self.data.ntf_is_being_handled_timestamp = None;
+        self.data.ntf_is_being_handled_service_id = None;
+    }
+
+    fn is_outage(&self, endpoint_id: EndpointId, outage_id: OutageId) -> bool {
+        self.data.endpoint_id == endpoint_id && self.data.outage_id == Some(outage_id)
+    }
+
+    fn is_waiting_for_first_response(&self) -> bool {
+        self.data.is_down
+            && self.data.ntf_is_first_notification_sent
+            && !self.data.ntf_is_second_notification_sent
+            && !self.data.ntf_first_responded
+    }
+}
+
+// Columns the service only writes are kept to mirror the tables.
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+struct SmsDelivery {
+    endpoint_id: EndpointId,
+    outage_id: OutageId,
+    admin_id: AdminId,
+    status: String,
+    error_code: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct TelegramMessage {
+    chat_id: ContactId,
+    message_id: i32,
+    outage_id: OutageId,
+    ack_token: String,
+}
+
+#[derive(Debug, Clone)]
+struct NotificationGroup {
+    admin_id: AdminId,
+    sent_timestamp: MyTime,
+    members: Vec<NotificationGroupMember>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+struct OutboxRow {
+    entry: OutboxEntry,
+    is_completed: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct NotificationLogEntry {
+    pub outage_id: OutageId,
+    pub endpoint_id: EndpointId,
+    pub admin_id: AdminId,
+    pub escalation_level: i32,
+    pub result: DeliveryResult,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeadLetter {
+    pub endpoint_id: EndpointId,
+    pub outage_id: Option<OutageId>,
+    pub reason: String,
+}
+
+#[derive(Default)]
+struct Tables {
+    endpoints: BTreeMap<EndpointId, EndpointRow>,
+    admins: BTreeMap<AdminId, Admin>,
+    contact_methods: Vec<ContactMethod>,
+    notification_rules: Vec<NotificationRule>,
+    quiet_hours: Vec<QuietHours>,
+    sms_deliveries: HashMap<String, SmsDelivery>,
+    chat_threads: HashMap<(OutageId, String), String>,
+    telegram_messages: Vec<TelegramMessage>,
+    notification_log: Vec<NotificationLogEntry>,
+    notification_groups: HashMap<Uuid, NotificationGroup>,
+    outbox: HashMap<Uuid, OutboxRow>,
+    dead_letters: Vec<DeadLetter>,
+}
+
+/*
+    DBQueryExecutor keeping the tables in memory, with the semantics of the postgres queries of MyDBQueryExecutor.
+    Time is read from the given clock instead of CURRENT_TIMESTAMP.
+    Instances created by `instance` share the tables, like service instances sharing a database.
+*/
+#[derive(Clone)]
+pub struct InMemoryDBQueryExecutor<C> {
+    tables: Arc<Mutex<Tables>>,
+    clock: C,
+    secs_wait_when_handled: u32,
+    n_endpoints_to_select: u32,
+    service_id: Uuid,
+    group_wait_secs: u32,
+    reminder_interval_secs: Option<u32>,
+}
+
+// Postgres intervals count a month as 30 days.
+fn to_duration(interval: &MyDuration) -> chrono::Duration {
+    chrono::Duration::days(i64::from(interval.months) * 30 + i64::from(interval.days))
+        + chrono::Duration::microseconds(interval.microseconds)
+}
+
+impl<C: Clock + Clone> InMemoryDBQueryExecutor<C> {
+    pub fn new(
+        clock: C,
+        secs_wait_when_handled: u32,
+        n_endpoints_to_select: u32,
+        service_id: Uuid,
+    ) -> InMemoryDBQueryExecutor<C> {
+        InMemoryDBQueryExecutor {
+            tables: Arc::new(Mutex::new(Tables::default())),
+            clock,
+            secs_wait_when_handled,
+            n_endpoints_to_select,
+            service_id,
+            group_wait_secs: 0,
+            reminder_interval_secs: None,
+        }
+    }
+
+    pub fn with_group_wait(mut self, group_wait_secs: u32) -> InMemoryDBQueryExecutor<C> {
+        self.group_wait_secs = group_wait_secs;
+        self
+    }
+
+    pub fn with_reminder_interval(
+        mut self,
+        reminder_interval_secs: Option<u32>,
+    ) -> InMemoryDBQueryExecutor<C> {
+        self.reminder_interval_secs = reminder_interval_secs;
+        self
+    }
+
+    // Executor of another service instance working on the same tables.
+    pub fn instance(&self, service_id: Uuid) -> InMemoryDBQueryExecutor<C> {
+        InMemoryDBQueryExecutor {
+            service_id,
+            ..self.clone()
+        }
+    }
+
+    pub fn insert_admin(&self, admin: Admin) {
+        self.tables().admins.insert(admin.admin_id.clone(), admin);
+    }
+
+    pub fn remove_admin(&self, admin_id: &AdminId) {
+        self.tables().admins.remove(admin_id);
+    }
+
+    pub fn insert_quiet_hours(&self, quiet_hours: QuietHours) {
+        self.tables().quiet_hours.push(quiet_hours);
+    }
+
+    pub fn insert_contact_method(&self, contact_method: ContactMethod) {
+        self.tables().contact_methods.push(contact_method);
+    }
+
+    pub fn insert_endpoint(&self, data: EndpointData) {
+        let row = EndpointRow {
+            data,
+            is_removed: false,
+            last_ping_time: None,
+            ntf_muted_until: None,
+            ntf_is_resolve_pending: false,
+            ntf_resolve_claimed_timestamp: None,
+            ntf_last_notification_timestamp: None,
+            ntf_responded_by: None,
+            ntf_responded_timestamp: None,
+        };
+        self.tables().endpoints.insert(row.data.endpoint_id, row);
+    }
+
+    pub fn endpoint(&self, endpoint_id: EndpointId) -> Option<EndpointData> {
+        self.tables()
+            .endpoints
+            .get(&endpoint_id)
+            .map(|row| row.data.clone())
+    }
+
+    pub fn responded_by(&self, endpoint_id: EndpointId) -> Option<AdminId> {
+        self.tables()
+            .endpoints
+            .get(&endpoint_id)
+            .and_then(|row| row.ntf_responded_by.clone())
+    }
+
+    // What the healthcheck does when the endpoint responds again.
+    pub fn set_endpoint_up(&self, endpoint_id: EndpointId) {
+        if let Some(row) = self.tables().endpoints.get_mut(&endpoint_id) {
+            row.data.is_down = false;
+        }
+    }
+
+    pub fn notification_log(&self) -> Vec<NotificationLogEntry> {
+        self.tables().notification_log.clone()
+    }
+
+    // Whether the delivery is completed, None if it is not in the outbox.
+    pub fn is_delivery_completed(&self, idempotency_key: Uuid) -> Option<bool> {
+        self.tables()
+            .outbox
+            .get(&idempotency_key)
+            .map(|row| row.is_completed)
+    }
+
+    pub fn dead_letters(&self) -> Vec<DeadLetter> {
+        self.tables().dead_letters.clone()
+    }
+
+    fn tables(&self) -> MutexGuard<'_, Tables> {
+        self.tables.lock().unwrap()
+    }
+
+    fn should_row_be_handled(&self, row: &EndpointRow, now: MyTime) -> bool {
+        let x = &row.data;
+        let is_not_handled = !x.ntf_is_being_handled
+            || x.ntf_is_being_handled_timestamp.is_some_and(|t| {
+                t + chrono::Duration::seconds(self.secs_wait_when_handled.into()) < now
+            });
+        let is_not_muted = row.ntf_muted_until.is_none_or(|t| t < now);
+        let first_needs_to_be_sent = !x.ntf_is_first_notification_sent
+            && x.outage_start_timestamp
+                .is_none_or(|t| t + chrono::Duration::seconds(self.group_wait_secs.into()) < now);
+        let second_needs_to_be_sent = !x.ntf_is_second_notification_sent
+            && x.ntf_first_notification_sent_timestamp
+                .is_some_and(|t| t + to_duration(&x.conf_allowed_response_duration) < now);
+        let reminder_is_due = x.ntf_is_second_notification_sent
+            && self
+                .reminder_interval_secs
+                .zip(row.ntf_last_notification_timestamp)
+                .is_some_and(|(secs, t)| t + chrono::Duration::seconds(secs.into()) < now);
+        !row.is_removed
+            && x.is_down
+            && is_not_handled
+            && is_not_muted
+            && !x.ntf_first_responded
+            && (first_needs_to_be_sent || second_needs_to_be_sent || reminder_is_due)
+    }
+
+    // Whether the claim on the row is still the one this instance took.
+    fn holds_claim(&self, row: &EndpointRow, claim_generation: i64) -> bool {
+        row.data.ntf_is_being_handled_service_id == Some(self.service_id)
+            && row.data.ntf_claim_generation == claim_generation
+    }
+
+    // Applies the update to the given outage, returns whether the outage matched the condition.
+    fn update_outage(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        condition: impl Fn(&EndpointRow) -> bool,
+        update: impl FnOnce(&mut EndpointRow),
+    ) -> bool {
+        let mut tables = self.tables();
+        match tables.endpoints.get_mut(&endpoint_id) {
+            Some(row) if row.is_outage(endpoint_id, outage_id) && condition(row) => {
+                update(row);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<C: Clock + Clone> DBQueryExecutor for InMemoryDBQueryExecutor<C> {
+    async fn get_endpoints_to_process(&self) -> Result<Vec<EndpointData>> {
+        let now = self.clock.now();
+        let mut tables = self.tables();
+        let Tables {
+            endpoints,
+            dead_letters,
+            ..
+        } = &mut *tables;
+        let is_dead_lettered = |row: &EndpointRow| {
+            dead_letters
+                .iter()
+                .any(|d| d.endpoint_id == row.data.endpoint_id && d.outage_id == row.data.outage_id)
+        };
+        let mut rows: Vec<&mut EndpointRow> = endpoints
+            .values_mut()
+            .filter(|row| self.should_row_be_handled(row, now) && !is_dead_lettered(row))
+            .collect();
+        // Outages without a start time come first, like NULLS FIRST.
+        rows.sort_by_key(|row| (row.data.outage_start_timestamp, row.data.endpoint_id));
+        rows.truncate(self.n_endpoints_to_select as usize);
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                row.data.ntf_is_being_handled = true;
+                row.data.ntf_is_being_handled_timestamp = Some(now);
+                row.data.ntf_is_being_handled_service_id = Some(self.service_id);
+                row.data.ntf_claim_generation += 1;
+                row.data.clone()
+            })
+            .collect())
+    }
+
+    async fn mark_first_notification_sent(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        claim_generation: i64,
+    ) -> Result<bool> {
+        let now = self.clock.now();
+        Ok(self.update_outage(
+            endpoint_id,
+            outage_id,
+            |row| self.holds_claim(row, claim_generation),
+            |row| {
+                row.release_claim();
+                row.data.ntf_is_first_notification_sent = true;
+                row.data.ntf_first_notification_sent_timestamp = Some(now);
+                row.ntf_is_resolve_pending = true;
+            },
+        ))
+    }
+
+    async fn mark_second_notification_sent(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        claim_generation: i64,
+    ) -> Result<bool> {
+        let now = self.clock.now();
+        Ok(self.update_outage(
+            endpoint_id,
+            outage_id,
+            |row| self.holds_claim(row, claim_generation),
+            |row| {
+                row.release_claim();
+                row.data.ntf_is_second_notification_sent = true;
+                row.ntf_last_notification_timestamp = Some(now);
+            },
+        ))
+    }
+
+    async fn mark_reminder_sent(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        claim_generation: i64,
+    ) -> Result<bool> {
+        let now = self.clock.now();
+        Ok(self.update_outage(
+            endpoint_id,
+            outage_id,
+            |row| self.holds_claim(row, claim_generation),
+            |row| {
+                row.release_claim();
+                row.data.ntf_reminders_sent += 1;
+                row.ntf_last_notification_timestamp = Some(now);
+            },
+        ))
+    }
+
+    async fn mark_endpoint_responded(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        admin_id: AdminId,
+    ) -> Result<bool> {
+        let now = self.clock.now();
+        Ok(self.update_outage(
+            endpoint_id,
+            outage_id,
+            |row| !row.data.ntf_first_responded,
+            |row| {
+                row.data.ntf_first_responded = true;
+                row.ntf_responded_by = Some(admin_id);
+                row.ntf_responded_timestamp = Some(now);
+            },
+        ))
+    }
+
+    async fn get_admin_data(&self, admin_id: AdminId) -> Result<Admin> {
+        self.tables()
+            .admins
+            .get(&admin_id)
+            .cloned()
+            .ok_or(NotificationError::AdminNotFound(admin_id))
+    }
+
+    async fn get_admin_by_telegram_contact_id(
+        &self,
+        telegram_contact_id: ContactId,
+    ) -> Result<Option<Admin>> {
+        let tables = self.tables();
+        Ok(tables
+            .admins
+            .values()
+            .find(|admin| {
+                admin.telegram_contact_id == telegram_contact_id
+                    || tables.contact_methods.iter().any(|m| {
+                        m.admin_id == admin.admin_id
+                            && m.channel == "telegram"
+                            && m.address == telegram_contact_id
+                    })
+            })
+            .cloned())
+    }
+
+    async fn get_current_outages(&self) -> Result<Vec<EndpointStatus>> {
+        Ok(self
+            .tables()
+            .endpoints
+            .values()
+            .filter(|row| row.data.is_down && !row.is_removed)
+            .map(EndpointRow::status)
+            .collect())
+    }
+
+    async fn get_endpoint_status(&self, endpoint: &str) -> Result<Option<EndpointStatus>> {
+        Ok(self
+            .tables()
+            .endpoints
+            .values()
+            .find(|row| {
+                !row.is_removed
+                    && (row.data.endpoint_id.to_string() == endpoint
+                        || row.data.http_address == endpoint)
+            })
+            .map(EndpointRow::status))
+    }
+
+    async fn get_endpoint_by_outage(&self, outage_id: OutageId) -> Result<Option<EndpointData>> {
+        Ok(self
+            .tables()
+            .endpoints
+            .values()
+            .find(|row| !row.is_removed && row.data.outage_id == Some(outage_id))
+            .map(|row| row.data.clone()))
+    }
+
+    async fn mute_endpoint(&self, endpoint_id: EndpointId, duration: MyDuration) -> Result<bool> {
+        let until = self.clock.now() + to_duration(&duration);
+        match self.tables().endpoints.get_mut(&endpoint_id) {
+            Some(row) if !row.is_removed => {
+                row.ntf_muted_until = Some(until);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn escalate_outage(&self, endpoint_id: EndpointId, outage_id: OutageId) -> Result<bool> {
+        let now = self.clock.now();
+        Ok(self.update_outage(
+            endpoint_id,
+            outage_id,
+            EndpointRow::is_waiting_for_first_response,
+            |row| {
+                row.data.ntf_first_notification_sent_timestamp =
+                    Some(now - to_duration(&row.data.conf_allowed_response_duration));
+            },
+        ))
+    }
+
+    async fn snooze_outage(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        duration: MyDuration,
+    ) -> Result<bool> {
+        let until = self.clock.now() + to_duration(&duration);
+        Ok(self.update_outage(
+            endpoint_id,
+            outage_id,
+            EndpointRow::is_waiting_for_first_response,
+            |row| row.data.ntf_first_notification_sent_timestamp = Some(until),
+        ))
+    }
+
+    async fn record_sms_sent(
+        &self,
+        provider_message_id: String,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        admin_id: AdminId,
+    ) -> Result<()> {
+        self.tables().sms_deliveries.insert(
+            provider_message_id,
+            SmsDelivery {
+                endpoint_id,
+                outage_id,
+                admin_id,
+                status: "sent".to_string(),
+                error_code: None,
+            },
+        );
+        Ok(())
+    }
+
+    async fn update_sms_delivery_status(
+        &self,
+        provider_message_id: String,
+        status: String,
+        error_code: Option<String>,
+    ) -> Result<bool> {
+        match self.tables().sms_deliveries.get_mut(&provider_message_id) {
+            Some(delivery) => {
+                delivery.status = status;
+                delivery.error_code = error_code;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn get_resolved_outages_to_process(&self) -> Result<Vec<EndpointData>> {
+        let now = self.clock.now();
+        let claim_duration = chrono::Duration::seconds(self.secs_wait_when_handled.into());
+        let mut tables = self.tables();
+        let mut ret = vec![];
+        for row in tables.endpoints.values_mut() {
+            let is_not_claimed = row
+                .ntf_resolve_claimed_timestamp
+                .is_none_or(|t| t < now - claim_duration);
+            if !row.data.is_down && row.ntf_is_resolve_pending && is_not_claimed {
+                row.ntf_resolve_claimed_timestamp = Some(now);
+                ret.push(row.data.clone());
+            }
+        }
+        Ok(ret)
+    }
+
+    async fn mark_resolution_reported(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+    ) -> Result<()> {
+        if let Some(row) = self.tables().endpoints.get_mut(&endpoint_id) {
+            if row.data.outage_id == Some(outage_id) {
+                row.ntf_is_resolve_pending = false;
+                row.ntf_resolve_claimed_timestamp = None;
+            }
+        }
+        Ok(())
+    }
+
+    async fn record_chat_thread(
+        &self,
+        outage_id: OutageId,
+        channel: String,
+        thread_id: String,
+    ) -> Result<()> {
+        self.tables()
+            .chat_threads
+            .entry((outage_id, channel))
+            .or_insert(thread_id);
+        Ok(())
+    }
+
+    async fn get_chat_thread(
+        &self,
+        outage_id: OutageId,
+        channel: String,
+    ) -> Result<Option<String>> {
+        Ok(self
+            .tables()
+            .chat_threads
+            .get(&(outage_id, channel))
+            .cloned())
+    }
+
+    async fn record_telegram_message(
+        &self,
+        chat_id: ContactId,
+        message_id: i32,
+        outage_id: OutageId,
+        ack_token: String,
+    ) -> Result<()> {
+        let mut tables = self.tables();
+        if !tables
+            .telegram_messages
+            .iter()
+            .any(|m| m.chat_id == chat_id && m.message_id == message_id)
+        {
+            tables.telegram_messages.push(TelegramMessage {
+                chat_id,
+                message_id,
+                outage_id,
+                ack_token,
+            });
+        }
+        Ok(())
+    }
+
+    async fn get_telegram_message_token(
+        &self,
+        chat_id: ContactId,
+        message_id: i32,
+    ) -> Result<Option<String>> {
+        Ok(self
+            .tables()
+            .telegram_messages
+            .iter()
+            .find(|m| m.chat_id == chat_id && m.message_id == message_id)
+            .map(|m| m.ack_token.clone()))
+    }
+
+    async fn get_telegram_messages(&self, outage_id: OutageId) -> Result<Vec<(ContactId, i32)>> {
+        Ok(self
+            .tables()
+            .telegram_messages
+            .iter()
+            .filter(|m| m.outage_id == outage_id)
+            .map(|m| (m.chat_id.clone(), m.message_id))
+            .collect())
+    }
+
+    async fn record_notification_attempts(
+        &self,
+        outage_id: OutageId,
+        endpoint_id: EndpointId,
+        admin_id: AdminId,
+        escalation_level: i32,
+        results: &[DeliveryResult],
+    ) -> Result<()> {
+        self.tables()
+            .notification_log
+            .extend(results.iter().map(|result| NotificationLogEntry {
+                outage_id,
+                endpoint_id,
+                admin_id: admin_id.clone(),
+                escalation_level,
+                result: result.clone(),
+            }));
+        Ok(())
+    }
+
+    async fn get_contact_methods(&self, admin_id: AdminId) -> Result<Vec<ContactMethod>> {
+        let mut ret: Vec<ContactMethod> = self
+            .tables()
+            .contact_methods
+            .iter()
+            .filter(|m| m.admin_id == admin_id)
+            .cloned()
+            .collect();
+        ret.sort_by_key(|m| m.rank);
+        Ok(ret)
+    }
+
+    async fn get_notification_rule(
+        &self,
+        admin_id: AdminId,
+        severity: Severity,
+    ) -> Result<Option<NotificationRule>> {
+        Ok(self
+            .tables()
+            .notification_rules
+            .iter()
+            .find(|r| r.admin_id == admin_id && r.severity == severity.as_str())
+            .cloned())
+    }
+
+    async fn get_quiet_hours(&self, admin_id: AdminId) -> Result<Vec<QuietHours>> {
+        Ok(self
+            .tables()
+            .quiet_hours
+            .iter()
+            .filter(|q| q.admin_id == admin_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn record_notification_group(
+        &self,
+        group_id: Uuid,
+        admin_id: AdminId,
+        members: &[NotificationGroupMember],
+    ) -> Result<()> {
+        let sent_timestamp = self.clock.now();
+        self.tables().notification_groups.insert(
+            group_id,
+            NotificationGroup {
+                admin_id,
+                sent_timestamp,
+                members: members.to_vec(),
+            },
+        );
+        Ok(())
+    }
+
+    async fn delete_notification_group(&self, group_id: Uuid) -> Result<()> {
+        self.tables().notification_groups.remove(&group_id);
+        Ok(())
+    }
+
+    async fn get_notification_group_members(
+        &self,
+        group_id: Uuid,
+    ) -> Result<Vec<NotificationGroupMember>> {
+        Ok(self
+            .tables()
+            .notification_groups
+            .get(&group_id)
+            .map(|g| g.members.clone())
+            .unwrap_or_default())
+    }
+
+    async fn get_notification_times(
+        &self,
+        admin_id: AdminId,
+        period: MyDuration,
+    ) -> Result<Vec<MyTime>> {
+        let since = self.clock.now() - to_duration(&period);
+        let mut ret: Vec<MyTime> = self
+            .tables()
+            .notification_groups
+            .values()
+            .filter(|g| g.admin_id == admin_id && g.sent_timestamp > since)
+            .map(|g| g.sent_timestamp)
+            .collect();
+        ret.sort();
+        Ok(ret)
+    }
+
+    async fn defer_notification(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        claim_generation: i64,
+        until: MyTime,
+    ) -> Result<bool> {
+        let claimed_at = until - chrono::Duration::seconds(self.secs_wait_when_handled.into());
+        Ok(self.update_outage(
+            endpoint_id,
+            outage_id,
+            |row| self.holds_claim(row, claim_generation),
+            |row| row.data.ntf_is_being_handled_timestamp = Some(claimed_at),
+        ))
+    }
+
+    async fn skip_first_notification(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        claim_generation: i64,
+    ) -> Result<bool> {
+        let now = self.clock.now();
+        Ok(self.update_outage(
+            endpoint_id,
+            outage_id,
+            |row| {
+                self.holds_claim(row, claim_generation) && !row.data.ntf_is_first_notification_sent
+            },
+            |row| {
+                row.release_claim();
+                row.data.ntf_is_first_notification_sent = true;
+                row.data.ntf_first_notification_sent_timestamp =
+                    Some(now - to_duration(&row.data.conf_allowed_response_duration));
+                row.ntf_is_resolve_pending = true;
+            },
+        ))
+    }
+
+    async fn record_pending_deliveries(&self, entries: &[OutboxEntry]) -> Result<Vec<Uuid>> {
+        let mut tables = self.tables();
+        for entry in entries {
+            tables
+                .outbox
+                .entry(entry.idempotency_key)
+                .or_insert_with(|| OutboxRow {
+                    entry: entry.clone(),
+                    is_completed: false,
+                });
+        }
+        Ok(entries
+            .iter()
+            .map(|e| e.idempotency_key)
+            .filter(|key| tables.outbox[key].is_completed)
+            .collect())
+    }
+
+    async fn complete_deliveries(&self, keys: &[Uuid]) -> Result<()> {
+        let mut tables = self.tables();
+        for key in keys {
+            if let Some(row) = tables.outbox.get_mut(key) {
+                row.is_completed = true;
+            }
+        }
+        Ok(())
+    }
+
+    async fn dead_letter_outage(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: Option<OutageId>,
+        claim_generation: i64,
+        reason: &str,
+    ) -> Result<bool> {
+        let mut tables = self.tables();
+        match tables.endpoints.get_mut(&endpoint_id) {
+            Some(row)
+                if row.data.outage_id == outage_id && self.holds_claim(row, claim_generation) =>
+            {
+                row.release_claim();
+            }
+            _ => return Ok(false),
+        }
+        tables.dead_letters.push(DeadLetter {
+            endpoint_id,
+            outage_id,
+            reason: reason.to_string(),
+        });
+        Ok(true)
+    }
+}
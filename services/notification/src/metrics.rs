@@ -0,0 +1,82 @@
+use std::{
+    env,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use axum::{routing::get, Router};
+
+// Counters of the service, served in the Prometheus text format. Clones share the counters.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    stale_claim_writes: Arc<AtomicU64>,
+    dead_lettered_outages: Arc<AtomicU64>,
+}
+
+impl Metrics {
+    // Instance tried to write under a claim that was taken over by another instance.
+    pub fn record_stale_claim_write(&self) {
+        self.stale_claim_writes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stale_claim_writes(&self) -> u64 {
+        self.stale_claim_writes.load(Ordering::Relaxed)
+    }
+
+    // Outage could not be notified because of its configuration.
+    pub fn record_dead_lettered_outage(&self) {
+        self.dead_lettered_outages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dead_lettered_outages(&self) -> u64 {
+        self.dead_lettered_outages.load(Ordering::Relaxed)
+    }
+
+    fn render(&self) -> String {
+        format!(
+            "# HELP notification_stale_claim_writes_total Writes rejected because the claim on the outage was taken over by another instance.\n\
+            # TYPE notification_stale_claim_writes_total counter\n\
+            notification_stale_claim_writes_total {}\n\
+            # HELP notification_dead_lettered_outages_total Outages that could not be notified because of their configuration.\n\
+            # TYPE notification_dead_lettered_outages_total counter\n\
+            notification_dead_lettered_outages_total {}\n",
+            self.stale_claim_writes(),
+            self.dead_lettered_outages()
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MetricsConfig {
+    pub listen_address: String,
+}
+
+impl MetricsConfig {
+    // Metrics are served only when METRICS_LISTEN_ADDRESS is set.
+    pub fn from_env() -> Option<MetricsConfig> {
+        Some(MetricsConfig {
+            listen_address: env::var("METRICS_LISTEN_ADDRESS").ok()?,
+        })
+    }
+}
+
+pub async fn serve_metrics(config: MetricsConfig, metrics: Metrics) {
+    let app = Router::new().route("/metrics", get(move || async move { metrics.render() }));
+    let listener = match tokio::net::TcpListener::bind(&config.listen_address).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!(
+                "Failed to bind metrics listener to {}: {}",
+                config.listen_address,
+                e
+            );
+            return;
+        }
+    };
+    log::info!("Serving metrics on {}", config.listen_address);
+    if let Err(e) = axum::serve(listener, app).await {
+        log::error!("Metrics listener failed: {}", e);
+    }
+}
@@ -1,273 +1,1026 @@
 #![allow(dead_code)]
 
-use std::{env, ops::DerefMut, sync::Arc};
+use std::{env, str::FromStr, time::Duration};
 
 use teloxide::{
     dispatching::Dispatcher,
     payloads::SendMessageSetters,
     requests::{Request, Requester, ResponseResult},
-    types::{Message, ParseMode, Update, UserId},
-    Bot,
+    types::{
+        CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup, Message, MessageId, Update,
+        User, UserId,
+    },
+    utils::command::BotCommands,
+    Bot, RequestError,
 };
 
 use lettre::{
-    message::header::ContentType, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
-    AsyncTransport, Tokio1Executor,
+    message::{Mailbox, MultiPart},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
 };
 
 use teloxide::prelude::*;
-use tokio::{
-    io::AsyncWriteExt,
-    net::TcpStream,
-    sync::{mpsc::Sender, Mutex},
-};
+use tokio::sync::mpsc::Sender;
 use uuid::Uuid;
 
 use crate::{
-    domain::EndpointId,
-    notification_service::{NotificationData, NotificationSender, ResponseData, ResponseListener},
+    ack_token::AckTokenSigner,
+    config::read_secret,
+    db_executor::MyDBQueryExecutor,
+    domain::{Admin, EndpointStatus, MyDuration, MyTime},
+    error::{NotificationError, Result},
+    notification_service::{
+        Channel, DBQueryExecutor, DeliveryResult, NotificationData, NotificationSender,
+        OutageUpdate, ResponseAction, ResponseData, ResponseListener,
+    },
+    templates::{TemplateContext, Templates},
 };
 
-#[derive(Debug, Clone)]
-pub struct TelegramNotificationSender {
+#[derive(Clone)]
+pub struct TelegramNotificationSender<D = MyDBQueryExecutor> {
     bot: Bot,
+    db_executor: D,
+    templates: Templates,
 }
 
-#[derive(Debug, Clone)]
-pub struct TelegramNotificationResponseListener {
+#[derive(Clone)]
+pub struct TelegramNotificationResponseListener<D = MyDBQueryExecutor> {
     bot: Bot,
     sender: Sender<ResponseData>,
+    db_executor: D,
+    token_signer: AckTokenSigner,
 }
 
-impl TelegramNotificationSender {
-    fn prepare_telegram_msg(msg: String) -> String {
-        msg.replace("_", "\\_")
-            .replace("*", "\\*")
-            .replace("[", "\\[")
-            .replace("`", "\\`")
-            .replace("=", "\\=")
-            .replace("-", "\\-")
-            .replace(".", "\\.")
-    }
+#[derive(BotCommands, Clone, Debug)]
+#[command(rename_rule = "lowercase", description = "Supported commands:")]
+enum TelegramCommand {
+    #[command(description = "show this message.")]
+    Help,
+    #[command(description = "list current outages.")]
+    Outages,
+    #[command(description = "show the latest probe state of an endpoint: /status <endpoint>")]
+    Status(String),
+    #[command(description = "acknowledge an outage: /ack <outage>")]
+    Ack(String),
+    #[command(
+        description = "suppress alerts about an endpoint: /mute <endpoint> <duration>, e.g. /mute 3 2h",
+        parse_with = "split"
+    )]
+    Mute { endpoint: String, duration: String },
+}
 
-    pub fn new(b: Bot) -> TelegramNotificationSender {
-        TelegramNotificationSender { bot: b }
+impl<D: DBQueryExecutor + Clone> TelegramNotificationSender<D> {
+    pub fn new(b: Bot, db_executor: D, templates: Templates) -> TelegramNotificationSender<D> {
+        TelegramNotificationSender {
+            bot: b,
+            db_executor,
+            templates,
+        }
     }
 }
 
 #[async_trait::async_trait]
-impl NotificationSender for TelegramNotificationSender {
-    async fn send_notification(&self, x: NotificationData) {
-        let text = format!(
-            "endpoint={};outage={};is_first={};admin={};http_address={}",
-            x.endpoint, x.outage_id, x.is_first, x.admin, x.http_address
-        );
+impl<D: DBQueryExecutor + Clone> NotificationSender for TelegramNotificationSender<D> {
+    async fn send_notification(&self, x: NotificationData) -> Vec<DeliveryResult> {
+        let text = match self
+            .templates
+            .render(Templates::TELEGRAM, &TemplateContext::notification(&x))
+        {
+            Ok(text) => text,
+            Err(e) => {
+                log::error!("Failed to render telegram message: {}", e);
+                return vec![DeliveryResult::failed(
+                    Channel::Telegram,
+                    x.telegram_contact_id,
+                    e,
+                )];
+            }
+        };
         log::info!(
             "Attempting to concat {} by chat {}",
             x.admin,
             x.telegram_contact_id
         );
-        let user_id = UserId(x.telegram_contact_id.parse().unwrap());
-        match self
-            .bot
-            .send_message(user_id, Self::prepare_telegram_msg(text))
-            .parse_mode(ParseMode::MarkdownV2)
-            .send()
+        let user_id = match x.telegram_contact_id.parse() {
+            Ok(user_id) => UserId(user_id),
+            Err(e) => {
+                log::error!("Failed to send message: invalid chat id: {}", e);
+                return vec![DeliveryResult::failed(
+                    Channel::Telegram,
+                    x.telegram_contact_id,
+                    e,
+                )];
+            }
+        };
+        let mut request = self.bot.send_message(user_id, text);
+        // Alerts about undeliverable outages can't be acknowledged.
+        if x.undeliverable.is_none() {
+            request = request.reply_markup(InlineKeyboardMarkup::new([[
+                InlineKeyboardButton::callback(
+                    "Acknowledge",
+                    TelegramNotificationResponseListener::ACK_CALLBACK_DATA,
+                ),
+            ]]));
+        }
+        let message = match request.send().await {
+            Ok(message) => message,
+            Err(e) => {
+                log::error!("Failed to send message: {}", e);
+                let retry_after = match e {
+                    RequestError::RetryAfter(retry_after) => Some(retry_after),
+                    _ => None,
+                };
+                return vec![
+                    DeliveryResult::failed(Channel::Telegram, x.telegram_contact_id, e)
+                        .with_retry_after(retry_after),
+                ];
+            }
+        };
+        log::info!("Message sent successfully");
+        if x.undeliverable.is_some() {
+            return vec![DeliveryResult::delivered(
+                Channel::Telegram,
+                x.telegram_contact_id,
+            )];
+        }
+        if let Err(e) = self
+            .db_executor
+            .record_telegram_message(
+                message.chat.id.to_string(),
+                message.id.0,
+                x.outage_id,
+                x.ack_token,
+            )
             .await
         {
-            Ok(_) => log::info!("Message sent successfully"),
-            Err(e) => log::error!("Failed to send message: {}", e),
+            log::error!(
+                "Failed to store telegram message of outage {}: {}",
+                x.outage_id,
+                e
+            );
+        }
+        vec![DeliveryResult::delivered(
+            Channel::Telegram,
+            x.telegram_contact_id,
+        )]
+    }
+
+    // Updates are posted as replies to every notification sent about the outage.
+    async fn send_outage_update(&self, x: OutageUpdate) {
+        let messages = match self.db_executor.get_telegram_messages(x.outage_id).await {
+            Ok(messages) => messages,
+            Err(e) => {
+                log::error!(
+                    "Failed to get telegram messages of outage {}: {}",
+                    x.outage_id,
+                    e
+                );
+                return;
+            }
+        };
+        if messages.is_empty() {
+            return;
+        }
+        let text = match self
+            .templates
+            .render(Templates::TELEGRAM, &TemplateContext::update(&x))
+        {
+            Ok(text) => text,
+            Err(e) => {
+                log::error!("Failed to render telegram message: {}", e);
+                return;
+            }
+        };
+        for (chat_id, message_id) in messages {
+            let Ok(chat_id) = chat_id.parse() else {
+                continue;
+            };
+            match self
+                .bot
+                .send_message(ChatId(chat_id), text.clone())
+                .reply_to_message_id(MessageId(message_id))
+                .send()
+                .await
+            {
+                Ok(_) => log::info!("Update of outage {} sent to chat {}", x.outage_id, chat_id),
+                Err(e) => log::error!("Failed to send update of outage {}: {}", x.outage_id, e),
+            }
         }
     }
 }
 
 impl TelegramNotificationResponseListener {
-    fn parse_telegram_response(input: &str) -> Option<ResponseData> {
-        let mut endpoint = None;
-        let mut admin = None;
-        let mut is_first = None;
-        let mut outage_id = None;
-
-        log::info!("Received {} telegram response", input);
-
-        for part in input.split(';') {
-            let key_value: Vec<&str> = part.trim().split('=').collect();
-            if key_value.len() == 2 {
-                match key_value[0].trim() {
-                    "endpoint" => endpoint = Some(key_value[1].trim().to_string()),
-                    "admin" => admin = Some(key_value[1].trim().to_string()),
-                    "is_first" => is_first = Some(key_value[1].trim().to_string()),
-                    "outage" => outage_id = Some(key_value[1].trim().to_string()),
-                    _ => (), // Unknown key
-                }
-            } else {
-                () // Invalid key-value pair
+    // Callback data of the inline button attached to every notification.
+    // The token is stored next to the message the button belongs to.
+    pub const ACK_CALLBACK_DATA: &'static str = "ack";
+
+    // Accepts durations like "90s", "15m", "2h" or "1d". A bare number is treated as minutes.
+    fn parse_duration(input: &str) -> Option<Duration> {
+        let input = input.trim();
+        let (value, unit_secs) = match input.char_indices().last()? {
+            (i, 's') => (&input[..i], 1),
+            (i, 'm') => (&input[..i], 60),
+            (i, 'h') => (&input[..i], 60 * 60),
+            (i, 'd') => (&input[..i], 24 * 60 * 60),
+            _ => (input, 60),
+        };
+        let value: u64 = value.parse().ok()?;
+        if value == 0 {
+            return None;
+        }
+        value.checked_mul(unit_secs).map(Duration::from_secs)
+    }
+}
+
+impl<D: DBQueryExecutor + Clone + 'static> TelegramNotificationResponseListener<D> {
+    async fn get_message_token(db_executor: &D, msg: &Message) -> Option<String> {
+        match db_executor
+            .get_telegram_message_token(msg.chat.id.to_string(), msg.id.0)
+            .await
+        {
+            Ok(token) => token,
+            Err(e) => {
+                log::error!(
+                    "Error getting token of telegram message {} in chat {}: {}",
+                    msg.id,
+                    msg.chat.id,
+                    e
+                );
+                None
             }
         }
-        log::debug!(
-            "Response after parse {:?} {:?} {:?} {:?}",
-            endpoint,
-            admin,
-            is_first,
-            outage_id
+    }
+
+    /*
+        Response is accepted only if the notification carries a token signed by us
+        and the token was issued to the admin registered with the responding telegram account.
+    */
+    async fn parse_telegram_response(
+        token: &str,
+        user: &User,
+        token_signer: &AckTokenSigner,
+        db_executor: &D,
+    ) -> Result<ResponseData> {
+        let Some(token) = token_signer.verify(token) else {
+            return Err(NotificationError::InvalidResponse(format!(
+                "invalid token {}",
+                AckTokenSigner::fingerprint(token)
+            )));
+        };
+        log::info!(
+            "Received telegram response of admin {} to outage {}",
+            token.admin,
+            token.outage_id
         );
 
-        // Check if all required values are present
-        if let (Some(endpoint), Some(admin), Some(is_first), Some(outage_id)) =
-            (endpoint, admin, is_first, outage_id)
-        {
-            Some(ResponseData {
-                admin: admin,
-                outage_id: Uuid::parse_str(outage_id.as_str()).unwrap(),
-                endpoint: endpoint.parse::<EndpointId>().unwrap(),
-                is_first: is_first.parse().unwrap(),
-            })
-        } else {
-            None // Missing one or more required keys
+        let recipient = db_executor.get_admin_data(token.admin.clone()).await?;
+        let user_id = user.id.to_string();
+        let is_recipient = recipient.telegram_contact_id == user_id
+            || db_executor
+                .get_contact_methods(token.admin.clone())
+                .await
+                .unwrap_or_else(|e| {
+                    log::error!(
+                        "Error getting contact methods of admin {}: {}",
+                        token.admin,
+                        e
+                    );
+                    vec![]
+                })
+                .iter()
+                .any(|m| m.channel == Channel::Telegram.as_str() && m.address == user_id);
+        if !is_recipient {
+            return Err(NotificationError::InvalidResponse(format!(
+                "notification was sent to admin {}",
+                token.admin
+            )));
         }
+
+        Ok(ResponseData {
+            admin: token.admin,
+            outage_id: token.outage_id,
+            endpoint: token.endpoint,
+            is_first: token.is_first,
+            action: ResponseAction::Acknowledge,
+            group: token.group,
+        })
     }
 
-    pub fn new(b: Bot, sender: Sender<ResponseData>) -> TelegramNotificationResponseListener {
+    pub fn new(
+        b: Bot,
+        sender: Sender<ResponseData>,
+        db_executor: D,
+        token_signer: AckTokenSigner,
+    ) -> TelegramNotificationResponseListener<D> {
         TelegramNotificationResponseListener {
             bot: b,
-            sender: sender,
+            sender,
+            db_executor,
+            token_signer,
+        }
+    }
+
+    // Looks up the admin registered with the given telegram account.
+    async fn get_admin_for_user(db_executor: &D, user: &User) -> Option<Admin> {
+        match db_executor
+            .get_admin_by_telegram_contact_id(user.id.to_string())
+            .await
+        {
+            Ok(Some(admin)) => Some(admin),
+            Ok(None) => {
+                log::warn!(
+                    "Rejected update from unregistered telegram user {}",
+                    user.id
+                );
+                None
+            }
+            Err(e) => {
+                log::error!(
+                    "Error looking up admin for telegram user {}: {}",
+                    user.id,
+                    e
+                );
+                None
+            }
         }
     }
 
+    // Same as get_admin_for_user, but unregistered users are told so.
+    async fn get_sending_admin(
+        msg: &Message,
+        bot: &Bot,
+        db_executor: &D,
+    ) -> ResponseResult<Option<Admin>> {
+        let Some(user) = msg.from() else {
+            return Ok(None);
+        };
+        let admin = Self::get_admin_for_user(db_executor, user).await;
+        if admin.is_none() {
+            bot.send_message(msg.chat.id, "You are not registered as an admin.")
+                .await?;
+        }
+        Ok(admin)
+    }
+
     async fn handle_reply(
         msg: Message,
         _bot: Bot,
         s_s: Sender<ResponseData>,
+        db_executor: D,
+        token_signer: AckTokenSigner,
+    ) -> ResponseResult<()> {
+        let (Some(replied), Some(user)) = (msg.reply_to_message(), msg.from()) else {
+            return Ok(());
+        };
+        let Some(token) = Self::get_message_token(&db_executor, replied).await else {
+            return Ok(());
+        };
+        let forwarded =
+            match Self::parse_telegram_response(&token, user, &token_signer, &db_executor).await {
+                Ok(response) => Self::forward(&s_s, response).await,
+                Err(e) => Err(e),
+            };
+        if let Err(e) = forwarded {
+            log::warn!("Rejected response of telegram user {}: {}", user.id, e);
+        }
+        Ok(())
+    }
+
+    async fn forward(s_s: &Sender<ResponseData>, response: ResponseData) -> Result<()> {
+        s_s.send(response)
+            .await
+            .map_err(|_| NotificationError::ResponseChannelClosed)
+    }
+
+    async fn handle_callback(
+        q: CallbackQuery,
+        bot: Bot,
+        s_s: Sender<ResponseData>,
+        db_executor: D,
+        token_signer: AckTokenSigner,
+    ) -> ResponseResult<()> {
+        let token = match &q.message {
+            Some(msg) => Self::get_message_token(&db_executor, msg).await,
+            None => None,
+        };
+        let answer = match (q.data.as_deref(), token) {
+            (Some(TelegramNotificationResponseListener::ACK_CALLBACK_DATA), Some(token)) => {
+                match Self::parse_telegram_response(&token, &q.from, &token_signer, &db_executor)
+                    .await
+                {
+                    Ok(response) => match Self::forward(&s_s, response).await {
+                        Ok(()) => "Acknowledged.",
+                        Err(e) => {
+                            log::error!("Failed to forward telegram response: {}", e);
+                            "Failed to process the acknowledgement, try again later."
+                        }
+                    },
+                    Err(e) => {
+                        log::warn!("Rejected response of telegram user {}: {}", q.from.id, e);
+                        "This notification cannot be acknowledged by you."
+                    }
+                }
+            }
+            _ => "Unknown action.",
+        };
+        bot.answer_callback_query(q.id).text(answer).await?;
+        Ok(())
+    }
+
+    fn format_time(time: Option<MyTime>) -> String {
+        time.map(|t| format!("{} UTC", t.format("%Y-%m-%d %H:%M:%S")))
+            .unwrap_or_else(|| "never".to_string())
+    }
+
+    fn format_endpoint_status(status: &EndpointStatus) -> String {
+        let mut text = format!(
+            "endpoint {} ({})\nstate: {}\nlast ping: {}",
+            status.endpoint_id,
+            status.http_address,
+            if status.is_down { "DOWN" } else { "UP" },
+            Self::format_time(status.last_ping_time)
+        );
+        if status.is_down {
+            if let Some(outage_id) = status.outage_id {
+                text.push_str(&format!(
+                    "\noutage: {}\nacknowledged: {}",
+                    outage_id, status.ntf_first_responded
+                ));
+            }
+        }
+        if let Some(muted_until) = status.ntf_muted_until {
+            if muted_until > chrono::Utc::now().naive_utc() {
+                text.push_str(&format!(
+                    "\nmuted until: {}",
+                    Self::format_time(Some(muted_until))
+                ));
+            }
+        }
+        text
+    }
+
+    async fn execute_command(
+        db_executor: &D,
+        s_s: &Sender<ResponseData>,
+        admin: Admin,
+        cmd: TelegramCommand,
+    ) -> Result<String> {
+        match cmd {
+            TelegramCommand::Help => Ok(TelegramCommand::descriptions().to_string()),
+            TelegramCommand::Outages => {
+                let outages = db_executor.get_current_outages().await?;
+                if outages.is_empty() {
+                    return Ok("No current outages.".to_string());
+                }
+                Ok(outages
+                    .iter()
+                    .map(Self::format_endpoint_status)
+                    .collect::<Vec<_>>()
+                    .join("\n\n"))
+            }
+            TelegramCommand::Status(endpoint) => {
+                match db_executor.get_endpoint_status(endpoint.trim()).await? {
+                    Some(status) => Ok(Self::format_endpoint_status(&status)),
+                    None => Ok(format!("Unknown endpoint {}.", endpoint.trim())),
+                }
+            }
+            TelegramCommand::Ack(outage) => {
+                let Ok(outage_id) = Uuid::parse_str(outage.trim()) else {
+                    return Ok("Usage: /ack <outage>".to_string());
+                };
+                let endpoint_data = match db_executor.get_endpoint_by_outage(outage_id).await? {
+                    Some(endpoint_data) if endpoint_data.is_down => endpoint_data,
+                    _ => return Ok(format!("Outage {} is not in progress.", outage_id)),
+                };
+                if !endpoint_data.is_on_escalation_path(&admin.admin_id) {
+                    log::warn!(
+                        "Rejected /ack of outage {} by admin {} who is not on the escalation path",
+                        outage_id,
+                        admin.admin_id
+                    );
+                    return Ok(format!(
+                        "You are not an admin of endpoint {}.",
+                        endpoint_data.endpoint_id
+                    ));
+                }
+                Self::forward(
+                    s_s,
+                    ResponseData {
+                        admin: admin.admin_id,
+                        outage_id,
+                        endpoint: endpoint_data.endpoint_id,
+                        is_first: !endpoint_data.ntf_is_second_notification_sent,
+                        action: ResponseAction::Acknowledge,
+                        group: None,
+                    },
+                )
+                .await?;
+                Ok(format!(
+                    "Outage {} of {} acknowledged.",
+                    outage_id, endpoint_data.http_address
+                ))
+            }
+            TelegramCommand::Mute { endpoint, duration } => {
+                let Some(duration) =
+                    TelegramNotificationResponseListener::parse_duration(&duration)
+                else {
+                    return Ok("Usage: /mute <endpoint> <duration>, e.g. /mute 3 2h".to_string());
+                };
+                let Some(status) = db_executor.get_endpoint_status(&endpoint).await? else {
+                    return Ok(format!("Unknown endpoint {}.", endpoint));
+                };
+                let interval = MyDuration::try_from(duration)
+                    .map_err(|e| NotificationError::InvalidResponse(e.to_string()))?;
+                if !db_executor
+                    .mute_endpoint(status.endpoint_id, interval)
+                    .await?
+                {
+                    return Ok(format!("Unknown endpoint {}.", endpoint));
+                }
+                log::info!(
+                    "Admin {} muted endpoint {} for {:?}",
+                    admin.admin_id,
+                    status.endpoint_id,
+                    duration
+                );
+                Ok(format!(
+                    "Alerts about endpoint {} ({}) muted for {:?}.",
+                    status.endpoint_id, status.http_address, duration
+                ))
+            }
+        }
+    }
+
+    async fn handle_command(
+        msg: Message,
+        bot: Bot,
+        s_s: Sender<ResponseData>,
+        db_executor: D,
+        cmd: TelegramCommand,
     ) -> ResponseResult<()> {
-        if let Some(response) = msg
-            .reply_to_message()
-            .and_then(|msg| msg.text())
-            .and_then(|x| Self::parse_telegram_response(x))
+        let Some(user) = msg.from() else {
+            return Ok(());
+        };
+        let admin = match db_executor
+            .get_admin_by_telegram_contact_id(user.id.to_string())
+            .await
         {
-            s_s.send(response).await.unwrap();
+            Ok(Some(admin)) => admin,
+            Ok(None) => {
+                log::warn!(
+                    "Rejected command {:?} from unregistered telegram user {}",
+                    cmd,
+                    user.id
+                );
+                bot.send_message(msg.chat.id, "You are not registered as an admin.")
+                    .await?;
+                return Ok(());
+            }
+            Err(e) => {
+                log::error!(
+                    "Error looking up admin for telegram user {}: {}",
+                    user.id,
+                    e
+                );
+                return Ok(());
+            }
+        };
+        log::info!("Admin {} issued command {:?}", admin.admin_id, cmd);
+
+        let reply = match Self::execute_command(&db_executor, &s_s, admin, cmd).await {
+            Ok(reply) => reply,
+            Err(e) => {
+                log::error!("Error executing telegram command: {}", e);
+                "Failed to execute the command, try again later.".to_string()
+            }
         };
+        bot.send_message(msg.chat.id, reply).await?;
         Ok(())
     }
 }
 
 #[async_trait::async_trait]
-impl ResponseListener for TelegramNotificationResponseListener {
-    async fn listen_for_responses(&self) {
-        let handler = Update::filter_message().endpoint(
-            |bot: Bot, sender: Sender<ResponseData>, msg: Message| async move {
-                Self::handle_reply(msg, bot, sender).await
+impl<D: DBQueryExecutor + Clone + 'static> ResponseListener
+    for TelegramNotificationResponseListener<D>
+{
+    async fn listen_for_responses(&self) -> Result<()> {
+        if let Err(e) = self
+            .bot
+            .set_my_commands(TelegramCommand::bot_commands())
+            .await
+        {
+            log::error!("Failed to register telegram commands: {}", e);
+        }
+
+        let message_handler = Update::filter_message()
+            .branch(
+                dptree::entry()
+                    .filter_command::<TelegramCommand>()
+                    .endpoint(
+                        |bot: Bot,
+                         sender: Sender<ResponseData>,
+                         db_executor: D,
+                         msg: Message,
+                         cmd: TelegramCommand| async move {
+                            Self::handle_command(msg, bot, sender, db_executor, cmd).await
+                        },
+                    ),
+            )
+            .branch(dptree::endpoint(
+                |bot: Bot,
+                 sender: Sender<ResponseData>,
+                 db_executor: D,
+                 token_signer: AckTokenSigner,
+                 msg: Message| async move {
+                    Self::handle_reply(msg, bot, sender, db_executor, token_signer).await
+                },
+            ));
+        let callback_handler = Update::filter_callback_query().endpoint(
+            |bot: Bot,
+             sender: Sender<ResponseData>,
+             db_executor: D,
+             token_signer: AckTokenSigner,
+             q: CallbackQuery| async move {
+                Self::handle_callback(q, bot, sender, db_executor, token_signer).await
             },
         );
+        let handler = dptree::entry()
+            .branch(message_handler)
+            .branch(callback_handler);
 
         Dispatcher::builder(self.bot.clone(), handler)
-            .dependencies(dptree::deps![self.sender.clone()])
+            .dependencies(dptree::deps![
+                self.sender.clone(),
+                self.db_executor.clone(),
+                self.token_signer.clone()
+            ])
             .build()
             .dispatch()
             .await;
+        Ok(())
     }
 }
 
 pub fn create_telegram_bot() -> Bot {
-    Bot::new(env::var("TELEGRAM_BOT_ID").unwrap())
+    Bot::new(env::var("TELEGRAM_BOT_ID").expect("TELEGRAM_BOT_ID must be set"))
 }
 
-pub fn create_telegram_notification_sender_and_receiver(
+pub fn create_telegram_notification_sender_and_receiver<D: DBQueryExecutor + Clone + 'static>(
     s: Sender<ResponseData>,
+    db_executor: D,
+    token_signer: AckTokenSigner,
+    templates: Templates,
 ) -> (
-    TelegramNotificationSender,
-    TelegramNotificationResponseListener,
+    TelegramNotificationSender<D>,
+    TelegramNotificationResponseListener<D>,
 ) {
     let b = create_telegram_bot();
     (
-        TelegramNotificationSender::new(b.clone()),
-        TelegramNotificationResponseListener::new(b, s),
+        TelegramNotificationSender::new(b.clone(), db_executor.clone(), templates),
+        TelegramNotificationResponseListener::new(b, s, db_executor, token_signer),
     )
 }
 
-#[derive(Clone)]
-pub struct EmailNotificationSender {
-    mailer: AsyncSmtpTransport<Tokio1Executor>,
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SmtpTlsMode {
+    // Plaintext connection, meant for local SMTP sinks only.
+    None,
+    StartTls,
+    Tls,
 }
 
-impl EmailNotificationSender {
-    pub fn new() -> EmailNotificationSender {
-        let creds = Credentials::new("irioi742@gmail.com".to_owned(), "irio2024".to_owned());
-
-        let mailer: AsyncSmtpTransport<Tokio1Executor> =
-            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay("smtp.gmail.com")
-                .unwrap()
-                .credentials(creds)
-                .build();
-        EmailNotificationSender { mailer: mailer }
+impl SmtpTlsMode {
+    fn default_port(&self) -> u16 {
+        match self {
+            SmtpTlsMode::None => 25,
+            SmtpTlsMode::StartTls => 587,
+            SmtpTlsMode::Tls => 465,
+        }
     }
 }
 
-#[async_trait::async_trait]
-impl NotificationSender for EmailNotificationSender {
-    async fn send_notification(&self, x: NotificationData) {
-        let text = format!(
-            "endpoint={};outage={};is_first={};admin={};http_address={}",
-            x.endpoint, x.outage_id, x.is_first, x.admin, x.http_address
-        );
-        log::info!("Attempting to concat {} by mail {}", x.admin, x.email);
+impl FromStr for SmtpTlsMode {
+    type Err = anyhow::Error;
 
-        let email = lettre::Message::builder()
-            .from("Irio <irioirio80@gmail.com>".parse().unwrap())
-            .to(format!("<{}>", x.email).parse().unwrap())
-            .subject(format!("Outage: {}", x.http_address))
-            .header(ContentType::TEXT_PLAIN)
-            .body(text)
-            .unwrap();
-
-        let result = self.mailer.send(email).await;
-        match result {
-            Ok(_) => log::info!("Message sent successfully"),
-            Err(e) => log::error!("Failed to send message: {}", e),
+    fn from_str(s: &str) -> anyhow::Result<SmtpTlsMode> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(SmtpTlsMode::None),
+            "starttls" => Ok(SmtpTlsMode::StartTls),
+            "tls" => Ok(SmtpTlsMode::Tls),
+            _ => Err(anyhow::anyhow!(
+                "unknown SMTP TLS mode {}, expected one of none, starttls, tls",
+                s
+            )),
         }
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct EmailConfig {
+    pub host: String,
+    pub port: u16,
+    pub tls: SmtpTlsMode,
+    // Username and password, either both or none of them are set.
+    pub credentials: Option<(String, String)>,
+    pub from: Mailbox,
+}
+
+impl EmailConfig {
+    /*
+        Email channel is enabled only when SMTP_HOST is set.
+        SMTP_PASSWORD can be provided as a file with SMTP_PASSWORD_FILE.
+    */
+    pub fn from_env() -> anyhow::Result<Option<EmailConfig>> {
+        let Ok(host) = env::var("SMTP_HOST") else {
+            return Ok(None);
+        };
+        let tls: SmtpTlsMode = env::var("SMTP_TLS")
+            .unwrap_or_else(|_| "starttls".to_string())
+            .parse()?;
+        let port = match env::var("SMTP_PORT") {
+            Ok(port) => port.parse()?,
+            Err(_) => tls.default_port(),
+        };
+        let from = env::var("SMTP_FROM")
+            .map_err(|_| anyhow::anyhow!("SMTP_FROM must be set when SMTP_HOST is set"))?
+            .parse()?;
+        let credentials = match (read_secret("SMTP_USERNAME"), read_secret("SMTP_PASSWORD")) {
+            (Some(username), Some(password)) => Some((username, password)),
+            (None, None) => None,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "SMTP_USERNAME and SMTP_PASSWORD must be set together"
+                ))
+            }
+        };
+        Ok(Some(EmailConfig {
+            host,
+            port,
+            tls,
+            credentials,
+            from,
+        }))
+    }
+}
+
+// Generic over the transport, so that tests can capture mails instead of sending them.
 #[derive(Clone)]
-pub struct TcpNotificationSender {
-    tcp_stream: Arc<Mutex<TcpStream>>,
-    server_address: String,
+pub struct EmailNotificationSender<M = AsyncSmtpTransport<Tokio1Executor>> {
+    mailer: M,
+    from: Mailbox,
+    templates: Templates,
 }
 
-impl TcpNotificationSender {
-    pub async fn new(server_address: String) -> anyhow::Result<TcpNotificationSender> {
-        let res = TcpStream::connect(server_address.clone()).await?;
+impl EmailNotificationSender {
+    pub fn new(
+        config: EmailConfig,
+        templates: Templates,
+    ) -> anyhow::Result<EmailNotificationSender> {
+        let builder = match config.tls {
+            SmtpTlsMode::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host)
+            }
+            SmtpTlsMode::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+            }
+            SmtpTlsMode::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+        }
+        .port(config.port);
+        let builder = match config.credentials {
+            Some((username, password)) => builder.credentials(Credentials::new(username, password)),
+            None => builder,
+        };
+        log::info!(
+            "Sending emails through {}:{} ({:?}) as {}",
+            config.host,
+            config.port,
+            config.tls,
+            config.from
+        );
 
-        Ok(TcpNotificationSender {
-            tcp_stream: Arc::new(Mutex::new(res)),
-            server_address: server_address,
+        Ok(EmailNotificationSender {
+            mailer: builder.build(),
+            from: config.from,
+            templates,
         })
     }
 }
 
+impl<M> EmailNotificationSender<M> {
+    // Message-ID of the first mail about the outage, follow-ups reference it so that mail clients thread them.
+    fn outage_root_id(&self, x: &NotificationData) -> String {
+        format!("<outage-{}@{}>", x.outage_id, self.from.email.domain())
+    }
+
+    // Message-ID of a follow-up is the idempotency key of the delivery, so that a resent mail is recognized as the same message.
+    fn message_id(&self, x: &NotificationData) -> String {
+        if x.escalation_level() == 1 && x.reminder.is_none() {
+            return self.outage_root_id(x);
+        }
+        format!(
+            "<{}@{}>",
+            x.idempotency_key(Channel::Email),
+            self.from.email.domain()
+        )
+    }
+
+    fn build_email(&self, x: &NotificationData) -> anyhow::Result<lettre::Message> {
+        let to: Mailbox = x.email.parse()?;
+        let message_id = self.message_id(x);
+        let root_id = self.outage_root_id(x);
+        let context = TemplateContext::notification(x);
+        let mut builder = lettre::Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(self.templates.render(Templates::EMAIL_SUBJECT, &context)?)
+            .message_id(Some(message_id.clone()));
+        if message_id != root_id {
+            builder = builder.in_reply_to(root_id.clone()).references(root_id);
+        }
+        Ok(builder.multipart(MultiPart::alternative_plain_html(
+            self.templates.render(Templates::EMAIL_TEXT, &context)?,
+            self.templates.render(Templates::EMAIL_HTML, &context)?,
+        ))?)
+    }
+}
+
 #[async_trait::async_trait]
-impl NotificationSender for TcpNotificationSender {
-    async fn send_notification(&self, x: NotificationData) {
-        let text = format!(
-            "endpoint={};outage={};is_first={};admin={};http_address={}",
-            x.endpoint, x.outage_id, x.is_first, x.admin, x.http_address
+impl<M> NotificationSender for EmailNotificationSender<M>
+where
+    M: AsyncTransport + Clone + Send + Sync,
+    M::Error: std::fmt::Display,
+{
+    async fn send_notification(&self, x: NotificationData) -> Vec<DeliveryResult> {
+        log::info!("Attempting to concat {} by mail {}", x.admin, x.email);
+
+        let email = match self.build_email(&x) {
+            Ok(email) => email,
+            Err(e) => {
+                log::error!("Failed to build email to {}: {}", x.email, e);
+                return vec![DeliveryResult::failed(Channel::Email, x.email, e)];
+            }
+        };
+        let result = match self.mailer.send(email).await {
+            Ok(_) => {
+                log::info!("Message sent successfully");
+                DeliveryResult::delivered(Channel::Email, x.email)
+            }
+            Err(e) => {
+                log::error!("Failed to send message: {}", e);
+                DeliveryResult::failed(Channel::Email, x.email, e)
+            }
+        };
+        vec![result]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lettre::transport::stub::AsyncStubTransport;
+
+    use super::*;
+    use crate::notification_service::DeliveryStatus;
+
+    fn email_sender() -> EmailNotificationSender<AsyncStubTransport> {
+        EmailNotificationSender {
+            mailer: AsyncStubTransport::new_ok(),
+            from: "irio <alerts@irio.example.com>".parse().unwrap(),
+            templates: Templates::built_in(),
+        }
+    }
+
+    // Value of the header in the raw mail.
+    fn header<'a>(mail: &'a str, name: &str) -> Option<&'a str> {
+        mail.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(": "))
+    }
+
+    fn parse_duration(input: &str) -> Option<Duration> {
+        TelegramNotificationResponseListener::parse_duration(input)
+    }
+
+    #[test]
+    fn durations_are_parsed_with_units() {
+        assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("15m"), Some(Duration::from_secs(15 * 60)));
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(
+            parse_duration("1d"),
+            Some(Duration::from_secs(24 * 60 * 60))
+        );
+        assert_eq!(parse_duration(" 5 "), Some(Duration::from_secs(5 * 60)));
+    }
+
+    #[test]
+    fn zero_duration_is_rejected() {
+        assert_eq!(parse_duration("0"), None);
+        assert_eq!(parse_duration("0h"), None);
+    }
+
+    #[test]
+    fn overflowing_duration_is_rejected() {
+        assert_eq!(parse_duration(&format!("{}d", u64::MAX)), None);
+        assert_eq!(parse_duration("99999999999999999999s"), None);
+    }
+
+    #[test]
+    fn garbage_duration_is_rejected() {
+        for input in ["", "m", "abc", "1x", "-5m", "1.5h", "2 h"] {
+            assert_eq!(parse_duration(input), None, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn commands_are_parsed() {
+        assert!(matches!(
+            TelegramCommand::parse("/outages", "irio_bot"),
+            Ok(TelegramCommand::Outages)
+        ));
+        assert!(matches!(
+            TelegramCommand::parse("/status@irio_bot http://example.com", "irio_bot"),
+            Ok(TelegramCommand::Status(endpoint)) if endpoint == "http://example.com"
+        ));
+        assert!(matches!(
+            TelegramCommand::parse("/mute 3 2h", "irio_bot"),
+            Ok(TelegramCommand::Mute { endpoint, duration }) if endpoint == "3" && duration == "2h"
+        ));
+    }
+
+    #[test]
+    fn malformed_commands_are_rejected() {
+        assert!(TelegramCommand::parse("/mute 3", "irio_bot").is_err());
+        assert!(TelegramCommand::parse("/unknown", "irio_bot").is_err());
+        assert!(TelegramCommand::parse("/outages@other_bot", "irio_bot").is_err());
+    }
+
+    #[tokio::test]
+    async fn email_is_rendered_and_threaded_per_outage() {
+        let sender = email_sender();
+        let first = NotificationData::example("primary", 1);
+        let mut second = NotificationData::example("secondary", 1);
+        second.outage_id = first.outage_id;
+        second.is_first = false;
+
+        let results = sender.send_notification(first.clone()).await;
+        assert_eq!(results[0].status, DeliveryStatus::Delivered);
+        sender.send_notification(second.clone()).await;
+
+        let mails = sender.mailer.messages().await;
+        assert_eq!(mails.len(), 2);
+        let (envelope, first_mail) = &mails[0];
+        assert_eq!(envelope.to()[0].to_string(), "primary@example.com");
+        assert_eq!(
+            header(first_mail, "Subject"),
+            Some("[irio] Outage of http://endpoint-1.example.com (level 1)")
+        );
+        assert!(first_mail.contains("Endpoint http://endpoint-1.example.com (id 1) is down."));
+        assert!(first_mail.contains("Admin: primary"));
+        assert_eq!(header(first_mail, "References"), None);
+        assert_eq!(header(first_mail, "In-Reply-To"), None);
+
+        let (_, second_mail) = &mails[1];
+        assert_eq!(
+            header(second_mail, "Subject"),
+            Some("[irio] Outage of http://endpoint-1.example.com (level 2)")
+        );
+    }
+
+    #[tokio::test]
+    async fn follow_ups_reply_to_first_email_about_outage() {
+        let sender = email_sender();
+        let first = NotificationData::example("primary", 1);
+        let mut escalation = NotificationData::example("secondary", 1);
+        escalation.outage_id = first.outage_id;
+        escalation.is_first = false;
+        let mut reminder = escalation.clone();
+        reminder.reminder = Some(1);
+
+        for x in [&first, &escalation, &reminder] {
+            sender.send_notification(x.clone()).await;
+        }
+
+        let mails = sender.mailer.messages().await;
+        let message_ids: Vec<_> = mails
+            .iter()
+            .map(|(_, mail)| header(mail, "Message-ID").unwrap())
+            .collect();
+        let root = message_ids[0];
+        assert_eq!(
+            root,
+            format!("<outage-{}@irio.example.com>", first.outage_id)
+        );
+        assert_eq!(
+            message_ids[1],
+            format!(
+                "<{}@irio.example.com>",
+                escalation.idempotency_key(Channel::Email)
+            )
         );
-        log::info!("Attempting to concat {} by tcp {}", x.admin, x.email);
-
-        // let email = lettre::Message::builder()
-        //     .from("Irio <irioirio80@gmail.com>".parse().unwrap())
-        //     .to(format!("<{}>", x.email).parse().unwrap())
-        //     .subject(format!("Outage: {}", x.http_address))
-        //     .header(ContentType::TEXT_PLAIN)
-        //     .body(text)
-        //     .unwrap();
-
-        let mut guard = self.tcp_stream.lock().await;
-        let tcp_stream = guard.deref_mut();
-        let result = tcp_stream.write_all(text.as_bytes()).await;
-        drop(guard);
-        match result {
-            Ok(_) => log::info!("Message sent successfully"),
-            Err(e) => log::error!("Failed to send message: {}", e),
+        assert_ne!(message_ids[1], message_ids[2]);
+        for (_, mail) in &mails[1..] {
+            assert_eq!(header(mail, "In-Reply-To"), Some(root));
+            assert_eq!(header(mail, "References"), Some(root));
         }
     }
 }
@@ -1,18 +1,39 @@
 #![allow(dead_code)]
 
-use std::{env, time::Duration};
+use std::{collections::HashMap, env, fmt, str::FromStr, sync::Arc, time::Duration};
 
 use crate::{
+    ack_link_listener::{AckLinkConfig, AckLinkResponseListener, AckLinks},
+    ack_token::{AckToken, AckTokenSigner},
+    chat_sender::{ChatConfig, ChatNotificationSender},
+    clock::{Clock, SystemClock},
+    db::get_postgres_connection,
     db_executor::MyDBQueryExecutor,
-    domain::{Admin, AdminId, ContactId, EndpointData, EndpointId, OutageId},
+    domain::{
+        Admin, AdminId, ContactId, ContactMethod, EndpointData, EndpointId, EndpointStatus,
+        MyDuration, MyTime, NotificationGroupMember, NotificationRule, OutageId, OutboxEntry,
+        QuietHours, QuietHoursAction, Severity,
+    },
+    error::{NotificationError, Result},
+    incident_sender::{IncidentConfig, IncidentNotificationSender},
+    metrics::{serve_metrics, Metrics, MetricsConfig},
     notification_sender::{
-        create_telegram_notification_sender_and_receiver, EmailNotificationSender,
-        TcpNotificationSender, TelegramNotificationResponseListener, TelegramNotificationSender,
+        create_telegram_notification_sender_and_receiver, EmailConfig, EmailNotificationSender,
+        TelegramNotificationResponseListener, TelegramNotificationSender,
+    },
+    sms_sender::{SmsConfig, SmsNotificationSender},
+    tcp_sender::{
+        TcpConfig, TcpListenerConfig, TcpNotificationSender, TcpResponseHandler,
+        TcpResponseListener,
     },
+    templates::Templates,
+    webhook_sender::{WebhookConfig, WebhookNotificationSender},
 };
 use ::futures::stream::FuturesUnordered;
-use anyhow::Result;
-use tokio::sync::mpsc::{channel, Receiver};
+use tokio::{
+    sync::mpsc::{channel, Receiver},
+    time::Instant,
+};
 use uuid::Uuid;
 
 #[async_trait::async_trait]
@@ -26,33 +47,588 @@ pub trait DBQueryExecutor: Send + Sync {
         3. Either
         3a) is notification sent is false
         3b) is notification sent is true BUT ntf_first_notification_send_time is too old
+        3c) second notification is sent and the last notification is older than the reminder interval
 
         Run LWT to update all the nodes if they are not handled already and are not down. Say that they are handled.
+        Every claim increments ntf_claim_generation of the endpoint.
     */
     async fn get_endpoints_to_process(&self) -> Result<Vec<EndpointData>>;
+    /*
+        Writes made under a claim are fenced by the claim generation returned with the endpoint.
+        They return false, without changing anything, if the claim was taken over by another instance.
+    */
     async fn mark_first_notification_sent(
         &self,
         endpoint_id: EndpointId,
         outage_id: OutageId,
-    ) -> Result<()>;
+        claim_generation: i64,
+    ) -> Result<bool>;
     async fn mark_second_notification_sent(
         &self,
         endpoint_id: EndpointId,
         outage_id: OutageId,
-    ) -> Result<()>;
+        claim_generation: i64,
+    ) -> Result<bool>;
+    async fn mark_reminder_sent(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        claim_generation: i64,
+    ) -> Result<bool>;
+    /*
+        Mark outage as responded, storing the admin whose response was accepted.
+        Returns false if the outage was already responded to.
+    */
     async fn mark_endpoint_responded(
         &self,
         endpoint_id: EndpointId,
         outage_id: OutageId,
-    ) -> Result<()>;
+        admin_id: AdminId,
+    ) -> Result<bool>;
 
     async fn get_admin_data(&self, admin_id: AdminId) -> Result<Admin>;
+
+    /*
+        Used by the bot commands to check that the user issuing a command is a registered admin.
+    */
+    async fn get_admin_by_telegram_contact_id(
+        &self,
+        telegram_contact_id: ContactId,
+    ) -> Result<Option<Admin>>;
+
+    async fn get_current_outages(&self) -> Result<Vec<EndpointStatus>>;
+
+    /*
+        Endpoint can be referenced either by its id or by its http address.
+    */
+    async fn get_endpoint_status(&self, endpoint: &str) -> Result<Option<EndpointStatus>>;
+
+    async fn get_endpoint_by_outage(&self, outage_id: OutageId) -> Result<Option<EndpointData>>;
+
+    /*
+        Suppress notifications about the endpoint for the given duration.
+        Returns false if there is no such endpoint.
+    */
+    async fn mute_endpoint(&self, endpoint_id: EndpointId, duration: MyDuration) -> Result<bool>;
+
+    /*
+        Make the response timeout of the first notification expire now, so that the secondary admin is notified.
+        Returns false if the outage is not waiting for a response to the first notification.
+    */
+    async fn escalate_outage(&self, endpoint_id: EndpointId, outage_id: OutageId) -> Result<bool>;
+
+    /*
+        Postpone the response timeout of the first notification by the given duration.
+        Returns false if the outage is not waiting for a response to the first notification.
+    */
+    async fn snooze_outage(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        duration: MyDuration,
+    ) -> Result<bool>;
+
+    async fn record_sms_sent(
+        &self,
+        provider_message_id: String,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        admin_id: AdminId,
+    ) -> Result<()>;
+
+    /*
+        Store delivery status reported by the SMS provider.
+        Returns false if the message was not sent by us.
+    */
+    async fn update_sms_delivery_status(
+        &self,
+        provider_message_id: String,
+        status: String,
+        error_code: Option<String>,
+    ) -> Result<bool>;
+
+    /*
+        Read all endpoints that are back up after an outage admins were notified about,
+        and claim them for secs_wait_when_handled so that other instances don't report them too.
+    */
+    async fn get_resolved_outages_to_process(&self) -> Result<Vec<EndpointData>>;
+
+    /*
+        Resolution of the outage was sent, so it isn't reported again when the claim expires.
+    */
+    async fn mark_resolution_reported(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+    ) -> Result<()>;
+
+    /*
+        Chat threads are kept per outage and channel, so that follow-ups are posted as replies.
+    */
+    async fn record_chat_thread(
+        &self,
+        outage_id: OutageId,
+        channel: String,
+        thread_id: String,
+    ) -> Result<()>;
+    async fn get_chat_thread(&self, outage_id: OutageId, channel: String)
+        -> Result<Option<String>>;
+
+    /*
+        Ack token of a sent telegram message is kept next to the message instead of in its text.
+        Replies and button presses are correlated with the outage by (chat, message).
+    */
+    async fn record_telegram_message(
+        &self,
+        chat_id: ContactId,
+        message_id: i32,
+        outage_id: OutageId,
+        ack_token: String,
+    ) -> Result<()>;
+    async fn get_telegram_message_token(
+        &self,
+        chat_id: ContactId,
+        message_id: i32,
+    ) -> Result<Option<String>>;
+    async fn get_telegram_messages(&self, outage_id: OutageId) -> Result<Vec<(ContactId, i32)>>;
+
+    /*
+        Every delivery attempt of a notification is kept in the notification log,
+        whether or not it succeeded.
+    */
+    async fn record_notification_attempts(
+        &self,
+        outage_id: OutageId,
+        endpoint_id: EndpointId,
+        admin_id: AdminId,
+        escalation_level: i32,
+        results: &[DeliveryResult],
+    ) -> Result<()>;
+
+    /*
+        Contact methods of the admin ordered by rank.
+        Admins without any are reached by the telegram id, email and phone stored with the admin.
+    */
+    async fn get_contact_methods(&self, admin_id: AdminId) -> Result<Vec<ContactMethod>>;
+    async fn get_notification_rule(
+        &self,
+        admin_id: AdminId,
+        severity: Severity,
+    ) -> Result<Option<NotificationRule>>;
+
+    async fn get_quiet_hours(&self, admin_id: AdminId) -> Result<Vec<QuietHours>>;
+
+    /*
+        Every notification sent to the contact methods of an admin is recorded as a group of the outages it covers.
+        Groups are used to expand responses to summaries and to rate limit notifications of an admin.
+    */
+    async fn record_notification_group(
+        &self,
+        group_id: Uuid,
+        admin_id: AdminId,
+        members: &[NotificationGroupMember],
+    ) -> Result<()>;
+    async fn delete_notification_group(&self, group_id: Uuid) -> Result<()>;
+    async fn get_notification_group_members(
+        &self,
+        group_id: Uuid,
+    ) -> Result<Vec<NotificationGroupMember>>;
+    // When notifications were sent to the admin within the last period, oldest first.
+    async fn get_notification_times(
+        &self,
+        admin_id: AdminId,
+        period: MyDuration,
+    ) -> Result<Vec<MyTime>>;
+    // Notification of the outage is retried once the given time passes.
+    async fn defer_notification(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        claim_generation: i64,
+        until: MyTime,
+    ) -> Result<bool>;
+
+    /*
+        Marks the first notification as sent without sending it, with the response deadline already passed,
+        so that the secondary admin is notified on the next poll.
+    */
+    async fn skip_first_notification(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: OutageId,
+        claim_generation: i64,
+    ) -> Result<bool>;
+
+    /*
+        Every delivery is recorded in the outbox as pending before it is sent and completed once it is delivered.
+        Entries already in the outbox keep their status. Returns keys of the entries completed by an earlier attempt.
+    */
+    async fn record_pending_deliveries(&self, entries: &[OutboxEntry]) -> Result<Vec<Uuid>>;
+    async fn complete_deliveries(&self, keys: &[Uuid]) -> Result<()>;
+
+    /*
+        Outage that can't be notified because of its configuration is recorded together with the reason
+        and never claimed again. Releases the claim, only while it is still held.
+    */
+    async fn dead_letter_outage(
+        &self,
+        endpoint_id: EndpointId,
+        outage_id: Option<OutageId>,
+        claim_generation: i64,
+        reason: &str,
+    ) -> Result<bool>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Telegram,
+    Email,
+    Tcp,
+    Sms,
+    Webhook,
+    Chat,
+    Incident,
+}
+
+impl Channel {
+    // Channels that reach the admin personally, through the admin's contact methods.
+    pub const CONTACT: [Channel; 3] = [Channel::Telegram, Channel::Email, Channel::Sms];
+    pub const DEFAULT_REQUIRED: [Channel; 3] = Self::CONTACT;
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Channel::Telegram => "telegram",
+            Channel::Email => "email",
+            Channel::Tcp => "tcp",
+            Channel::Sms => "sms",
+            Channel::Webhook => "webhook",
+            Channel::Chat => "chat",
+            Channel::Incident => "incident",
+        }
+    }
+}
+
+impl FromStr for Channel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Channel> {
+        match s.trim().to_lowercase().as_str() {
+            "telegram" => Ok(Channel::Telegram),
+            "email" => Ok(Channel::Email),
+            "tcp" => Ok(Channel::Tcp),
+            "sms" => Ok(Channel::Sms),
+            "webhook" => Ok(Channel::Webhook),
+            "chat" => Ok(Channel::Chat),
+            "incident" => Ok(Channel::Incident),
+            _ => Err(anyhow::anyhow!("unknown notification channel {}", s)),
+        }
+    }
+}
+
+/*
+    Key of the delivery of one escalation step of an outage to an admin by one channel, the same for every attempt.
+    Every reminder is a step of its own. It is a UUID v5 in the namespace of the outage.
+*/
+pub fn idempotency_key(
+    outage_id: OutageId,
+    escalation_level: i32,
+    reminder: Option<i32>,
+    admin: &str,
+    channel: Channel,
+) -> Uuid {
+    let mut name = format!("{}:{}:{}", escalation_level, admin, channel.as_str());
+    if let Some(reminder) = reminder {
+        name.push_str(&format!(":reminder-{}", reminder));
+    }
+    Uuid::new_v5(&outage_id, name.as_bytes())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeliveryStatus {
+    // Accepted by the recipient's server or provider.
+    Delivered,
+    // Handed over to a background connection that delivers it later, e.g. the TCP buffer.
+    Queued,
+    Failed(String),
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Queued => "queued",
+            DeliveryStatus::Failed(_) => "failed",
+        }
+    }
+
+    pub fn is_failed(&self) -> bool {
+        matches!(self, DeliveryStatus::Failed(_))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DeliveryResult {
+    pub channel: Channel,
+    // Address the notification was sent to: chat id, email, phone number, URL, ...
+    pub target: String,
+    pub status: DeliveryStatus,
+    pub attempted_at: MyTime,
+    // Delay the provider asked for before the next attempt, e.g. when rate limited.
+    pub retry_after: Option<Duration>,
+}
+
+impl DeliveryResult {
+    fn new(channel: Channel, target: impl Into<String>, status: DeliveryStatus) -> DeliveryResult {
+        DeliveryResult {
+            channel,
+            target: target.into(),
+            status,
+            attempted_at: chrono::Utc::now().naive_utc(),
+            retry_after: None,
+        }
+    }
+
+    pub fn delivered(channel: Channel, target: impl Into<String>) -> DeliveryResult {
+        Self::new(channel, target, DeliveryStatus::Delivered)
+    }
+
+    pub fn queued(channel: Channel, target: impl Into<String>) -> DeliveryResult {
+        Self::new(channel, target, DeliveryStatus::Queued)
+    }
+
+    pub fn failed(
+        channel: Channel,
+        target: impl Into<String>,
+        error: impl std::fmt::Display,
+    ) -> DeliveryResult {
+        Self::new(channel, target, DeliveryStatus::Failed(error.to_string()))
+    }
+
+    pub fn with_retry_after(mut self, retry_after: Option<Duration>) -> DeliveryResult {
+        self.retry_after = retry_after;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DeliveryConfig {
+    // Notification counts as sent only if one of these delivered it.
+    pub required_channels: Vec<Channel>,
+    // Attempts per contact method.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    // Retries and fallbacks have to finish before the claim on the endpoint expires,
+    // otherwise another instance would send the notification again.
+    pub budget: Duration,
+}
+
+// Budget fits the default claim, from_env fits it to the configured one.
+impl Default for DeliveryConfig {
+    fn default() -> DeliveryConfig {
+        DeliveryConfig {
+            required_channels: Channel::DEFAULT_REQUIRED.to_vec(),
+            max_attempts: Self::DEFAULT_MAX_ATTEMPTS,
+            initial_backoff: Duration::from_millis(Self::DEFAULT_INITIAL_BACKOFF_MS),
+            budget: Self::budget(Duration::from_secs(DEFAULT_SECS_WAIT_WHEN_HANDLED.into())),
+        }
+    }
+}
+
+impl DeliveryConfig {
+    const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+    const DEFAULT_INITIAL_BACKOFF_MS: u64 = 1000;
+    const MAX_BACKOFF: Duration = Duration::from_secs(30);
+    // Left of the claim for marking the notification as sent.
+    const CLAIM_MARGIN: Duration = Duration::from_secs(5);
+
+    pub fn parse_channels(channels: &str) -> anyhow::Result<Vec<Channel>> {
+        channels
+            .split(',')
+            .filter(|c| !c.trim().is_empty())
+            .map(Channel::from_str)
+            .collect()
+    }
+
+    fn budget(claim_duration: Duration) -> Duration {
+        claim_duration.saturating_sub(Self::CLAIM_MARGIN)
+    }
+
+    pub fn from_env(claim_duration: Duration) -> anyhow::Result<DeliveryConfig> {
+        let mut config = DeliveryConfig {
+            budget: Self::budget(claim_duration),
+            ..Default::default()
+        };
+        if let Ok(channels) = env::var("REQUIRED_CHANNELS") {
+            config.required_channels = Self::parse_channels(&channels)?;
+        }
+        if let Ok(attempts) = env::var("DELIVERY_MAX_ATTEMPTS") {
+            config.max_attempts = attempts.parse::<u32>()?.max(1);
+        }
+        if let Ok(backoff) = env::var("DELIVERY_INITIAL_BACKOFF_MS") {
+            config.initial_backoff = Duration::from_millis(backoff.parse()?);
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GroupingConfig {
+    // First notifications wait this long after the outage started, so that outages opening together are grouped.
+    pub group_wait_secs: u32,
+    // Notifications sent to one admin per period. Overflow is deferred and sent as one digest once the period allows.
+    pub rate_limit: Option<usize>,
+    pub rate_limit_period: Duration,
+}
+
+impl GroupingConfig {
+    const DEFAULT_RATE_LIMIT_PERIOD_SECS: u64 = 600;
+
+    pub fn from_env() -> anyhow::Result<GroupingConfig> {
+        let group_wait_secs = match env::var("NOTIFICATION_GROUP_WAIT_SECS") {
+            Ok(secs) => secs.parse::<u32>()?,
+            Err(_) => 0,
+        };
+        let rate_limit = match env::var("ADMIN_RATE_LIMIT") {
+            Ok(limit) => Some(limit.parse::<usize>()?.max(1)),
+            Err(_) => None,
+        };
+        let rate_limit_period = match env::var("ADMIN_RATE_LIMIT_PERIOD_SECS") {
+            Ok(secs) => Duration::from_secs(secs.parse::<u64>()?),
+            Err(_) => Duration::from_secs(Self::DEFAULT_RATE_LIMIT_PERIOD_SECS),
+        };
+        Ok(GroupingConfig {
+            group_wait_secs,
+            rate_limit,
+            rate_limit_period,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlannedContact {
+    pub channel: Channel,
+    pub address: String,
+}
+
+// Contact methods of one admin a notification is sent to.
+#[derive(Debug, Clone, Default)]
+pub struct DeliveryPlan {
+    // Contact methods of equal rank, in order of rank. Next step is used only when the previous one failed.
+    pub steps: Vec<Vec<PlannedContact>>,
+}
+
+impl DeliveryPlan {
+    /*
+        Uses the contact methods of channels allowed by the admin's rule for the severity, all of them without a rule.
+        Admins without contact methods are reached by telegram, then email, then SMS as stored with the admin.
+    */
+    pub fn new(
+        admin: &Admin,
+        methods: Vec<ContactMethod>,
+        rule: Option<NotificationRule>,
+    ) -> DeliveryPlan {
+        let methods = if methods.is_empty() {
+            [
+                ("telegram", &admin.telegram_contact_id),
+                ("email", &admin.email_address),
+                ("sms", &admin.phone_number),
+            ]
+            .into_iter()
+            .zip(1..)
+            .map(|((channel, address), rank)| ContactMethod {
+                admin_id: admin.admin_id.clone(),
+                channel: channel.to_string(),
+                address: address.clone(),
+                rank,
+            })
+            .collect()
+        } else {
+            methods
+        };
+        let allowed = rule.and_then(
+            |rule| match DeliveryConfig::parse_channels(&rule.channels) {
+                Ok(channels) => Some(channels),
+                Err(e) => {
+                    log::error!(
+                        "Ignoring notification rule of admin {} for {} outages: {}",
+                        rule.admin_id,
+                        rule.severity,
+                        e
+                    );
+                    None
+                }
+            },
+        );
+
+        let mut steps: Vec<(i32, Vec<PlannedContact>)> = vec![];
+        for method in methods {
+            let channel = match Channel::from_str(&method.channel) {
+                Ok(channel) if Channel::CONTACT.contains(&channel) => channel,
+                _ => {
+                    log::warn!(
+                        "Ignoring contact method {} of admin {}, unsupported channel {}",
+                        method.address,
+                        method.admin_id,
+                        method.channel
+                    );
+                    continue;
+                }
+            };
+            if allowed.as_ref().is_some_and(|a| !a.contains(&channel)) {
+                continue;
+            }
+            let contact = PlannedContact {
+                channel,
+                address: method.address,
+            };
+            match steps.iter_mut().find(|(rank, _)| *rank == method.rank) {
+                Some((_, step)) => step.push(contact),
+                None => steps.push((method.rank, vec![contact])),
+            }
+        }
+        steps.sort_by_key(|(rank, _)| *rank);
+        DeliveryPlan {
+            steps: steps.into_iter().map(|(_, step)| step).collect(),
+        }
+    }
 }
 
 // Send notification to given
 #[async_trait::async_trait]
 pub trait NotificationSender: Send + Sync + Clone {
-    async fn send_notification(&self, x: NotificationData);
+    /*
+        Returns one result per attempted recipient of the channel.
+        Channels with nothing to send to for this notification return no results.
+    */
+    async fn send_notification(&self, x: NotificationData) -> Vec<DeliveryResult>;
+
+    /*
+        Report what happened to an outage admins were already notified about.
+        Channels that can't relate it to the original notification ignore it.
+    */
+    async fn send_outage_update(&self, _x: OutageUpdate) {}
+
+    /*
+        Sends one notification about all outages of the summary.
+        Returns results of every outage of the summary, in the order of `summary.grouped`.
+    */
+    async fn send_summary(&self, summary: &NotificationData) -> Vec<Vec<DeliveryResult>> {
+        let results = self.send_notification(summary.clone()).await;
+        summary.grouped.iter().map(|_| results.clone()).collect()
+    }
+
+    // Whether the notification counts as sent, so that escalation can move on.
+    fn is_delivered(&self, results: &[DeliveryResult]) -> bool {
+        results.iter().any(|r| !r.status.is_failed())
+    }
+
+    /*
+        Channels the notification may be delivered by. Only deliveries by these are tracked in the outbox.
+        Channels with nothing to send to for this notification are left out, their deliveries would never complete.
+    */
+    fn channels(&self, _x: &NotificationData) -> Vec<Channel> {
+        vec![]
+    }
 }
 
 // #[async_trait::async_trait]
@@ -66,15 +642,58 @@ enum ImplementedNotificationSender {
     Telegram(TelegramNotificationSender),
     Email(EmailNotificationSender),
     Tcp(TcpNotificationSender),
+    Sms(SmsNotificationSender),
+    Webhook(WebhookNotificationSender),
+    Chat(ChatNotificationSender),
+    Incident(IncidentNotificationSender),
+}
+
+impl ImplementedNotificationSender {
+    fn channel(&self) -> Channel {
+        match self {
+            ImplementedNotificationSender::Telegram(_) => Channel::Telegram,
+            ImplementedNotificationSender::Email(_) => Channel::Email,
+            ImplementedNotificationSender::Tcp(_) => Channel::Tcp,
+            ImplementedNotificationSender::Sms(_) => Channel::Sms,
+            ImplementedNotificationSender::Webhook(_) => Channel::Webhook,
+            ImplementedNotificationSender::Chat(_) => Channel::Chat,
+            ImplementedNotificationSender::Incident(_) => Channel::Incident,
+        }
+    }
 }
 
 #[async_trait::async_trait]
 impl NotificationSender for ImplementedNotificationSender {
-    async fn send_notification(&self, x: NotificationData) {
+    async fn send_notification(&self, x: NotificationData) -> Vec<DeliveryResult> {
         match &self {
             ImplementedNotificationSender::Telegram(s) => s.send_notification(x).await,
             ImplementedNotificationSender::Email(s) => s.send_notification(x).await,
             ImplementedNotificationSender::Tcp(s) => s.send_notification(x).await,
+            ImplementedNotificationSender::Sms(s) => s.send_notification(x).await,
+            ImplementedNotificationSender::Webhook(s) => s.send_notification(x).await,
+            ImplementedNotificationSender::Chat(s) => s.send_notification(x).await,
+            ImplementedNotificationSender::Incident(s) => s.send_notification(x).await,
+        }
+    }
+
+    async fn send_outage_update(&self, x: OutageUpdate) {
+        match &self {
+            ImplementedNotificationSender::Telegram(s) => s.send_outage_update(x).await,
+            ImplementedNotificationSender::Email(s) => s.send_outage_update(x).await,
+            ImplementedNotificationSender::Tcp(s) => s.send_outage_update(x).await,
+            ImplementedNotificationSender::Sms(s) => s.send_outage_update(x).await,
+            ImplementedNotificationSender::Webhook(s) => s.send_outage_update(x).await,
+            ImplementedNotificationSender::Chat(s) => s.send_outage_update(x).await,
+            ImplementedNotificationSender::Incident(s) => s.send_outage_update(x).await,
+        }
+    }
+
+    fn channels(&self, x: &NotificationData) -> Vec<Channel> {
+        match &self {
+            ImplementedNotificationSender::Webhook(s) => s.channels(x),
+            ImplementedNotificationSender::Chat(s) => s.channels(x),
+            ImplementedNotificationSender::Incident(s) => s.channels(x),
+            _ => vec![self.channel()],
         }
     }
 }
@@ -82,68 +701,287 @@ impl NotificationSender for ImplementedNotificationSender {
 #[derive(Clone)]
 pub struct AggregatedNotificationSender {
     senders: Vec<ImplementedNotificationSender>,
+    delivery: DeliveryConfig,
 }
 
 impl AggregatedNotificationSender {
     fn create(
         t_sender: TelegramNotificationSender,
-        _email_sender: Option<EmailNotificationSender>,
-        _tcp_sender: Option<TcpNotificationSender>,
+        email_sender: Option<EmailNotificationSender>,
+        tcp_sender: Option<TcpNotificationSender>,
+        sms_sender: Option<SmsNotificationSender>,
+        webhook_sender: Option<WebhookNotificationSender>,
+        chat_sender: Option<ChatNotificationSender>,
+        incident_sender: Option<IncidentNotificationSender>,
     ) -> AggregatedNotificationSender {
         let t = ImplementedNotificationSender::Telegram(t_sender);
         let mut res = vec![t];
-        if let Some(email_sender) = _email_sender {
-            let e: ImplementedNotificationSender =
-                ImplementedNotificationSender::Email(email_sender);
-            res.push(e);
+        if let Some(email_sender) = email_sender {
+            res.push(ImplementedNotificationSender::Email(email_sender));
+        }
+        if let Some(tcp_sender) = tcp_sender {
+            res.push(ImplementedNotificationSender::Tcp(tcp_sender));
+        }
+        if let Some(sms_sender) = sms_sender {
+            res.push(ImplementedNotificationSender::Sms(sms_sender));
         }
-        if let Some(tcp_sender) = _tcp_sender {
-            let t = ImplementedNotificationSender::Tcp(tcp_sender);
-            res.push(t);
+        if let Some(webhook_sender) = webhook_sender {
+            res.push(ImplementedNotificationSender::Webhook(webhook_sender));
+        }
+        if let Some(chat_sender) = chat_sender {
+            res.push(ImplementedNotificationSender::Chat(chat_sender));
+        }
+        if let Some(incident_sender) = incident_sender {
+            res.push(ImplementedNotificationSender::Incident(incident_sender));
+        }
+        AggregatedNotificationSender {
+            senders: res,
+            delivery: DeliveryConfig::default(),
         }
-        AggregatedNotificationSender { senders: res }
     }
-}
 
-enum ImplementedNotificationResponseListener {
-    Telegram(TelegramNotificationResponseListener),
-}
+    // At least one required channel has to be enabled, otherwise no notification could ever count as sent.
+    fn with_delivery_config(
+        mut self,
+        delivery: DeliveryConfig,
+    ) -> anyhow::Result<AggregatedNotificationSender> {
+        if !self
+            .senders
+            .iter()
+            .any(|s| delivery.required_channels.contains(&s.channel()))
+        {
+            return Err(anyhow::anyhow!(
+                "none of the required channels {:?} is enabled",
+                delivery.required_channels
+            ));
+        }
+        self.delivery = delivery;
+        Ok(self)
+    }
 
-#[async_trait::async_trait]
-impl ResponseListener for ImplementedNotificationResponseListener {
-    async fn listen_for_responses(&self) {
-        match &self {
-            ImplementedNotificationResponseListener::Telegram(r) => r.listen_for_responses().await,
+    // Attempt still running at the deadline is cancelled and counts as failed.
+    async fn send_before<N: NotificationSender>(
+        sender: &N,
+        channel: Channel,
+        x: NotificationData,
+        deadline: Instant,
+    ) -> Vec<DeliveryResult> {
+        let target = x.admin.clone();
+        match tokio::time::timeout_at(deadline, sender.send_notification(x)).await {
+            Ok(results) => results,
+            Err(_) => vec![DeliveryResult::failed(
+                channel,
+                target,
+                "not finished before the claim would expire",
+            )],
         }
     }
-}
 
-#[async_trait::async_trait]
-impl NotificationSender for AggregatedNotificationSender {
-    async fn send_notification(&self, x: NotificationData) {
+    /*
+        Sends by one channel until it doesn't fail, with exponential backoff between attempts.
+        Gives up early when the next attempt would not start before the deadline.
+    */
+    async fn send_with_retries<N: NotificationSender>(
+        &self,
+        sender: &N,
+        channel: Channel,
+        x: NotificationData,
+        deadline: Instant,
+    ) -> Vec<DeliveryResult> {
+        let mut all_results = vec![];
+        let mut backoff = self.delivery.initial_backoff;
+        for attempt in 1..=self.delivery.max_attempts {
+            let results = Self::send_before(sender, channel, x.clone(), deadline).await;
+            let failed = !results.is_empty() && results.iter().all(|r| r.status.is_failed());
+            let retry_after = results.iter().filter_map(|r| r.retry_after).max();
+            all_results.extend(results);
+            if !failed || attempt == self.delivery.max_attempts {
+                break;
+            }
+            let delay = retry_after.unwrap_or(backoff);
+            if Instant::now() + delay >= deadline {
+                log::warn!(
+                    "Not retrying {} notification of outage {}, claim would expire",
+                    channel.as_str(),
+                    x.outage_id
+                );
+                break;
+            }
+            log::info!(
+                "Retrying {} notification of outage {} in {:?}",
+                channel.as_str(),
+                x.outage_id,
+                delay
+            );
+            tokio::time::sleep(delay).await;
+            backoff = (backoff * 2).min(DeliveryConfig::MAX_BACKOFF);
+        }
+        all_results
+    }
+
+    /*
+        Channels that don't reach the admin personally get every notification.
+        Their own retries, e.g. of webhooks, are cut off at the deadline too.
+    */
+    async fn broadcast(&self, x: NotificationData, deadline: Instant) -> Vec<DeliveryResult> {
         let futures = self
             .senders
             .clone()
             .into_iter()
+            .filter(|i| !Channel::CONTACT.contains(&i.channel()))
             .map(|i| {
-                let new_i = i.clone();
-                let new_x = x.clone();
-                tokio::spawn(async move {
-                    new_i.send_notification(new_x).await;
-                })
+                let x = x.clone();
+                tokio::spawn(async move { Self::send_before(&i, i.channel(), x, deadline).await })
             })
             .collect::<FuturesUnordered<_>>();
-
-        futures::future::join_all(futures).await;
+        futures::future::join_all(futures)
+            .await
+            .into_iter()
+            .flat_map(|results| match results {
+                Ok(results) => results,
+                Err(e) => {
+                    log::error!("Notification sender task failed: {}", e);
+                    vec![]
+                }
+            })
+            .collect()
     }
-}
-
-#[async_trait::async_trait]
-pub trait ResponseListener: Send + Sync {
-    async fn listen_for_responses(&self) {}
-}
 
-#[derive(Clone, Debug)]
+    /*
+        Steps of the delivery plan are tried in order until one of them delivers.
+        Contact methods of one step are used together.
+    */
+    async fn send_with_fallback(
+        &self,
+        x: &NotificationData,
+        deadline: Instant,
+    ) -> Vec<DeliveryResult> {
+        let mut all_results = vec![];
+        for step in &x.delivery_plan.steps {
+            let futures = step.iter().filter_map(|contact| {
+                let sender = self
+                    .senders
+                    .iter()
+                    .find(|s| s.channel() == contact.channel)?;
+                Some(self.send_with_retries(
+                    sender,
+                    contact.channel,
+                    x.for_contact(contact),
+                    deadline,
+                ))
+            });
+            let results: Vec<DeliveryResult> = futures::future::join_all(futures)
+                .await
+                .into_iter()
+                .flatten()
+                .collect();
+            let delivered = results.iter().any(|r| !r.status.is_failed());
+            all_results.extend(results);
+            if delivered {
+                break;
+            }
+            if Instant::now() >= deadline {
+                log::warn!(
+                    "Not falling back to next contact methods of {} for outage {}, claim would expire",
+                    x.admin,
+                    x.outage_id
+                );
+                break;
+            }
+        }
+        all_results
+    }
+}
+
+enum ImplementedNotificationResponseListener {
+    Telegram(TelegramNotificationResponseListener),
+    AckLink(AckLinkResponseListener),
+    Tcp(TcpResponseListener),
+}
+
+#[async_trait::async_trait]
+impl ResponseListener for ImplementedNotificationResponseListener {
+    async fn listen_for_responses(&self) -> Result<()> {
+        match &self {
+            ImplementedNotificationResponseListener::Telegram(r) => r.listen_for_responses().await,
+            ImplementedNotificationResponseListener::AckLink(r) => r.listen_for_responses().await,
+            ImplementedNotificationResponseListener::Tcp(r) => r.listen_for_responses().await,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl NotificationSender for AggregatedNotificationSender {
+    async fn send_notification(&self, x: NotificationData) -> Vec<DeliveryResult> {
+        let deadline = Instant::now() + self.delivery.budget;
+        if x.undeliverable.is_some() {
+            return self.send_with_fallback(&x, deadline).await;
+        }
+        let (mut results, broadcast_results) = tokio::join!(
+            self.send_with_fallback(&x, deadline),
+            self.broadcast(x.clone(), deadline)
+        );
+        results.extend(broadcast_results);
+        results
+    }
+
+    // Outages of the summary are broadcast one by one, contact methods of the admin get the summary only.
+    async fn send_summary(&self, summary: &NotificationData) -> Vec<Vec<DeliveryResult>> {
+        let deadline = Instant::now() + self.delivery.budget;
+        let broadcasts = summary
+            .grouped
+            .iter()
+            .map(|x| self.broadcast(x.clone(), deadline));
+        let (contact_results, broadcast_results) = tokio::join!(
+            self.send_with_fallback(summary, deadline),
+            futures::future::join_all(broadcasts)
+        );
+        broadcast_results
+            .into_iter()
+            .map(|mut results| {
+                results.extend(contact_results.iter().cloned());
+                results
+            })
+            .collect()
+    }
+
+    fn is_delivered(&self, results: &[DeliveryResult]) -> bool {
+        results.iter().any(|r| {
+            r.status == DeliveryStatus::Delivered
+                && self.delivery.required_channels.contains(&r.channel)
+        })
+    }
+
+    fn channels(&self, x: &NotificationData) -> Vec<Channel> {
+        self.senders.iter().flat_map(|s| s.channels(x)).collect()
+    }
+
+    async fn send_outage_update(&self, x: OutageUpdate) {
+        let futures = self
+            .senders
+            .clone()
+            .into_iter()
+            .map(|i| {
+                let x = x.clone();
+                tokio::spawn(async move {
+                    i.send_outage_update(x).await;
+                })
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        futures::future::join_all(futures).await;
+    }
+}
+
+#[async_trait::async_trait]
+pub trait ResponseListener: Send + Sync {
+    // Returns only when the listener can't go on, e.g. its address can't be bound.
+    async fn listen_for_responses(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
 pub struct NotificationData {
     pub admin: AdminId,
     pub outage_id: OutageId,
@@ -152,245 +990,2666 @@ pub struct NotificationData {
     pub is_first: bool,
     pub http_address: String,
     pub email: String,
+    pub phone_number: String,
+    // Signed token the recipient has to send back to acknowledge the outage.
+    pub ack_token: String,
+    // Present when the acknowledgement link listener is enabled.
+    pub ack_links: Option<AckLinks>,
+    pub first_notification_sent_at: Option<MyTime>,
+    // Webhook configured for the endpoint, posted to next to the global ones.
+    pub webhook_url: Option<String>,
+    pub outage_start: Option<MyTime>,
+    // Chat channel of the team owning the endpoint, overrides the default one.
+    pub chat_channel: Option<String>,
+    // Incident service integration of the team owning the endpoint, overrides the default one.
+    pub incident_routing_key: Option<String>,
+    // What the healthcheck saw when the endpoint went down.
+    pub failure_reason: Option<String>,
+    pub severity: Severity,
+    pub delivery_plan: DeliveryPlan,
+    // Outages covered by a summary, empty in notifications about a single outage.
+    pub grouped: Vec<NotificationData>,
+    // Generation of the claim on the endpoint the notification is sent under.
+    pub claim_generation: i64,
+    // Set in alerts to the fallback admin about an outage that can't be notified, holds the reason.
+    pub undeliverable: Option<String>,
+    // Number of the reminder, set in reminders of an outage the secondary admin didn't respond to.
+    pub reminder: Option<i32>,
+}
+
+// Notifications are logged, so the ack token is replaced by its fingerprint and the links carrying it are left out.
+impl fmt::Debug for NotificationData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NotificationData")
+            .field("admin", &self.admin)
+            .field("outage_id", &self.outage_id)
+            .field("endpoint", &self.endpoint)
+            .field("telegram_contact_id", &self.telegram_contact_id)
+            .field("is_first", &self.is_first)
+            .field("http_address", &self.http_address)
+            .field("email", &self.email)
+            .field("phone_number", &self.phone_number)
+            .field("ack_token", &AckTokenSigner::fingerprint(&self.ack_token))
+            .field(
+                "first_notification_sent_at",
+                &self.first_notification_sent_at,
+            )
+            .field("webhook_url", &self.webhook_url)
+            .field("outage_start", &self.outage_start)
+            .field("chat_channel", &self.chat_channel)
+            .field("incident_routing_key", &self.incident_routing_key)
+            .field("failure_reason", &self.failure_reason)
+            .field("severity", &self.severity)
+            .field("delivery_plan", &self.delivery_plan)
+            .field("grouped", &self.grouped)
+            .field("claim_generation", &self.claim_generation)
+            .field("undeliverable", &self.undeliverable)
+            .field("reminder", &self.reminder)
+            .finish_non_exhaustive()
+    }
+}
+
+impl NotificationData {
+    pub fn escalation_level(&self) -> i32 {
+        if self.is_first {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn idempotency_key(&self, channel: Channel) -> Uuid {
+        idempotency_key(
+            self.outage_id,
+            self.escalation_level(),
+            self.reminder,
+            &self.admin,
+            channel,
+        )
+    }
+
+    fn outbox_entry(&self, channel: Channel) -> OutboxEntry {
+        OutboxEntry {
+            idempotency_key: self.idempotency_key(channel),
+            outage_id: self.outage_id,
+            escalation_level: self.escalation_level(),
+            admin_id: self.admin.clone(),
+            channel: channel.as_str().to_string(),
+        }
+    }
+
+    // Copy of the notification addressed to one contact method of the admin.
+    fn for_contact(&self, contact: &PlannedContact) -> NotificationData {
+        let mut x = self.clone();
+        match contact.channel {
+            Channel::Telegram => x.telegram_contact_id = contact.address.clone(),
+            Channel::Email => x.email = contact.address.clone(),
+            Channel::Sms => x.phone_number = contact.address.clone(),
+            _ => (),
+        }
+        x
+    }
+
+    // First notification about an outage of the endpoint, for the senders' tests.
+    #[cfg(test)]
+    pub fn example(admin: &str, endpoint: EndpointId) -> NotificationData {
+        NotificationData {
+            admin: admin.to_string(),
+            outage_id: Uuid::new_v4(),
+            endpoint,
+            telegram_contact_id: format!("{}-telegram", admin),
+            is_first: true,
+            http_address: format!("http://endpoint-{}.example.com", endpoint),
+            email: format!("{}@example.com", admin),
+            phone_number: "+48123456789".to_string(),
+            ack_token: "token".to_string(),
+            ack_links: None,
+            first_notification_sent_at: None,
+            webhook_url: None,
+            outage_start: None,
+            chat_channel: None,
+            incident_routing_key: None,
+            failure_reason: None,
+            severity: Severity::Critical,
+            delivery_plan: DeliveryPlan::default(),
+            grouped: vec![],
+            claim_generation: 0,
+            undeliverable: None,
+            reminder: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OutageEvent {
+    Acknowledged(AdminId),
+    // Endpoint is back up.
+    Resolved,
+}
+
+#[derive(Clone, Debug)]
+pub struct OutageUpdate {
+    pub outage_id: OutageId,
+    pub endpoint: EndpointId,
+    pub http_address: String,
+    pub outage_start: Option<MyTime>,
+    pub chat_channel: Option<String>,
+    pub incident_routing_key: Option<String>,
+    pub failure_reason: Option<String>,
+    pub event: OutageEvent,
+}
+
+impl OutageUpdate {
+    fn new(endpoint_data: EndpointData, event: OutageEvent) -> Option<OutageUpdate> {
+        Some(OutageUpdate {
+            outage_id: endpoint_data.outage_id?,
+            endpoint: endpoint_data.endpoint_id,
+            http_address: endpoint_data.http_address,
+            outage_start: endpoint_data.outage_start_timestamp,
+            chat_channel: endpoint_data.conf_chat_channel,
+            incident_routing_key: endpoint_data.conf_incident_routing_key,
+            failure_reason: endpoint_data.outage_failure_reason,
+            event,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResponseAction {
+    Acknowledge,
+    // Notify the secondary admin now instead of waiting for the response timeout.
+    Escalate,
+    // Postpone escalation to the secondary admin by the given duration.
+    Snooze(std::time::Duration),
 }
 
 pub struct ResponseData {
+    // Admin whose identity was verified by the listener that received the response.
     pub admin: AdminId,
     pub outage_id: OutageId,
     pub endpoint: EndpointId,
     pub is_first: bool,
+    pub action: ResponseAction,
+    // Set when responding to a summary, the action is then executed for every outage of the group.
+    pub group: Option<Uuid>,
 }
 
+const DEFAULT_SECS_WAIT_WHEN_HANDLED: u32 = 40;
+
 pub struct ServiceParams {
     pub endpoints_in_query: u32,
     pub secs_wait_when_handled: u32,
     pub service_uuid: Uuid,
+    // Not reminded when not set.
+    pub reminder_interval_secs: Option<u32>,
 }
 
 pub fn init_service_params() -> ServiceParams {
     let n_endpoints_in_query = env::var("ENDPOINTS_IN_QUERY")
         .ok()
         .and_then(|x| x.parse::<u32>().ok())
-        .unwrap_or(1);
+        .unwrap_or(50)
+        .max(1);
     let secs_wait_when_handled = env::var("SECS_WAIT_WHEN_HANDLED")
         .ok()
         .and_then(|x| x.parse::<u32>().ok())
-        .unwrap_or(40);
+        .unwrap_or(DEFAULT_SECS_WAIT_WHEN_HANDLED);
+    let reminder_interval_secs = env::var("REMINDER_INTERVAL_SECS")
+        .ok()
+        .and_then(|x| x.parse::<u32>().ok());
 
     let id = Uuid::new_v4();
     ServiceParams {
         endpoints_in_query: n_endpoints_in_query,
-        secs_wait_when_handled: secs_wait_when_handled,
+        secs_wait_when_handled,
         service_uuid: id,
+        reminder_interval_secs,
     }
 }
 
-struct NotificationService {
-    db_executor: MyDBQueryExecutor,
-    ntf_sender: AggregatedNotificationSender,
+/*
+    Core loop of the service. Independent of where outages are stored and how admins are notified,
+    both are injected by run_notification_service.
+*/
+struct NotificationService<D, S> {
+    db_executor: D,
+    ntf_sender: S,
+    clock: Arc<dyn Clock>,
+    metrics: Metrics,
+    token_signer: AckTokenSigner,
+    ack_link_base_url: Option<String>,
+    grouping: GroupingConfig,
     db_poll_freq: Duration,
+    // Alerted about outages that can't be notified because of their configuration.
+    fallback_admin: Option<AdminId>,
 }
 
-impl NotificationService {
+impl<D, S> NotificationService<D, S>
+where
+    D: DBQueryExecutor + Clone + 'static,
+    S: NotificationSender + 'static,
+{
     pub fn new(
-        db_executor: MyDBQueryExecutor,
-        ntf_sender: AggregatedNotificationSender,
+        db_executor: D,
+        ntf_sender: S,
+        clock: Arc<dyn Clock>,
+        token_signer: AckTokenSigner,
+        ack_link_base_url: Option<String>,
+        grouping: GroupingConfig,
         db_poll_freq: Duration,
-    ) -> NotificationService {
+    ) -> NotificationService<D, S> {
         NotificationService {
             db_executor,
             ntf_sender,
+            clock,
+            metrics: Metrics::default(),
+            token_signer,
+            ack_link_base_url,
+            grouping,
             db_poll_freq,
+            fallback_admin: None,
         }
     }
 
-    pub async fn init_service(
+    pub fn with_fallback_admin(
+        mut self,
+        fallback_admin: Option<AdminId>,
+    ) -> NotificationService<D, S> {
+        self.fallback_admin = fallback_admin;
+        self
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
+
+    pub async fn init_service<L: ResponseListener + 'static>(
         &self,
-        telegram_ntf_receiver: TelegramNotificationResponseListener,
+        ntf_receivers: Vec<L>,
         response_data_receiver: Receiver<ResponseData>,
     ) {
         self.spawn_response_data_receiver_task(response_data_receiver)
             .await;
-        self.spawn_notification_response_listener_task(
-            ImplementedNotificationResponseListener::Telegram(telegram_ntf_receiver),
-        )
-        .await;
-        Self::run_main_loop(
-            self.db_executor.clone(),
-            self.ntf_sender.clone(),
-            self.db_poll_freq,
-        )
-        .await;
+        for ntf_receiver in ntf_receivers {
+            self.spawn_notification_response_listener_task(ntf_receiver)
+                .await;
+        }
+        self.run_main_loop().await;
     }
 
-    async fn run_main_loop(
-        db_executor: MyDBQueryExecutor,
-        ntf_sender: AggregatedNotificationSender,
-        db_poll_freq: Duration,
-    ) {
+    async fn run_main_loop(&self) {
         loop {
-            let x = db_executor.get_endpoints_to_process().await;
-            if let Err(error) = x {
-                log::error!("Errror getting endpoints to process: {:?}", error);
-            } else {
-                let v = x.unwrap();
-                if v.len() > 0 {
-                    log::info!("Got {:?} dead endpoints!", v.len());
-                }
-                let db_executor = db_executor.clone();
-                let sender = ntf_sender.clone();
+            self.poll().await;
+            tokio::time::sleep(self.db_poll_freq).await;
+        }
+    }
 
-                let futures: FuturesUnordered<_> = v
-                    .into_iter()
-                    .map(|x| {
-                        let db_executor = db_executor.clone();
-                        let sender = sender.clone();
-                        tokio::spawn(async move {
-                            let ntf_data =
-                                Self::get_notification_from_endpoint_data(&db_executor, x.clone())
-                                    .await;
-                            log::info!(
-                                "Sending notification {:?} about endpoint {}",
-                                ntf_data,
-                                x.http_address
-                            );
-                            Self::send_notification_and_mark_it(&db_executor, &sender, ntf_data)
-                                .await;
-                        })
+    // Sends notifications of all outages due for one and updates of resolved outages.
+    async fn poll(&self) {
+        let db_executor = &self.db_executor;
+        let x = db_executor.get_endpoints_to_process().await;
+        if let Err(error) = x {
+            log::error!("Errror getting endpoints to process: {:?}", error);
+        } else {
+            let v = x.unwrap();
+            if !v.is_empty() {
+                log::info!("Got {:?} dead endpoints!", v.len());
+            }
+            let now = self.clock.now();
+            let notifications =
+                futures::future::join_all(v.into_iter().map(|x| self.build_notification(now, x)))
+                    .await;
+            let mut by_admin: HashMap<AdminId, Vec<NotificationData>> = HashMap::new();
+            for ntf_data in notifications.into_iter().flatten() {
+                by_admin
+                    .entry(ntf_data.admin.clone())
+                    .or_default()
+                    .push(ntf_data);
+            }
+
+            let futures: FuturesUnordered<_> = by_admin
+                .into_values()
+                .map(|notifications| {
+                    let db_executor = db_executor.clone();
+                    let sender = self.ntf_sender.clone();
+                    let metrics = self.metrics.clone();
+                    let token_signer = self.token_signer.clone();
+                    let ack_link_base_url = self.ack_link_base_url.clone();
+                    let grouping = self.grouping.clone();
+                    tokio::spawn(async move {
+                        Self::notify_admin(
+                            &db_executor,
+                            &sender,
+                            &metrics,
+                            &token_signer,
+                            ack_link_base_url.as_deref(),
+                            &grouping,
+                            notifications,
+                        )
+                        .await;
                     })
-                    .collect::<FuturesUnordered<_>>();
-                futures::future::join_all(futures).await;
+                })
+                .collect::<FuturesUnordered<_>>();
+            futures::future::join_all(futures).await;
+        }
+        Self::send_resolved_updates(db_executor, &self.ntf_sender).await;
+    }
+
+    /*
+        Outages that can't be notified because of the configuration of the endpoint are dead-lettered.
+        On other errors the claim is left to expire, so the notification is retried after SECS_WAIT_WHEN_HANDLED.
+    */
+    async fn build_notification(
+        &self,
+        now: MyTime,
+        endpoint_data: EndpointData,
+    ) -> Option<NotificationData> {
+        let endpoint_id = endpoint_data.endpoint_id;
+        match Self::get_notification_from_endpoint_data(
+            &self.db_executor,
+            &self.token_signer,
+            self.ack_link_base_url.as_deref(),
+            self.fallback_admin.as_ref(),
+            now,
+            endpoint_data.clone(),
+        )
+        .await
+        {
+            Ok(x) => x,
+            Err(error) if error.is_misconfiguration() => {
+                self.dead_letter(endpoint_data, &error).await;
+                None
+            }
+            Err(error) => {
+                log::error!(
+                    "Error building notification of endpoint {}: {:?}",
+                    endpoint_id,
+                    error
+                );
+                None
             }
-            tokio::time::sleep(db_poll_freq).await;
         }
     }
 
-    // #[derive(Clone, Debug)]
-    // pub struct NotificationData {
-    //     pub admin: AdminId,
-    //     pub outage_id: OutageId,
-    //     pub endpoint: EndpointId,
-    //     pub telegram_contact_id: ContactId,
-    //     pub is_first: bool,
-    // }
+    async fn dead_letter(&self, endpoint_data: EndpointData, error: &NotificationError) {
+        let reason = error.to_string();
+        log::error!(
+            "Outage {:?} of endpoint {} can't be notified, dead-lettering it: {}",
+            endpoint_data.outage_id,
+            endpoint_data.endpoint_id,
+            reason
+        );
+        match self
+            .db_executor
+            .dead_letter_outage(
+                endpoint_data.endpoint_id,
+                endpoint_data.outage_id,
+                endpoint_data.ntf_claim_generation,
+                &reason,
+            )
+            .await
+        {
+            Ok(true) => {
+                self.metrics.record_dead_lettered_outage();
+                self.alert_fallback_admin(endpoint_data, reason).await;
+            }
+            Ok(false) => {
+                self.metrics.record_stale_claim_write();
+                log::warn!(
+                    "Claim on endpoint {} was taken over by another instance, not dead-lettering it",
+                    endpoint_data.endpoint_id
+                );
+            }
+            Err(e) => log::error!(
+                "Error dead-lettering outage of endpoint {}: {:?}",
+                endpoint_data.endpoint_id,
+                e
+            ),
+        }
+    }
+
+    // Alert goes to the contact methods of the fallback admin only, it is not about an outage of their team.
+    async fn alert_fallback_admin(&self, endpoint_data: EndpointData, reason: String) {
+        let Some(fallback_admin) = self.fallback_admin.clone() else {
+            log::warn!(
+                "FALLBACK_ADMIN is not set, nobody is alerted about endpoint {}",
+                endpoint_data.endpoint_id
+            );
+            return;
+        };
+        let admin_data = match self.db_executor.get_admin_data(fallback_admin).await {
+            Ok(admin_data) => admin_data,
+            Err(e) => {
+                log::error!("Error getting fallback admin: {:?}", e);
+                return;
+            }
+        };
+        let delivery_plan =
+            Self::get_delivery_plan(&self.db_executor, &admin_data, Severity::Critical).await;
+        let alert = NotificationData {
+            admin: admin_data.admin_id,
+            // Nil when the endpoint is down without an outage.
+            outage_id: endpoint_data.outage_id.unwrap_or_default(),
+            endpoint: endpoint_data.endpoint_id,
+            telegram_contact_id: admin_data.telegram_contact_id,
+            is_first: true,
+            http_address: endpoint_data.http_address,
+            email: admin_data.email_address,
+            phone_number: admin_data.phone_number,
+            ack_token: String::new(),
+            ack_links: None,
+            first_notification_sent_at: None,
+            webhook_url: None,
+            outage_start: endpoint_data.outage_start_timestamp,
+            chat_channel: None,
+            incident_routing_key: None,
+            failure_reason: endpoint_data.outage_failure_reason,
+            severity: Severity::Critical,
+            delivery_plan,
+            grouped: vec![],
+            claim_generation: endpoint_data.ntf_claim_generation,
+            undeliverable: Some(reason),
+            reminder: None,
+        };
+        let results = self.ntf_sender.send_notification(alert.clone()).await;
+        if self.ntf_sender.is_delivered(&results) {
+            log::info!("Fallback admin {} alerted", alert.admin);
+        } else {
+            log::error!(
+                "Failed to alert fallback admin {}: {:?}",
+                alert.admin,
+                results
+            );
+        }
+    }
 
     async fn get_notification_from_endpoint_data(
-        db_executor: &MyDBQueryExecutor,
+        db_executor: &D,
+        token_signer: &AckTokenSigner,
+        ack_link_base_url: Option<&str>,
+        fallback_admin: Option<&AdminId>,
+        now: MyTime,
         endpoint_data: EndpointData,
-    ) -> NotificationData {
+    ) -> Result<Option<NotificationData>> {
         let is_first = !endpoint_data.ntf_is_first_notification_sent;
-        let admin = if is_first {
-            endpoint_data.conf_primary_admin
-        } else {
-            endpoint_data.conf_secondary_admin
+        let outage_id = endpoint_data
+            .outage_id
+            .ok_or(NotificationError::MissingOutage(endpoint_data.endpoint_id))?;
+        let Some(admin_data) =
+            Self::get_escalation_target(db_executor, fallback_admin, &endpoint_data, outage_id)
+                .await?
+        else {
+            return Ok(None);
         };
+        let severity = Severity::from_str(&endpoint_data.conf_severity).unwrap_or_else(|e| {
+            log::warn!(
+                "Endpoint {}: {}, treating outage as critical",
+                endpoint_data.endpoint_id,
+                e
+            );
+            Severity::Critical
+        });
 
-        let admin_data = db_executor.get_admin_data(admin.clone()).await.unwrap();
+        let quiet_hours_end = match severity {
+            Severity::Critical => None,
+            _ => Self::quiet_hours_end(db_executor, &admin_data, now).await,
+        };
+        if let Some(until) = quiet_hours_end {
+            let action =
+                QuietHoursAction::from_str(&admin_data.quiet_hours_action).unwrap_or_else(|e| {
+                    log::warn!("Admin {}: {}, deferring", admin_data.admin_id, e);
+                    QuietHoursAction::Defer
+                });
+            if action == QuietHoursAction::Escalate && is_first {
+                log::info!(
+                    "Admin {} is in quiet hours, escalating {} outage {} to the secondary admin",
+                    admin_data.admin_id,
+                    severity.as_str(),
+                    outage_id
+                );
+                Self::skip_first_notification(db_executor, &endpoint_data, outage_id).await;
+            } else {
+                log::info!(
+                    "Admin {} is in quiet hours, deferring notification about {} outage {} until {}",
+                    admin_data.admin_id,
+                    severity.as_str(),
+                    outage_id,
+                    until
+                );
+                if let Err(e) = db_executor
+                    .defer_notification(
+                        endpoint_data.endpoint_id,
+                        outage_id,
+                        endpoint_data.ntf_claim_generation,
+                        until,
+                    )
+                    .await
+                {
+                    log::error!(
+                        "Error deferring notification about outage {}: {:?}",
+                        outage_id,
+                        e
+                    );
+                }
+            }
+            return Ok(None);
+        }
 
-        NotificationData {
+        let ack_token = token_signer.sign(&AckToken {
+            endpoint: endpoint_data.endpoint_id,
+            outage_id,
+            is_first,
+            admin: admin_data.admin_id.clone(),
+            group: None,
+        });
+        let ack_links = ack_link_base_url.map(|base_url| AckLinks::new(base_url, &ack_token));
+        // Outage is claimed after the second notification only when a reminder is due.
+        let reminder = endpoint_data
+            .ntf_is_second_notification_sent
+            .then_some(endpoint_data.ntf_reminders_sent + 1);
+        let delivery_plan = Self::get_delivery_plan(db_executor, &admin_data, severity).await;
+
+        Ok(Some(NotificationData {
             admin: admin_data.admin_id,
-            outage_id: endpoint_data.outage_id.unwrap(),
+            outage_id,
             endpoint: endpoint_data.endpoint_id,
             telegram_contact_id: admin_data.telegram_contact_id,
-            is_first: is_first,
+            is_first,
             http_address: endpoint_data.http_address,
             email: admin_data.email_address,
+            phone_number: admin_data.phone_number,
+            ack_token,
+            ack_links,
+            first_notification_sent_at: endpoint_data.ntf_first_notification_sent_timestamp,
+            webhook_url: endpoint_data.conf_webhook_url,
+            outage_start: endpoint_data.outage_start_timestamp,
+            chat_channel: endpoint_data.conf_chat_channel,
+            incident_routing_key: endpoint_data.conf_incident_routing_key,
+            failure_reason: endpoint_data.outage_failure_reason,
+            severity,
+            delivery_plan,
+            grouped: vec![],
+            claim_generation: endpoint_data.ntf_claim_generation,
+            undeliverable: None,
+            reminder,
+        }))
+    }
+
+    /*
+        Admin to notify at the current escalation level of the outage. A removed or missing primary admin
+        is skipped, so the secondary admin is notified on the next poll. The fallback admin is paged instead
+        of a removed or missing secondary admin. None when the notification is skipped.
+    */
+    async fn get_escalation_target(
+        db_executor: &D,
+        fallback_admin: Option<&AdminId>,
+        endpoint_data: &EndpointData,
+        outage_id: OutageId,
+    ) -> Result<Option<Admin>> {
+        let is_first = !endpoint_data.ntf_is_first_notification_sent;
+        let admin = if is_first {
+            &endpoint_data.conf_primary_admin
+        } else {
+            &endpoint_data.conf_secondary_admin
+        };
+        match db_executor.get_admin_data(admin.clone()).await {
+            Err(NotificationError::AdminNotFound(_)) if is_first => {
+                log::warn!(
+                    "Primary admin {} of endpoint {} was removed, escalating outage {} to the secondary admin",
+                    admin,
+                    endpoint_data.endpoint_id,
+                    outage_id
+                );
+                Self::skip_first_notification(db_executor, endpoint_data, outage_id).await;
+                Ok(None)
+            }
+            Err(NotificationError::AdminNotFound(secondary)) => {
+                let Some(fallback_admin) = fallback_admin else {
+                    return Err(NotificationError::AdminNotFound(secondary));
+                };
+                log::warn!(
+                    "Secondary admin {} of endpoint {} was removed, paging fallback admin {} about outage {}",
+                    secondary,
+                    endpoint_data.endpoint_id,
+                    fallback_admin,
+                    outage_id
+                );
+                db_executor
+                    .get_admin_data(fallback_admin.clone())
+                    .await
+                    .map(Some)
+            }
+            result => result.map(Some),
         }
     }
 
-    async fn send_notification_and_mark_it(
-        db_executor: &MyDBQueryExecutor,
-        ntf_sender: &AggregatedNotificationSender,
-        ntf_data: NotificationData,
+    // First notification counts as sent and unanswered, so the outage is escalated on the next poll.
+    async fn skip_first_notification(
+        db_executor: &D,
+        endpoint_data: &EndpointData,
+        outage_id: OutageId,
+    ) {
+        match db_executor
+            .skip_first_notification(
+                endpoint_data.endpoint_id,
+                outage_id,
+                endpoint_data.ntf_claim_generation,
+            )
+            .await
+        {
+            Ok(true) => (),
+            Ok(false) => log::warn!(
+                "Claim on outage {} was taken over by another instance, not escalating it",
+                outage_id
+            ),
+            Err(e) => log::error!(
+                "Error skipping first notification of outage {}: {:?}",
+                outage_id,
+                e
+            ),
+        }
+    }
+
+    async fn get_delivery_plan(
+        db_executor: &D,
+        admin_data: &Admin,
+        severity: Severity,
+    ) -> DeliveryPlan {
+        let contact_methods = db_executor
+            .get_contact_methods(admin_data.admin_id.clone())
+            .await
+            .unwrap_or_else(|e| {
+                log::error!(
+                    "Error getting contact methods of admin {}: {:?}",
+                    admin_data.admin_id,
+                    e
+                );
+                vec![]
+            });
+        let rule = db_executor
+            .get_notification_rule(admin_data.admin_id.clone(), severity)
+            .await
+            .unwrap_or_else(|e| {
+                log::error!(
+                    "Error getting notification rule of admin {}: {:?}",
+                    admin_data.admin_id,
+                    e
+                );
+                None
+            });
+        DeliveryPlan::new(admin_data, contact_methods, rule)
+    }
+
+    // Notifications deferred because of quiet hours are claimed again only once they end.
+    async fn quiet_hours_end(db_executor: &D, admin: &Admin, now: MyTime) -> Option<MyTime> {
+        let quiet_hours = match db_executor.get_quiet_hours(admin.admin_id.clone()).await {
+            Ok(quiet_hours) => quiet_hours,
+            Err(e) => {
+                log::error!(
+                    "Error getting quiet hours of admin {}: {:?}",
+                    admin.admin_id,
+                    e
+                );
+                return None;
+            }
+        };
+        admin
+            .quiet_hours_end(&quiet_hours, now.and_utc())
+            .map(|end| end.naive_utc())
+    }
+
+    /*
+        Notifications of one admin from a single poll are sent as one summary.
+        Over the rate limit of the admin they are deferred, so that they are sent together once the limit allows.
+    */
+    async fn notify_admin(
+        db_executor: &D,
+        ntf_sender: &S,
+        metrics: &Metrics,
+        token_signer: &AckTokenSigner,
+        ack_link_base_url: Option<&str>,
+        grouping: &GroupingConfig,
+        notifications: Vec<NotificationData>,
     ) {
-        ntf_sender.send_notification(ntf_data.clone()).await;
+        let Some(admin) = notifications.first().map(|x| x.admin.clone()) else {
+            return;
+        };
+        if let Some(until) = Self::rate_limited_until(db_executor, grouping, &admin).await {
+            for x in &notifications {
+                log::info!(
+                    "Admin {} is over the rate limit, deferring notification about outage {} until {}",
+                    admin,
+                    x.outage_id,
+                    until
+                );
+                if let Err(error) = db_executor
+                    .defer_notification(x.endpoint, x.outage_id, x.claim_generation, until)
+                    .await
+                {
+                    log::error!(
+                        "Error deferring notification about outage {}: {:?}",
+                        x.outage_id,
+                        error
+                    );
+                }
+            }
+            return;
+        }
+
+        let mut pending = vec![];
+        for x in notifications {
+            if Self::record_pending_deliveries(db_executor, ntf_sender, &x).await {
+                log::info!(
+                    "Notification {:?} was delivered by an earlier attempt, marking it as sent",
+                    x
+                );
+                Self::mark_sent(db_executor, metrics, &x).await;
+            } else {
+                pending.push(x);
+            }
+        }
+        let notifications = pending;
+        if notifications.is_empty() {
+            return;
+        }
+
+        let group_id = Uuid::new_v4();
+        let members: Vec<NotificationGroupMember> = notifications
+            .iter()
+            .map(|x| NotificationGroupMember {
+                endpoint_id: x.endpoint,
+                outage_id: x.outage_id,
+                is_first: x.is_first,
+            })
+            .collect();
+        if let Err(error) = db_executor
+            .record_notification_group(group_id, admin.clone(), &members)
+            .await
+        {
+            log::error!(
+                "Error recording notification group of admin {}: {:?}",
+                admin,
+                error
+            );
+        }
+
+        let results = match notifications.as_slice() {
+            [] => return,
+            [x] => {
+                log::info!("Sending notification {:?}", x);
+                vec![ntf_sender.send_notification(x.clone()).await]
+            }
+            [head, others @ ..] => {
+                log::info!(
+                    "Sending summary of {} outages to admin {}",
+                    notifications.len(),
+                    admin
+                );
+                let summary =
+                    Self::summarize(token_signer, ack_link_base_url, group_id, head, others);
+                ntf_sender.send_summary(&summary).await
+            }
+        };
+        let mut delivered = false;
+        for (ntf_data, results) in notifications.into_iter().zip(results) {
+            delivered |=
+                Self::mark_notification(db_executor, ntf_sender, metrics, ntf_data, results).await;
+        }
+        // Only notifications that reached the admin count towards the rate limit.
+        if !delivered {
+            if let Err(error) = db_executor.delete_notification_group(group_id).await {
+                log::error!(
+                    "Error deleting notification group {}: {:?}",
+                    group_id,
+                    error
+                );
+            }
+        }
+    }
+
+    /*
+        Records deliveries of the notification by every channel of the sender as pending.
+        Returns whether an earlier attempt already delivered the notification, e.g. one of an instance
+        that crashed before marking it as sent.
+    */
+    async fn record_pending_deliveries(
+        db_executor: &D,
+        ntf_sender: &S,
+        x: &NotificationData,
+    ) -> bool {
+        let channels = ntf_sender.channels(x);
+        let entries: Vec<OutboxEntry> = channels.iter().map(|c| x.outbox_entry(*c)).collect();
+        let completed = match db_executor.record_pending_deliveries(&entries).await {
+            Ok(completed) => completed,
+            Err(error) => {
+                log::error!(
+                    "Error recording pending deliveries of notification {:?}: {:?}",
+                    x,
+                    error
+                );
+                return false;
+            }
+        };
+        let results: Vec<DeliveryResult> = channels
+            .into_iter()
+            .filter(|c| completed.contains(&x.idempotency_key(*c)))
+            .map(|c| DeliveryResult::delivered(c, x.admin.clone()))
+            .collect();
+        ntf_sender.is_delivered(&results)
+    }
+
+    // Time when the admin can be notified again, if the admin is over the rate limit.
+    async fn rate_limited_until(
+        db_executor: &D,
+        grouping: &GroupingConfig,
+        admin: &AdminId,
+    ) -> Option<MyTime> {
+        let limit = grouping.rate_limit?;
+        let period = MyDuration::try_from(grouping.rate_limit_period).ok()?;
+        let times = match db_executor
+            .get_notification_times(admin.clone(), period)
+            .await
+        {
+            Ok(times) => times,
+            Err(error) => {
+                log::error!(
+                    "Error getting notifications sent to admin {}: {:?}",
+                    admin,
+                    error
+                );
+                return None;
+            }
+        };
+        if times.len() < limit {
+            return None;
+        }
+        Some(
+            times[times.len() - limit]
+                + chrono::Duration::from_std(grouping.rate_limit_period).ok()?,
+        )
+    }
+
+    /*
+        Summary is the notification about the most severe of the outages, with a token responding to all of them.
+        Grouped notifications are the head followed by the others.
+    */
+    fn summarize(
+        token_signer: &AckTokenSigner,
+        ack_link_base_url: Option<&str>,
+        group_id: Uuid,
+        head: &NotificationData,
+        others: &[NotificationData],
+    ) -> NotificationData {
+        let mut summary = others
+            .iter()
+            .fold(head, |most_severe, x| {
+                if x.severity < most_severe.severity {
+                    x
+                } else {
+                    most_severe
+                }
+            })
+            .clone();
+        summary.ack_token = token_signer.sign(&AckToken {
+            endpoint: summary.endpoint,
+            outage_id: summary.outage_id,
+            is_first: summary.is_first,
+            admin: summary.admin.clone(),
+            group: Some(group_id),
+        });
+        summary.ack_links =
+            ack_link_base_url.map(|base_url| AckLinks::new(base_url, &summary.ack_token));
+        summary.grouped = std::iter::once(head).chain(others).cloned().collect();
+        summary
+    }
+
+    // Returns whether the notification was delivered.
+    async fn mark_notification(
+        db_executor: &D,
+        ntf_sender: &S,
+        metrics: &Metrics,
+        ntf_data: NotificationData,
+        results: Vec<DeliveryResult>,
+    ) -> bool {
+        if let Err(error) = db_executor
+            .record_notification_attempts(
+                ntf_data.outage_id,
+                ntf_data.endpoint,
+                ntf_data.admin.clone(),
+                ntf_data.escalation_level(),
+                &results,
+            )
+            .await
+        {
+            log::error!(
+                "Error logging delivery of notification {:?}: {:?}",
+                ntf_data,
+                error
+            );
+        }
+        let completed: Vec<Uuid> = results
+            .iter()
+            .filter(|r| !r.status.is_failed())
+            .map(|r| ntf_data.idempotency_key(r.channel))
+            .collect();
+        if !completed.is_empty() {
+            if let Err(error) = db_executor.complete_deliveries(&completed).await {
+                log::error!(
+                    "Error completing deliveries of notification {:?}: {:?}",
+                    ntf_data,
+                    error
+                );
+            }
+        }
+        // Claim on the endpoint is left to expire, so the notification is retried after SECS_WAIT_WHEN_HANDLED.
+        if !ntf_sender.is_delivered(&results) {
+            log::error!(
+                "Notification {:?} was not delivered by any required channel: {:?}",
+                ntf_data,
+                results
+            );
+            return false;
+        }
+        Self::mark_sent(db_executor, metrics, &ntf_data).await;
+        true
+    }
+
+    async fn mark_sent(db_executor: &D, metrics: &Metrics, ntf_data: &NotificationData) {
         let result = if ntf_data.is_first {
             db_executor
-                .mark_first_notification_sent(ntf_data.endpoint.clone(), ntf_data.outage_id)
+                .mark_first_notification_sent(
+                    ntf_data.endpoint,
+                    ntf_data.outage_id,
+                    ntf_data.claim_generation,
+                )
+                .await
+        } else if ntf_data.reminder.is_some() {
+            db_executor
+                .mark_reminder_sent(
+                    ntf_data.endpoint,
+                    ntf_data.outage_id,
+                    ntf_data.claim_generation,
+                )
                 .await
         } else {
             db_executor
-                .mark_second_notification_sent(ntf_data.endpoint.clone(), ntf_data.outage_id)
+                .mark_second_notification_sent(
+                    ntf_data.endpoint,
+                    ntf_data.outage_id,
+                    ntf_data.claim_generation,
+                )
                 .await
         };
-        if let Err(error) = result {
-            log::error!(
+        match result {
+            Ok(true) => log::info!("Notification {:?} marked as sent succcessfully", ntf_data),
+            // The other instance sends the notification again, the admin gets it twice.
+            Ok(false) => {
+                log::warn!(
+                    "Claim on outage {} was taken over by another instance while sending notification {:?}",
+                    ntf_data.outage_id,
+                    ntf_data
+                );
+                metrics.record_stale_claim_write();
+            }
+            Err(error) => log::error!(
                 "Error marking notification as sent {:?}: {:?}",
                 ntf_data,
                 error
-            );
-        } else {
-            log::info!("Notification {:?} marked as sent succcessfully", ntf_data);
+            ),
         }
     }
 
-    async fn spawn_response_data_receiver_task(
-        &self,
-        mut response_receiver: Receiver<ResponseData>,
-    ) {
-        let db_executor: MyDBQueryExecutor = self.db_executor.clone();
-        tokio::spawn(async move {
-            loop {
-                if let Some(response_data) = response_receiver.recv().await {
-                    let x = db_executor
-                        .mark_endpoint_responded(
-                            response_data.endpoint.clone(),
-                            response_data.outage_id,
-                        )
-                        .await;
-                    if x.is_ok() {
-                        log::info!(
-                            "endpoint: {}, outage: {:?} marked as 'responded' by admin {}",
-                            response_data.endpoint,
-                            response_data.outage_id,
-                            response_data.admin
-                        );
-                    } else {
-                        log::error!(
-                            "error marking endpoint: {}, outage {:?} as responded: {}",
-                            response_data.endpoint,
-                            response_data.outage_id,
-                            x.err().unwrap()
-                        );
-                    }
+    // Resolution is marked as reported only after the update was sent.
+    async fn send_resolved_updates(db_executor: &D, ntf_sender: &S) {
+        match db_executor.get_resolved_outages_to_process().await {
+            Ok(v) => {
+                let futures = v
+                    .into_iter()
+                    .filter_map(|x| OutageUpdate::new(x, OutageEvent::Resolved))
+                    .map(|update| async move {
+                        log::info!("Sending update {:?}", update);
+                        let (endpoint, outage_id) = (update.endpoint, update.outage_id);
+                        ntf_sender.send_outage_update(update).await;
+                        if let Err(error) = db_executor
+                            .mark_resolution_reported(endpoint, outage_id)
+                            .await
+                        {
+                            log::error!(
+                                "Error marking resolution of outage {:?} as reported: {:?}",
+                                outage_id,
+                                error
+                            );
+                        }
+                    });
+                futures::future::join_all(futures).await;
+            }
+            Err(error) => log::error!("Error getting resolved outages: {:?}", error),
+        }
+    }
+
+    async fn send_acknowledged_update(
+        db_executor: &D,
+        ntf_sender: &S,
+        response_data: &ResponseData,
+    ) {
+        match db_executor
+            .get_endpoint_by_outage(response_data.outage_id)
+            .await
+        {
+            Ok(Some(endpoint_data)) => {
+                let event = OutageEvent::Acknowledged(response_data.admin.clone());
+                if let Some(update) = OutageUpdate::new(endpoint_data, event) {
+                    ntf_sender.send_outage_update(update).await;
                 }
             }
+            Ok(None) => (),
+            Err(error) => log::error!(
+                "Error reading outage {:?} to report acknowledgement: {:?}",
+                response_data.outage_id,
+                error
+            ),
+        }
+    }
+
+    // Response is accepted only from admins configured for the endpoint the outage belongs to.
+    // Fallback admin can respond in place of a removed or missing secondary admin, whom it is paged instead of.
+    async fn is_response_authorized(
+        db_executor: &D,
+        fallback_admin: Option<&AdminId>,
+        response_data: &ResponseData,
+    ) -> Result<bool> {
+        let Some(endpoint_data) = db_executor
+            .get_endpoint_by_outage(response_data.outage_id)
+            .await?
+            .filter(|e| e.endpoint_id == response_data.endpoint)
+        else {
+            return Ok(false);
+        };
+        if endpoint_data.is_on_escalation_path(&response_data.admin) {
+            return Ok(true);
+        }
+        if fallback_admin != Some(&response_data.admin) {
+            return Ok(false);
+        }
+        match db_executor
+            .get_admin_data(endpoint_data.conf_secondary_admin)
+            .await
+        {
+            Ok(_) => Ok(false),
+            Err(NotificationError::AdminNotFound(_)) => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn spawn_response_data_receiver_task(
+        &self,
+        mut response_receiver: Receiver<ResponseData>,
+    ) {
+        let db_executor: D = self.db_executor.clone();
+        let ntf_sender = self.ntf_sender.clone();
+        let fallback_admin = self.fallback_admin.clone();
+        tokio::spawn(async move {
+            while let Some(response_data) = response_receiver.recv().await {
+                Self::handle_response(
+                    &db_executor,
+                    &ntf_sender,
+                    fallback_admin.as_ref(),
+                    response_data,
+                )
+                .await;
+            }
+            log::error!("All response listeners stopped, no more responses will be handled");
         });
     }
-    async fn spawn_notification_response_listener_task(
+
+    async fn handle_response(
+        db_executor: &D,
+        ntf_sender: &S,
+        fallback_admin: Option<&AdminId>,
+        response_data: ResponseData,
+    ) {
+        for response_data in Self::expand_group_response(db_executor, response_data).await {
+            match Self::is_response_authorized(db_executor, fallback_admin, &response_data).await {
+                Ok(true) => (),
+                Ok(false) => {
+                    log::warn!(
+                        "Rejected response to endpoint: {}, outage: {:?} from admin {} who is not on its escalation path",
+                        response_data.endpoint,
+                        response_data.outage_id,
+                        response_data.admin
+                    );
+                    continue;
+                }
+                Err(error) => {
+                    log::error!(
+                        "error authorizing response to endpoint: {}, outage {:?}: {}",
+                        response_data.endpoint,
+                        response_data.outage_id,
+                        error
+                    );
+                    continue;
+                }
+            }
+            Self::execute_response_action(db_executor, ntf_sender, response_data).await;
+        }
+    }
+
+    // Response to a summary is a response to every outage of the summary.
+    async fn expand_group_response(
+        db_executor: &D,
+        response_data: ResponseData,
+    ) -> Vec<ResponseData> {
+        let Some(group_id) = response_data.group else {
+            return vec![response_data];
+        };
+        match db_executor.get_notification_group_members(group_id).await {
+            Ok(members) if !members.is_empty() => members
+                .into_iter()
+                .map(|member| ResponseData {
+                    admin: response_data.admin.clone(),
+                    outage_id: member.outage_id,
+                    endpoint: member.endpoint_id,
+                    is_first: member.is_first,
+                    action: response_data.action,
+                    group: None,
+                })
+                .collect(),
+            result => {
+                if let Err(error) = result {
+                    log::error!(
+                        "Error getting outages of notification group {}: {:?}",
+                        group_id,
+                        error
+                    );
+                }
+                log::warn!(
+                    "Responding only to outage {} of unknown notification group {}",
+                    response_data.outage_id,
+                    group_id
+                );
+                vec![ResponseData {
+                    group: None,
+                    ..response_data
+                }]
+            }
+        }
+    }
+
+    async fn execute_response_action(db_executor: &D, ntf_sender: &S, response_data: ResponseData) {
+        let x = match response_data.action {
+            ResponseAction::Acknowledge => {
+                db_executor
+                    .mark_endpoint_responded(
+                        response_data.endpoint,
+                        response_data.outage_id,
+                        response_data.admin.clone(),
+                    )
+                    .await
+            }
+            ResponseAction::Escalate => {
+                db_executor
+                    .escalate_outage(response_data.endpoint, response_data.outage_id)
+                    .await
+            }
+            ResponseAction::Snooze(duration) => match MyDuration::try_from(duration) {
+                Ok(interval) => {
+                    db_executor
+                        .snooze_outage(response_data.endpoint, response_data.outage_id, interval)
+                        .await
+                }
+                Err(e) => Err(NotificationError::InvalidResponse(format!(
+                    "snooze duration {:?}: {}",
+                    duration, e
+                ))),
+            },
+        };
+        match x {
+            Ok(true) => {
+                log::info!(
+                    "endpoint: {}, outage: {:?} action {:?} executed for admin {}",
+                    response_data.endpoint,
+                    response_data.outage_id,
+                    response_data.action,
+                    response_data.admin
+                );
+                if response_data.action == ResponseAction::Acknowledge {
+                    Self::send_acknowledged_update(db_executor, ntf_sender, &response_data).await;
+                }
+            }
+            Ok(false) => log::info!(
+                "endpoint: {}, outage: {:?} action {:?} of admin {} had no effect",
+                response_data.endpoint,
+                response_data.outage_id,
+                response_data.action,
+                response_data.admin
+            ),
+            Err(error) => log::error!(
+                "error executing action {:?} for endpoint: {}, outage {:?}: {}",
+                response_data.action,
+                response_data.endpoint,
+                response_data.outage_id,
+                error
+            ),
+        }
+    }
+
+    async fn spawn_notification_response_listener_task<L: ResponseListener + 'static>(
         &self,
-        ntf_receiver: ImplementedNotificationResponseListener,
+        ntf_receiver: L,
     ) {
         tokio::spawn(async move {
-            ntf_receiver.listen_for_responses().await;
+            if let Err(e) = ntf_receiver.listen_for_responses().await {
+                log::error!("Response listener stopped: {}", e);
+            }
         });
     }
 }
 
 pub async fn run_notification_service(db_poll_freq: Duration, tcp_server: Option<String>) {
     let c = init_service_params();
+    let grouping = GroupingConfig::from_env().expect("Invalid grouping configuration");
+    let postgres = get_postgres_connection()
+        .await
+        .expect("Failed to connect to postgres");
     let db_executor = MyDBQueryExecutor::new(
+        postgres,
         c.secs_wait_when_handled,
         c.endpoints_in_query,
         c.service_uuid,
     )
-    .await;
+    .with_group_wait(grouping.group_wait_secs)
+    .with_reminder_interval(c.reminder_interval_secs);
+    let token_signer =
+        AckTokenSigner::from_env().expect("Invalid acknowledgement token configuration");
     let (sender, receiver) = channel(constants::RESPONSE_DATA_CHANNEL_BUFFER_SIZE);
-    let (telegram_ntf_sender, ntf_receiver) =
-        create_telegram_notification_sender_and_receiver(sender);
-    let email_sender = None;
-    let tcp_sender = if let Some(tcp_server) = tcp_server {
-        Some(TcpNotificationSender::new(tcp_server).await.unwrap())
-    } else {
-        None
-    };
-    let ntf_sender =
-        AggregatedNotificationSender::create(telegram_ntf_sender, email_sender, tcp_sender);
-    let ntf_service: NotificationService =
-        NotificationService::new(db_executor, ntf_sender, db_poll_freq);
-    ntf_service.init_service(ntf_receiver, receiver).await;
+    let templates = Templates::from_env().expect("Invalid templates configuration");
+    let (telegram_ntf_sender, ntf_receiver) = create_telegram_notification_sender_and_receiver(
+        sender.clone(),
+        db_executor.clone(),
+        token_signer.clone(),
+        templates.clone(),
+    );
+    let email_sender = EmailConfig::from_env()
+        .expect("Invalid SMTP configuration")
+        .map(|config| {
+            EmailNotificationSender::new(config, templates.clone())
+                .expect("Invalid SMTP configuration")
+        });
+    let tcp_response_handler = TcpResponseHandler::new(sender.clone(), token_signer.clone());
+    let tcp_sender = tcp_server.map(|address| {
+        TcpConfig::from_env(address)
+            .and_then(|config| TcpNotificationSender::new(config, tcp_response_handler.clone()))
+            .expect("Invalid TCP configuration")
+    });
+    let ack_link_config = AckLinkConfig::from_env();
+    let ack_link_base_url = ack_link_config.as_ref().map(|c| c.base_url.clone());
+    let sms_sender = SmsConfig::from_env()
+        .expect("Invalid SMS configuration")
+        .map(|config| {
+            let provider = config
+                .create_provider(ack_link_base_url.as_deref())
+                .expect("Invalid SMS configuration");
+            SmsNotificationSender::new(
+                provider,
+                config.max_length,
+                db_executor.clone(),
+                templates.clone(),
+            )
+        });
+    let webhook_sender = WebhookConfig::from_env()
+        .expect("Invalid webhook configuration")
+        .map(WebhookNotificationSender::new);
+    let chat_sender = ChatConfig::from_env()
+        .expect("Invalid chat configuration")
+        .map(|config| ChatNotificationSender::new(config, db_executor.clone(), templates.clone()));
+    let incident_sender = IncidentConfig::from_env().map(|config| {
+        let provider = config
+            .create_provider()
+            .expect("Invalid incident service configuration");
+        IncidentNotificationSender::new(provider, config.default_routing_key)
+    });
+    let mut ntf_receivers = vec![ImplementedNotificationResponseListener::Telegram(
+        ntf_receiver,
+    )];
+    if let Some(config) = TcpListenerConfig::from_env() {
+        ntf_receivers.push(ImplementedNotificationResponseListener::Tcp(
+            TcpResponseListener::new(config, tcp_response_handler),
+        ));
+    }
+    if let Some(ack_link_config) = ack_link_config {
+        let mut ack_link_listener =
+            AckLinkResponseListener::new(ack_link_config, sender, token_signer.clone());
+        if let Some(router) = sms_sender.as_ref().and_then(|s| s.status_callback_router()) {
+            ack_link_listener = ack_link_listener.merge(router);
+        }
+        ntf_receivers.push(ImplementedNotificationResponseListener::AckLink(
+            ack_link_listener,
+        ));
+    }
+    let ntf_sender = AggregatedNotificationSender::create(
+        telegram_ntf_sender,
+        email_sender,
+        tcp_sender,
+        sms_sender,
+        webhook_sender,
+        chat_sender,
+        incident_sender,
+    )
+    .with_delivery_config(
+        DeliveryConfig::from_env(Duration::from_secs(c.secs_wait_when_handled.into()))
+            .expect("Invalid delivery configuration"),
+    )
+    .expect("Invalid delivery configuration");
+    let ntf_service = NotificationService::new(
+        db_executor,
+        ntf_sender,
+        Arc::new(SystemClock),
+        token_signer,
+        ack_link_base_url,
+        grouping,
+        db_poll_freq,
+    )
+    .with_fallback_admin(env::var("FALLBACK_ADMIN").ok());
+    if let Some(config) = MetricsConfig::from_env() {
+        tokio::spawn(serve_metrics(config, ntf_service.metrics()));
+    }
+    ntf_service.init_service(ntf_receivers, receiver).await;
 }
 
 pub mod constants {
     pub const RESPONSE_DATA_CHANNEL_BUFFER_SIZE: usize = 128;
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    };
+
+    use super::*;
+    use crate::{
+        clock::VirtualClock,
+        incident_sender::{ImplementedIncidentProvider, PagerDutyProvider},
+        memory_executor::InMemoryDBQueryExecutor,
+    };
+
+    const SECS_WAIT_WHEN_HANDLED: u32 = 40;
+    const ENDPOINTS_IN_QUERY: u32 = 2;
+    const ALLOWED_RESPONSE_SECS: u64 = 60;
+    const ENDPOINT: EndpointId = 1;
+
+    #[derive(Clone, Default)]
+    struct RecordingSender {
+        notifications: Arc<Mutex<Vec<NotificationData>>>,
+        updates: Arc<Mutex<Vec<OutageUpdate>>>,
+        failing: Arc<AtomicBool>,
+    }
+
+    impl RecordingSender {
+        // Admin and escalation level of every notification sent so far.
+        fn sent(&self) -> Vec<(AdminId, bool)> {
+            self.notifications
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|x| (x.admin.clone(), x.is_first))
+                .collect()
+        }
+
+        fn events(&self) -> Vec<OutageEvent> {
+            self.updates
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|x| x.event.clone())
+                .collect()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl NotificationSender for RecordingSender {
+        async fn send_notification(&self, x: NotificationData) -> Vec<DeliveryResult> {
+            let target = x.admin.clone();
+            self.notifications.lock().unwrap().push(x);
+            if self.failing.load(Ordering::SeqCst) {
+                vec![DeliveryResult::failed(
+                    Channel::Telegram,
+                    target,
+                    "unreachable",
+                )]
+            } else {
+                vec![DeliveryResult::delivered(Channel::Telegram, target)]
+            }
+        }
+
+        async fn send_outage_update(&self, x: OutageUpdate) {
+            self.updates.lock().unwrap().push(x);
+        }
+
+        fn channels(&self, _: &NotificationData) -> Vec<Channel> {
+            vec![Channel::Telegram]
+        }
+    }
+
+    type TestService = NotificationService<InMemoryDBQueryExecutor<VirtualClock>, RecordingSender>;
+
+    fn admin(admin_id: &str) -> Admin {
+        Admin {
+            admin_id: admin_id.to_string(),
+            telegram_contact_id: format!("{}-telegram", admin_id),
+            phone_number: String::new(),
+            email_address: format!("{}@example.com", admin_id),
+            timezone: "UTC".to_string(),
+            quiet_hours_action: "defer".to_string(),
+        }
+    }
+
+    fn outage(endpoint_id: EndpointId, started: MyTime) -> EndpointData {
+        EndpointData {
+            endpoint_id,
+            http_address: format!("http://endpoint-{}.example.com", endpoint_id),
+            is_down: true,
+            outage_id: Some(Uuid::new_v4()),
+            ntf_is_being_handled: false,
+            ntf_is_being_handled_timestamp: None,
+            ntf_is_being_handled_service_id: None,
+            ntf_is_first_notification_sent: false,
+            ntf_first_notification_sent_timestamp: None,
+            ntf_is_second_notification_sent: false,
+            ntf_reminders_sent: 0,
+            conf_primary_admin: "primary".to_string(),
+            conf_secondary_admin: "secondary".to_string(),
+            conf_allowed_response_duration: MyDuration::try_from(Duration::from_secs(
+                ALLOWED_RESPONSE_SECS,
+            ))
+            .unwrap(),
+            ntf_first_responded: false,
+            conf_webhook_url: None,
+            conf_chat_channel: None,
+            outage_start_timestamp: Some(started),
+            conf_incident_routing_key: None,
+            outage_failure_reason: None,
+            conf_severity: Severity::Critical.as_str().to_string(),
+            ntf_claim_generation: 0,
+        }
+    }
+
+    // Stalls past the claim timeout on every notification, so that the peer takes the outage over.
+    #[derive(Clone)]
+    struct TakeoverSender {
+        clock: VirtualClock,
+        peer: InMemoryDBQueryExecutor<VirtualClock>,
+    }
+
+    #[async_trait::async_trait]
+    impl NotificationSender for TakeoverSender {
+        async fn send_notification(&self, x: NotificationData) -> Vec<DeliveryResult> {
+            self.clock.advance(chrono::Duration::seconds(
+                (SECS_WAIT_WHEN_HANDLED + 1).into(),
+            ));
+            assert_eq!(self.peer.get_endpoints_to_process().await.unwrap().len(), 1);
+            vec![DeliveryResult::delivered(Channel::Telegram, x.admin)]
+        }
+
+        async fn send_outage_update(&self, _: OutageUpdate) {}
+    }
+
+    struct Setup {
+        clock: VirtualClock,
+        db: InMemoryDBQueryExecutor<VirtualClock>,
+        sender: RecordingSender,
+        outage_id: OutageId,
+    }
+
+    impl Setup {
+        // Endpoint that went down a moment ago, escalated from the primary to the secondary admin.
+        fn new() -> Setup {
+            let clock = VirtualClock::new(
+                chrono::NaiveDate::from_ymd_opt(2024, 1, 1)
+                    .unwrap()
+                    .and_hms_opt(12, 0, 0)
+                    .unwrap(),
+            );
+            let db = InMemoryDBQueryExecutor::new(
+                clock.clone(),
+                SECS_WAIT_WHEN_HANDLED,
+                ENDPOINTS_IN_QUERY,
+                Uuid::new_v4(),
+            );
+            db.insert_admin(admin("primary"));
+            db.insert_admin(admin("secondary"));
+            let endpoint = outage(ENDPOINT, clock.now());
+            let outage_id = endpoint.outage_id.unwrap();
+            db.insert_endpoint(endpoint);
+            clock.advance(chrono::Duration::seconds(1));
+            Setup {
+                clock,
+                db,
+                sender: RecordingSender::default(),
+                outage_id,
+            }
+        }
+
+        fn service(&self) -> TestService {
+            self.service_of(self.db.clone())
+        }
+
+        fn service_of(&self, db: InMemoryDBQueryExecutor<VirtualClock>) -> TestService {
+            self.service_with(db, self.sender.clone())
+        }
+
+        fn service_with<S: NotificationSender + 'static>(
+            &self,
+            db: InMemoryDBQueryExecutor<VirtualClock>,
+            sender: S,
+        ) -> NotificationService<InMemoryDBQueryExecutor<VirtualClock>, S> {
+            NotificationService::new(
+                db,
+                sender,
+                Arc::new(self.clock.clone()),
+                AckTokenSigner::new(b"secret".to_vec()),
+                None,
+                GroupingConfig {
+                    group_wait_secs: 0,
+                    rate_limit: None,
+                    rate_limit_period: Duration::from_secs(600),
+                },
+                Duration::from_secs(1),
+            )
+        }
+
+        fn advance_secs(&self, secs: i64) {
+            self.clock.advance(chrono::Duration::seconds(secs));
+        }
+
+        async fn respond(&self, service: &TestService, admin: &str, action: ResponseAction) {
+            let response_data = ResponseData {
+                admin: admin.to_string(),
+                outage_id: self.outage_id,
+                endpoint: ENDPOINT,
+                is_first: true,
+                action,
+                group: None,
+            };
+            TestService::handle_response(
+                &service.db_executor,
+                &service.ntf_sender,
+                service.fallback_admin.as_ref(),
+                response_data,
+            )
+            .await;
+        }
+    }
+
+    // Delivery of the first notification to the primary admin by telegram.
+    fn first_delivery(outage_id: OutageId) -> OutboxEntry {
+        OutboxEntry {
+            idempotency_key: idempotency_key(outage_id, 1, None, "primary", Channel::Telegram),
+            outage_id,
+            escalation_level: 1,
+            admin_id: "primary".to_string(),
+            channel: Channel::Telegram.as_str().to_string(),
+        }
+    }
+
+    fn primary() -> (AdminId, bool) {
+        ("primary".to_string(), true)
+    }
+
+    fn secondary() -> (AdminId, bool) {
+        ("secondary".to_string(), false)
+    }
+
+    // Answers attempts with the given results in order, never finishes once they run out.
+    #[derive(Clone, Default)]
+    struct ScriptedSender {
+        results: Arc<Mutex<Vec<DeliveryResult>>>,
+        attempts: Arc<Mutex<Vec<Instant>>>,
+    }
+
+    impl ScriptedSender {
+        fn new(mut results: Vec<DeliveryResult>) -> ScriptedSender {
+            results.reverse();
+            ScriptedSender {
+                results: Arc::new(Mutex::new(results)),
+                ..Default::default()
+            }
+        }
+
+        // Seconds since the first attempt of every attempt.
+        fn attempt_secs(&self) -> Vec<u64> {
+            let attempts = self.attempts.lock().unwrap();
+            attempts
+                .iter()
+                .map(|t| (*t - attempts[0]).as_secs())
+                .collect()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl NotificationSender for ScriptedSender {
+        async fn send_notification(&self, _: NotificationData) -> Vec<DeliveryResult> {
+            self.attempts.lock().unwrap().push(Instant::now());
+            let result = self.results.lock().unwrap().pop();
+            match result {
+                Some(result) => vec![result],
+                None => std::future::pending().await,
+            }
+        }
+    }
+
+    fn delivery(
+        max_attempts: u32,
+        initial_backoff_secs: u64,
+        budget_secs: u64,
+    ) -> AggregatedNotificationSender {
+        AggregatedNotificationSender {
+            senders: vec![],
+            delivery: DeliveryConfig {
+                max_attempts,
+                initial_backoff: Duration::from_secs(initial_backoff_secs),
+                budget: Duration::from_secs(budget_secs),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn telegram_failure() -> DeliveryResult {
+        DeliveryResult::failed(Channel::Telegram, "admin", "unreachable")
+    }
+
+    async fn send_with_retries(
+        aggregated: &AggregatedNotificationSender,
+        sender: &ScriptedSender,
+    ) -> Vec<DeliveryResult> {
+        let deadline = Instant::now() + aggregated.delivery.budget;
+        aggregated
+            .send_with_retries(
+                sender,
+                Channel::Telegram,
+                NotificationData::example("admin", ENDPOINT),
+                deadline,
+            )
+            .await
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_attempts_are_retried_with_exponential_backoff() {
+        let sender = ScriptedSender::new(vec![
+            telegram_failure(),
+            telegram_failure(),
+            telegram_failure(),
+            DeliveryResult::delivered(Channel::Telegram, "admin"),
+        ]);
+
+        let results = send_with_retries(&delivery(5, 1, 60), &sender).await;
+
+        assert_eq!(sender.attempt_secs(), vec![0, 1, 3, 7]);
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[3].status, DeliveryStatus::Delivered);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn attempts_stop_at_max_attempts() {
+        let sender = ScriptedSender::new(vec![telegram_failure(); 3]);
+
+        let results = send_with_retries(&delivery(2, 1, 60), &sender).await;
+
+        assert_eq!(sender.attempt_secs(), vec![0, 1]);
+        assert!(results.iter().all(|r| r.status.is_failed()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_after_of_provider_replaces_backoff() {
+        let sender = ScriptedSender::new(vec![
+            telegram_failure().with_retry_after(Some(Duration::from_secs(5))),
+            DeliveryResult::delivered(Channel::Telegram, "admin"),
+        ]);
+
+        send_with_retries(&delivery(5, 1, 60), &sender).await;
+
+        assert_eq!(sender.attempt_secs(), vec![0, 5]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_that_would_start_after_deadline_is_skipped() {
+        let sender = ScriptedSender::new(vec![telegram_failure(); 5]);
+
+        let results = send_with_retries(&delivery(5, 10, 25), &sender).await;
+
+        // Third attempt would start 30 s after the first one.
+        assert_eq!(sender.attempt_secs(), vec![0, 10]);
+        assert_eq!(results.len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn attempt_running_at_deadline_fails() {
+        let sender = ScriptedSender::new(vec![]);
+        let started = Instant::now();
+
+        let results = send_with_retries(&delivery(5, 1, 30), &sender).await;
+
+        assert_eq!(started.elapsed(), Duration::from_secs(30));
+        assert_eq!(sender.attempt_secs(), vec![0]);
+        assert_eq!(results.len(), 1);
+        assert!(results[0].status.is_failed());
+    }
+
+    #[tokio::test]
+    async fn broadcast_is_cut_off_at_deadline() {
+        // Accepts connections but never answers, the webhook keeps waiting for the response.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let mut aggregated = delivery(1, 1, 0);
+        aggregated.senders = vec![ImplementedNotificationSender::Webhook(
+            WebhookNotificationSender::new(WebhookConfig {
+                urls: vec![url],
+                secret: "secret".to_string(),
+                max_attempts: 5,
+                initial_backoff: Duration::from_secs(1),
+            }),
+        )];
+        let started = Instant::now();
+
+        let results = aggregated
+            .broadcast(
+                NotificationData::example("admin", ENDPOINT),
+                started + Duration::from_millis(200),
+            )
+            .await;
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].channel, Channel::Webhook);
+        assert!(results[0].status.is_failed());
+        drop(listener);
+    }
+
+    #[test]
+    fn default_delivery_budget_fits_default_claim() {
+        let budget = DeliveryConfig::default().budget;
+        assert!(budget > Duration::ZERO);
+        assert!(budget < Duration::from_secs(DEFAULT_SECS_WAIT_WHEN_HANDLED.into()));
+    }
+
+    #[test]
+    fn logged_notification_does_not_contain_ack_token() {
+        let mut x = NotificationData::example("admin", 1);
+        x.ack_token = "secret-ack-token".to_string();
+        x.ack_links = Some(AckLinks {
+            acknowledge: "http://ack.example.com/ack?token=secret-ack-token".to_string(),
+            escalate: "http://ack.example.com/escalate?token=secret-ack-token".to_string(),
+            snooze: "http://ack.example.com/snooze?token=secret-ack-token".to_string(),
+        });
+
+        let logged = format!("{:?}", x);
+        assert!(!logged.contains("secret-ack-token"));
+        assert!(logged.contains(&AckTokenSigner::fingerprint("secret-ack-token")));
+    }
+
+    #[test]
+    fn idempotency_key_is_name_based_uuid_of_outage() {
+        let outage_id = Uuid::new_v4();
+        let key = idempotency_key(outage_id, 2, None, "secondary", Channel::Sms);
+
+        assert_eq!(key.get_version_num(), 5);
+        assert_eq!(key, Uuid::new_v5(&outage_id, "2:secondary:sms".as_bytes()));
+        assert_eq!(
+            idempotency_key(outage_id, 2, Some(3), "secondary", Channel::Sms),
+            Uuid::new_v5(&outage_id, "2:secondary:sms:reminder-3".as_bytes())
+        );
+    }
+
+    #[test]
+    fn deliveries_are_tracked_only_for_channels_with_recipients() {
+        let aggregated = AggregatedNotificationSender {
+            senders: vec![
+                ImplementedNotificationSender::Webhook(WebhookNotificationSender::new(
+                    WebhookConfig {
+                        urls: vec![],
+                        secret: "secret".to_string(),
+                        max_attempts: 1,
+                        initial_backoff: Duration::from_secs(1),
+                    },
+                )),
+                ImplementedNotificationSender::Incident(IncidentNotificationSender::new(
+                    ImplementedIncidentProvider::PagerDuty(PagerDutyProvider::new(
+                        "http://pagerduty.example.com".to_string(),
+                    )),
+                    None,
+                )),
+            ],
+            delivery: DeliveryConfig::default(),
+        };
+        let mut x = NotificationData::example("admin", ENDPOINT);
+
+        assert_eq!(aggregated.channels(&x), vec![]);
+
+        x.webhook_url = Some("http://hooks.example.com".to_string());
+        x.incident_routing_key = Some("routing-key".to_string());
+        assert_eq!(
+            aggregated.channels(&x),
+            vec![Channel::Webhook, Channel::Incident]
+        );
+    }
+
+    fn contact(channel: &str, address: &str, rank: i32) -> ContactMethod {
+        ContactMethod {
+            admin_id: "admin".to_string(),
+            channel: channel.to_string(),
+            address: address.to_string(),
+            rank,
+        }
+    }
+
+    fn planned(channel: Channel, address: &str) -> PlannedContact {
+        PlannedContact {
+            channel,
+            address: address.to_string(),
+        }
+    }
+
+    fn rule(channels: &str) -> NotificationRule {
+        NotificationRule {
+            admin_id: "admin".to_string(),
+            severity: "critical".to_string(),
+            channels: channels.to_string(),
+        }
+    }
+
+    #[test]
+    fn contact_methods_of_equal_rank_are_one_step() {
+        let methods = vec![
+            contact("sms", "+48123456789", 2),
+            contact("telegram", "42", 1),
+            contact("email", "admin@example.com", 2),
+        ];
+
+        let plan = DeliveryPlan::new(&admin("admin"), methods, None);
+
+        assert_eq!(
+            plan.steps,
+            vec![
+                vec![planned(Channel::Telegram, "42")],
+                vec![
+                    planned(Channel::Sms, "+48123456789"),
+                    planned(Channel::Email, "admin@example.com"),
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn contact_methods_of_channels_outside_rule_are_skipped() {
+        let methods = vec![
+            contact("telegram", "42", 1),
+            contact("email", "admin@example.com", 2),
+            contact("sms", "+48123456789", 3),
+        ];
+
+        let plan = DeliveryPlan::new(&admin("admin"), methods, Some(rule("sms, email")));
+
+        assert_eq!(
+            plan.steps,
+            vec![
+                vec![planned(Channel::Email, "admin@example.com")],
+                vec![planned(Channel::Sms, "+48123456789")],
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_rule_allows_every_channel() {
+        let methods = vec![
+            contact("telegram", "42", 1),
+            contact("email", "admin@example.com", 1),
+        ];
+
+        let plan = DeliveryPlan::new(&admin("admin"), methods, Some(rule("telegram,pager")));
+
+        assert_eq!(plan.steps.len(), 1);
+        assert_eq!(plan.steps[0].len(), 2);
+    }
+
+    #[test]
+    fn contact_methods_of_unsupported_channels_are_skipped() {
+        let methods = vec![
+            contact("webhook", "http://example.com", 1),
+            contact("sms", "+48123456789", 2),
+        ];
+
+        let plan = DeliveryPlan::new(&admin("admin"), methods, None);
+
+        assert_eq!(
+            plan.steps,
+            vec![vec![planned(Channel::Sms, "+48123456789")]]
+        );
+    }
+
+    #[test]
+    fn admin_without_contact_methods_is_reached_by_stored_contacts() {
+        let plan = DeliveryPlan::new(&admin("admin"), vec![], Some(rule("telegram,email")));
+
+        assert_eq!(
+            plan.steps,
+            vec![
+                vec![planned(Channel::Telegram, "admin-telegram")],
+                vec![planned(Channel::Email, "admin@example.com")],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn escalates_to_secondary_admin_after_response_timeout() {
+        let setup = Setup::new();
+        let service = setup.service();
+
+        service.poll().await;
+        assert_eq!(setup.sender.sent(), vec![primary()]);
+
+        setup.advance_secs(30);
+        service.poll().await;
+        assert_eq!(setup.sender.sent(), vec![primary()]);
+
+        setup.advance_secs(31);
+        service.poll().await;
+        assert_eq!(setup.sender.sent(), vec![primary(), secondary()]);
+
+        setup.advance_secs(600);
+        service.poll().await;
+        assert_eq!(setup.sender.sent(), vec![primary(), secondary()]);
+        assert!(
+            setup
+                .db
+                .endpoint(ENDPOINT)
+                .unwrap()
+                .ntf_is_second_notification_sent
+        );
+    }
+
+    #[tokio::test]
+    async fn secondary_admin_is_reminded_until_acknowledgement() {
+        let setup = Setup::new();
+        let service = setup.service_of(setup.db.clone().with_reminder_interval(Some(300)));
+        let reminders = || -> Vec<Option<i32>> {
+            setup
+                .sender
+                .notifications
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|x| x.reminder)
+                .collect()
+        };
+
+        service.poll().await;
+        setup.advance_secs(61);
+        service.poll().await;
+        setup.advance_secs(300);
+        service.poll().await;
+        assert_eq!(setup.sender.sent(), vec![primary(), secondary()]);
+
+        setup.advance_secs(1);
+        service.poll().await;
+        setup.advance_secs(301);
+        service.poll().await;
+        assert_eq!(
+            setup.sender.sent(),
+            vec![primary(), secondary(), secondary(), secondary()]
+        );
+        assert_eq!(reminders(), vec![None, None, Some(1), Some(2)]);
+
+        setup
+            .respond(&service, "secondary", ResponseAction::Acknowledge)
+            .await;
+        assert_eq!(
+            setup.db.responded_by(ENDPOINT),
+            Some("secondary".to_string())
+        );
+        setup.advance_secs(301);
+        service.poll().await;
+        assert_eq!(reminders().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn acknowledgement_stops_escalation() {
+        let setup = Setup::new();
+        let service = setup.service();
+
+        service.poll().await;
+        setup.advance_secs(10);
+        setup
+            .respond(&service, "primary", ResponseAction::Acknowledge)
+            .await;
+        setup.advance_secs(600);
+        service.poll().await;
+
+        assert_eq!(setup.sender.sent(), vec![primary()]);
+        assert_eq!(setup.db.responded_by(ENDPOINT), Some("primary".to_string()));
+        assert_eq!(
+            setup.sender.events(),
+            vec![OutageEvent::Acknowledged("primary".to_string())]
+        );
+    }
+
+    // Outage of another endpoint of the same admins, started a moment ago.
+    fn insert_outage(setup: &Setup, endpoint_id: EndpointId, severity: Severity) -> OutageId {
+        let mut endpoint = outage(
+            endpoint_id,
+            setup.clock.now() - chrono::Duration::seconds(1),
+        );
+        endpoint.conf_severity = severity.as_str().to_string();
+        let outage_id = endpoint.outage_id.unwrap();
+        setup.db.insert_endpoint(endpoint);
+        outage_id
+    }
+
+    fn grouped_outages(x: &NotificationData) -> Vec<OutageId> {
+        x.grouped.iter().map(|g| g.outage_id).collect()
+    }
+
+    #[tokio::test]
+    async fn outages_of_one_poll_are_summarized_by_most_severe_one() {
+        let setup = Setup::new();
+        setup.db.insert_endpoint(EndpointData {
+            conf_severity: Severity::Warning.as_str().to_string(),
+            ..setup.db.endpoint(ENDPOINT).unwrap()
+        });
+        let critical = insert_outage(&setup, ENDPOINT + 1, Severity::Critical);
+        let service = setup.service();
+
+        service.poll().await;
+
+        let notifications = setup.sender.notifications.lock().unwrap().clone();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].admin, "primary");
+        assert_eq!(notifications[0].outage_id, critical);
+        let mut grouped = grouped_outages(&notifications[0]);
+        grouped.sort();
+        let mut expected = vec![setup.outage_id, critical];
+        expected.sort();
+        assert_eq!(grouped, expected);
+    }
+
+    #[tokio::test]
+    async fn acknowledging_summary_acknowledges_every_outage_of_it() {
+        let setup = Setup::new();
+        insert_outage(&setup, ENDPOINT + 1, Severity::Critical);
+        let service = setup.service();
+
+        service.poll().await;
+        let summary = setup.sender.notifications.lock().unwrap()[0].clone();
+        let token = service.token_signer.verify(&summary.ack_token).unwrap();
+        assert!(token.group.is_some());
+        TestService::handle_response(
+            &service.db_executor,
+            &service.ntf_sender,
+            service.fallback_admin.as_ref(),
+            ResponseData {
+                admin: token.admin,
+                outage_id: token.outage_id,
+                endpoint: token.endpoint,
+                is_first: token.is_first,
+                action: ResponseAction::Acknowledge,
+                group: token.group,
+            },
+        )
+        .await;
+        setup.advance_secs(600);
+        service.poll().await;
+
+        assert_eq!(setup.sender.sent(), vec![primary()]);
+        assert_eq!(setup.db.responded_by(ENDPOINT), Some("primary".to_string()));
+        assert_eq!(
+            setup.db.responded_by(ENDPOINT + 1),
+            Some("primary".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn notifications_over_rate_limit_are_deferred_into_digest() {
+        let setup = Setup::new();
+        let mut service = setup.service();
+        service.grouping.rate_limit = Some(1);
+
+        service.poll().await;
+        setup
+            .respond(&service, "primary", ResponseAction::Acknowledge)
+            .await;
+        setup.advance_secs(10);
+        let deferred = [
+            insert_outage(&setup, ENDPOINT + 1, Severity::Critical),
+            insert_outage(&setup, ENDPOINT + 2, Severity::Critical),
+        ];
+        service.poll().await;
+        assert_eq!(setup.sender.sent(), vec![primary()]);
+
+        // Limit allows the next notification 600 s after the first one.
+        setup.advance_secs(600);
+        service.poll().await;
+
+        let notifications = setup.sender.notifications.lock().unwrap().clone();
+        assert_eq!(notifications.len(), 2);
+        assert_eq!(notifications[1].admin, "primary");
+        let mut grouped = grouped_outages(&notifications[1]);
+        grouped.sort();
+        let mut expected = deferred.to_vec();
+        expected.sort();
+        assert_eq!(grouped, expected);
+    }
+
+    #[tokio::test]
+    async fn notification_in_quiet_hours_is_deferred_until_they_end() {
+        let setup = Setup::new();
+        let service = setup.service();
+        service.poll().await;
+        setup
+            .respond(&service, "primary", ResponseAction::Acknowledge)
+            .await;
+        // Clock starts at 12:00 UTC.
+        setup.db.insert_quiet_hours(QuietHours {
+            admin_id: "primary".to_string(),
+            start_time: chrono::NaiveTime::from_hms_opt(11, 0, 0).unwrap(),
+            end_time: chrono::NaiveTime::from_hms_opt(13, 0, 0).unwrap(),
+        });
+        insert_outage(&setup, ENDPOINT + 1, Severity::Warning);
+
+        service.poll().await;
+        let claimed_at = setup
+            .db
+            .endpoint(ENDPOINT + 1)
+            .unwrap()
+            .ntf_is_being_handled_timestamp
+            .unwrap();
+        assert_eq!(
+            claimed_at + chrono::Duration::seconds(SECS_WAIT_WHEN_HANDLED.into()),
+            setup.clock.now().date().and_hms_opt(13, 0, 0).unwrap()
+        );
+
+        setup.advance_secs((SECS_WAIT_WHEN_HANDLED + 1).into());
+        service.poll().await;
+        assert_eq!(setup.sender.sent(), vec![primary()]);
+
+        setup.advance_secs(3600);
+        service.poll().await;
+        let notifications = setup.sender.notifications.lock().unwrap().clone();
+        assert_eq!(notifications.len(), 2);
+        assert_eq!(notifications[1].endpoint, ENDPOINT + 1);
+        assert_eq!(notifications[1].admin, "primary");
+    }
+
+    #[tokio::test]
+    async fn first_notification_waits_for_outages_to_group() {
+        let setup = Setup::new();
+        let service = setup.service_of(setup.db.clone().with_group_wait(30));
+
+        service.poll().await;
+        assert_eq!(setup.sender.sent(), vec![]);
+
+        setup.advance_secs(30);
+        service.poll().await;
+        assert_eq!(setup.sender.sent(), vec![primary()]);
+    }
+
+    #[tokio::test]
+    async fn notification_is_planned_for_contact_methods_of_admin() {
+        let setup = Setup::new();
+        setup.db.insert_contact_method(ContactMethod {
+            admin_id: "primary".to_string(),
+            channel: "sms".to_string(),
+            address: "+48123456789".to_string(),
+            rank: 1,
+        });
+        let service = setup.service();
+
+        service.poll().await;
+
+        let notifications = setup.sender.notifications.lock().unwrap().clone();
+        assert_eq!(
+            notifications[0].delivery_plan.steps,
+            vec![vec![planned(Channel::Sms, "+48123456789")]]
+        );
+    }
+
+    #[tokio::test]
+    async fn acknowledgement_of_secondary_admin_is_accepted_once() {
+        let setup = Setup::new();
+        let service = setup.service();
+
+        service.poll().await;
+        setup.advance_secs(61);
+        service.poll().await;
+        setup
+            .respond(&service, "secondary", ResponseAction::Acknowledge)
+            .await;
+        setup
+            .respond(&service, "primary", ResponseAction::Acknowledge)
+            .await;
+
+        assert_eq!(
+            setup.db.responded_by(ENDPOINT),
+            Some("secondary".to_string())
+        );
+        assert_eq!(
+            setup.sender.events(),
+            vec![OutageEvent::Acknowledged("secondary".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn response_of_admin_off_escalation_path_is_rejected() {
+        let setup = Setup::new();
+        setup.db.insert_admin(admin("intruder"));
+        let service = setup.service();
+
+        service.poll().await;
+        setup
+            .respond(&service, "intruder", ResponseAction::Acknowledge)
+            .await;
+
+        assert!(!setup.db.endpoint(ENDPOINT).unwrap().ntf_first_responded);
+        assert!(setup.sender.events().is_empty());
+    }
+
+    #[tokio::test]
+    async fn escalate_response_notifies_secondary_admin_on_next_poll() {
+        let setup = Setup::new();
+        let service = setup.service();
+
+        service.poll().await;
+        setup
+            .respond(&service, "primary", ResponseAction::Escalate)
+            .await;
+        // Deadline is moved to the time of the response, the next poll is always later.
+        setup.advance_secs(1);
+        service.poll().await;
+
+        assert_eq!(setup.sender.sent(), vec![primary(), secondary()]);
+    }
+
+    #[tokio::test]
+    async fn snooze_postpones_escalation() {
+        let setup = Setup::new();
+        let service = setup.service();
+
+        service.poll().await;
+        setup
+            .respond(
+                &service,
+                "primary",
+                ResponseAction::Snooze(Duration::from_secs(300)),
+            )
+            .await;
+        setup.advance_secs(300);
+        service.poll().await;
+        assert_eq!(setup.sender.sent(), vec![primary()]);
+
+        setup.advance_secs(61);
+        service.poll().await;
+        assert_eq!(setup.sender.sent(), vec![primary(), secondary()]);
+    }
+
+    #[tokio::test]
+    async fn undelivered_notification_is_retried_after_claim_expires() {
+        let setup = Setup::new();
+        let service = setup.service();
+        setup.sender.failing.store(true, Ordering::SeqCst);
+
+        service.poll().await;
+        setup.advance_secs(SECS_WAIT_WHEN_HANDLED.into());
+        service.poll().await;
+        assert_eq!(setup.sender.sent(), vec![primary()]);
+        assert!(
+            !setup
+                .db
+                .endpoint(ENDPOINT)
+                .unwrap()
+                .ntf_is_first_notification_sent
+        );
+
+        setup.sender.failing.store(false, Ordering::SeqCst);
+        setup.advance_secs(1);
+        service.poll().await;
+        assert_eq!(setup.sender.sent(), vec![primary(), primary()]);
+        assert!(
+            setup
+                .db
+                .endpoint(ENDPOINT)
+                .unwrap()
+                .ntf_is_first_notification_sent
+        );
+        let log: Vec<_> = setup
+            .db
+            .notification_log()
+            .into_iter()
+            .map(|e| {
+                (
+                    e.outage_id,
+                    e.endpoint_id,
+                    e.admin_id,
+                    e.escalation_level,
+                    e.result.status.is_failed(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            log,
+            vec![
+                (setup.outage_id, ENDPOINT, "primary".to_string(), 1, true),
+                (setup.outage_id, ENDPOINT, "primary".to_string(), 1, false),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_instances_notify_once() {
+        let setup = Setup::new();
+        let first = setup.service();
+        let second = setup.service_of(setup.db.instance(Uuid::new_v4()));
+
+        tokio::join!(first.poll(), second.poll());
+        assert_eq!(setup.sender.sent(), vec![primary()]);
+
+        setup.advance_secs(61);
+        tokio::join!(second.poll(), first.poll());
+        assert_eq!(setup.sender.sent(), vec![primary(), secondary()]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_claims_return_outage_once() {
+        let setup = Setup::new();
+        let claims = futures::future::join_all((0..8).map(|_| {
+            let db = setup.db.instance(Uuid::new_v4());
+            tokio::spawn(async move { db.get_endpoints_to_process().await.unwrap().len() })
+        }))
+        .await;
+        assert_eq!(claims.into_iter().map(|c| c.unwrap()).sum::<usize>(), 1);
+    }
+
+    #[tokio::test]
+    async fn claims_oldest_outages_first_up_to_limit() {
+        let setup = Setup::new();
+        let started = setup.clock.now();
+        for (endpoint_id, age_secs) in [(2, 10), (3, 30), (4, 20)] {
+            setup.db.insert_endpoint(outage(
+                endpoint_id,
+                started - chrono::Duration::seconds(age_secs),
+            ));
+        }
+
+        let claimed = setup.db.get_endpoints_to_process().await.unwrap();
+        let claimed: Vec<EndpointId> = claimed.iter().map(|x| x.endpoint_id).collect();
+        assert_eq!(claimed, vec![3, 4]);
+
+        let peer = setup.db.instance(Uuid::new_v4());
+        let claimed = peer.get_endpoints_to_process().await.unwrap();
+        let claimed: Vec<EndpointId> = claimed.iter().map(|x| x.endpoint_id).collect();
+        assert_eq!(claimed, vec![2, ENDPOINT]);
+    }
+
+    #[tokio::test]
+    async fn outage_of_crashed_instance_is_taken_over_after_claim_expires() {
+        let setup = Setup::new();
+        let crashed = setup.db.instance(Uuid::new_v4());
+        let service = setup.service();
+
+        // Instance claims the outage and dies before notifying anyone.
+        assert_eq!(crashed.get_endpoints_to_process().await.unwrap().len(), 1);
+        service.poll().await;
+        setup.advance_secs(SECS_WAIT_WHEN_HANDLED.into());
+        service.poll().await;
+        assert!(setup.sender.sent().is_empty());
+
+        setup.advance_secs(1);
+        service.poll().await;
+        assert_eq!(setup.sender.sent(), vec![primary()]);
+    }
+
+    #[tokio::test]
+    async fn resolution_is_reported_once() {
+        let setup = Setup::new();
+        let service = setup.service();
+
+        service.poll().await;
+        setup.db.set_endpoint_up(ENDPOINT);
+        service.poll().await;
+        service.poll().await;
+
+        assert_eq!(setup.sender.events(), vec![OutageEvent::Resolved]);
+    }
+
+    #[tokio::test]
+    async fn resolution_of_crashed_instance_is_reported_after_claim_expires() {
+        let setup = Setup::new();
+        let crashed = setup.db.instance(Uuid::new_v4());
+        let service = setup.service();
+
+        service.poll().await;
+        setup.db.set_endpoint_up(ENDPOINT);
+        // Instance claims the resolution and dies before reporting it.
+        assert_eq!(
+            crashed
+                .get_resolved_outages_to_process()
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        setup.advance_secs(SECS_WAIT_WHEN_HANDLED.into());
+        service.poll().await;
+        assert!(setup.sender.events().is_empty());
+
+        setup.advance_secs(1);
+        service.poll().await;
+        service.poll().await;
+        assert_eq!(setup.sender.events(), vec![OutageEvent::Resolved]);
+    }
+
+    #[tokio::test]
+    async fn stale_claim_cannot_mark_notification_sent() {
+        let setup = Setup::new();
+        let stale = setup.db.instance(Uuid::new_v4());
+        let service = setup.service();
+
+        let claimed = stale.get_endpoints_to_process().await.unwrap();
+        setup.advance_secs((SECS_WAIT_WHEN_HANDLED + 1).into());
+        service.poll().await;
+        assert_eq!(setup.sender.sent(), vec![primary()]);
+
+        let marked = stale
+            .mark_first_notification_sent(
+                ENDPOINT,
+                setup.outage_id,
+                claimed[0].ntf_claim_generation,
+            )
+            .await
+            .unwrap();
+        assert!(!marked);
+    }
+
+    #[tokio::test]
+    async fn write_after_takeover_is_rejected_and_counted() {
+        let setup = Setup::new();
+        let peer_id = Uuid::new_v4();
+        let peer = setup.db.instance(peer_id);
+        let service = setup.service_with(
+            setup.db.clone(),
+            TakeoverSender {
+                clock: setup.clock.clone(),
+                peer,
+            },
+        );
+
+        service.poll().await;
+
+        assert_eq!(service.metrics().stale_claim_writes(), 1);
+        let endpoint = setup.db.endpoint(ENDPOINT).unwrap();
+        assert!(!endpoint.ntf_is_first_notification_sent);
+        assert_eq!(endpoint.ntf_is_being_handled_service_id, Some(peer_id));
+    }
+
+    #[tokio::test]
+    async fn delivered_notification_is_not_sent_again_after_crash() {
+        let setup = Setup::new();
+        let crashed = setup.db.instance(Uuid::new_v4());
+        let service = setup.service();
+
+        // Instance delivers the first notification and dies before marking it as sent.
+        assert_eq!(crashed.get_endpoints_to_process().await.unwrap().len(), 1);
+        let delivery = first_delivery(setup.outage_id);
+        crashed
+            .record_pending_deliveries(std::slice::from_ref(&delivery))
+            .await
+            .unwrap();
+        crashed
+            .complete_deliveries(&[delivery.idempotency_key])
+            .await
+            .unwrap();
+
+        setup.advance_secs((SECS_WAIT_WHEN_HANDLED + 1).into());
+        service.poll().await;
+        assert!(setup.sender.sent().is_empty());
+        assert!(
+            setup
+                .db
+                .endpoint(ENDPOINT)
+                .unwrap()
+                .ntf_is_first_notification_sent
+        );
+
+        setup.advance_secs(ALLOWED_RESPONSE_SECS as i64 + 1);
+        service.poll().await;
+        assert_eq!(setup.sender.sent(), vec![secondary()]);
+    }
+
+    #[tokio::test]
+    async fn pending_delivery_is_retried_with_the_same_key() {
+        let setup = Setup::new();
+        let crashed = setup.db.instance(Uuid::new_v4());
+        let service = setup.service();
+
+        // Instance dies while sending the first notification.
+        assert_eq!(crashed.get_endpoints_to_process().await.unwrap().len(), 1);
+        let delivery = first_delivery(setup.outage_id);
+        crashed
+            .record_pending_deliveries(std::slice::from_ref(&delivery))
+            .await
+            .unwrap();
+
+        setup.advance_secs((SECS_WAIT_WHEN_HANDLED + 1).into());
+        service.poll().await;
+        assert_eq!(setup.sender.sent(), vec![primary()]);
+        let sent = setup.sender.notifications.lock().unwrap()[0].clone();
+        assert_eq!(
+            sent.idempotency_key(Channel::Telegram),
+            delivery.idempotency_key
+        );
+        assert_eq!(
+            setup.db.is_delivery_completed(delivery.idempotency_key),
+            Some(true)
+        );
+    }
+
+    #[tokio::test]
+    async fn removed_primary_admin_is_skipped_to_secondary_admin() {
+        let setup = Setup::new();
+        setup.db.remove_admin(&"primary".to_string());
+        let service = setup.service();
+
+        service.poll().await;
+        assert!(setup.sender.sent().is_empty());
+        assert!(setup.db.dead_letters().is_empty());
+
+        setup.advance_secs(1);
+        service.poll().await;
+        assert_eq!(setup.sender.sent(), vec![secondary()]);
+    }
+
+    #[tokio::test]
+    async fn fallback_admin_is_paged_instead_of_removed_secondary_admin() {
+        let setup = Setup::new();
+        setup.db.remove_admin(&"secondary".to_string());
+        setup.db.insert_admin(admin("fallback"));
+        let service = setup
+            .service()
+            .with_fallback_admin(Some("fallback".to_string()));
+
+        service.poll().await;
+        setup.advance_secs(ALLOWED_RESPONSE_SECS as i64 + 1);
+        service.poll().await;
+        assert_eq!(
+            setup.sender.sent(),
+            vec![primary(), ("fallback".to_string(), false)]
+        );
+        assert!(setup.db.dead_letters().is_empty());
+
+        setup
+            .respond(&service, "fallback", ResponseAction::Acknowledge)
+            .await;
+        assert_eq!(
+            setup.db.responded_by(ENDPOINT),
+            Some("fallback".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn outage_of_removed_admin_without_fallback_is_dead_lettered() {
+        let setup = Setup::new();
+        setup.db.remove_admin(&"secondary".to_string());
+        let service = setup.service();
+
+        service.poll().await;
+        setup.advance_secs(ALLOWED_RESPONSE_SECS as i64 + 1);
+        service.poll().await;
+        assert_eq!(setup.sender.sent(), vec![primary()]);
+        let dead_letters = setup.db.dead_letters();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].outage_id, Some(setup.outage_id));
+        assert!(dead_letters[0].reason.contains("secondary"));
+        assert_eq!(service.metrics().dead_lettered_outages(), 1);
+        assert!(!setup.db.endpoint(ENDPOINT).unwrap().ntf_is_being_handled);
+
+        // Dead-lettered outage is not claimed again.
+        setup.advance_secs((SECS_WAIT_WHEN_HANDLED + 1).into());
+        service.poll().await;
+        assert_eq!(setup.sender.sent().len(), 1);
+        assert!(setup
+            .db
+            .get_endpoints_to_process()
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn endpoint_down_without_outage_is_dead_lettered_and_fallback_alerted() {
+        let setup = Setup::new();
+        setup.db.insert_admin(admin("fallback"));
+        let mut endpoint = outage(2, setup.clock.now());
+        endpoint.outage_id = None;
+        setup.db.insert_endpoint(endpoint);
+        setup.advance_secs(1);
+        let service = setup
+            .service()
+            .with_fallback_admin(Some("fallback".to_string()));
+
+        service.poll().await;
+        let alerts: Vec<NotificationData> = setup
+            .sender
+            .notifications
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|x| x.undeliverable.is_some())
+            .cloned()
+            .collect();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].admin, "fallback");
+        assert_eq!(alerts[0].endpoint, 2);
+        let dead_letters = setup.db.dead_letters();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].endpoint_id, 2);
+        assert_eq!(dead_letters[0].outage_id, None);
+    }
+}
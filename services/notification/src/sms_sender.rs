@@ -0,0 +1,474 @@
+use std::{collections::BTreeMap, env, sync::Arc};
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::post,
+    Form, Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use tokio::sync::Mutex;
+
+use crate::{
+    config::read_secret,
+    db_executor::MyDBQueryExecutor,
+    error::{NotificationError, Result},
+    notification_service::{
+        Channel, DBQueryExecutor, DeliveryResult, NotificationData, NotificationSender,
+    },
+    templates::{TemplateContext, Templates},
+};
+
+#[async_trait::async_trait]
+pub trait SmsProvider: Send + Sync {
+    // Returns the id the provider assigned to the message, if it reports one.
+    async fn send_sms(&self, to: &str, body: &str) -> Result<Option<String>>;
+}
+
+#[derive(Debug, Clone)]
+pub struct TwilioConfig {
+    // Base url of a Twilio compatible API.
+    pub api_url: String,
+    pub account_sid: String,
+    pub auth_token: String,
+    pub from: String,
+    // Url the provider reports delivery status to.
+    pub status_callback_url: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct TwilioSmsProvider {
+    client: reqwest::Client,
+    config: TwilioConfig,
+}
+
+impl TwilioSmsProvider {
+    pub fn new(config: TwilioConfig) -> TwilioSmsProvider {
+        TwilioSmsProvider {
+            client: reqwest::Client::new(),
+            config,
+        }
+    }
+
+    /*
+        Twilio signs callbacks with HMAC-SHA1 of the callback url followed by
+        all POST parameters sorted by name, keyed with the auth token.
+    */
+    fn is_valid_callback_signature(
+        &self,
+        url: &str,
+        params: &BTreeMap<String, String>,
+        signature: &str,
+    ) -> bool {
+        let Ok(signature) = STANDARD.decode(signature) else {
+            return false;
+        };
+        let mut mac = Hmac::<Sha1>::new_from_slice(self.config.auth_token.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(url.as_bytes());
+        for (key, value) in params {
+            mac.update(key.as_bytes());
+            mac.update(value.as_bytes());
+        }
+        mac.verify_slice(&signature).is_ok()
+    }
+}
+
+#[async_trait::async_trait]
+impl SmsProvider for TwilioSmsProvider {
+    async fn send_sms(&self, to: &str, body: &str) -> Result<Option<String>> {
+        let url = format!(
+            "{}/2010-04-01/Accounts/{}/Messages.json",
+            self.config.api_url.trim_end_matches('/'),
+            self.config.account_sid
+        );
+        let mut form = vec![("To", to), ("From", &self.config.from), ("Body", body)];
+        if let Some(callback_url) = &self.config.status_callback_url {
+            form.push(("StatusCallback", callback_url));
+        }
+        let response = self
+            .client
+            .post(url)
+            .basic_auth(&self.config.account_sid, Some(&self.config.auth_token))
+            .form(&form)
+            .send()
+            .await?;
+        let status = response.status();
+        let body: serde_json::Value = response.json().await?;
+        if !status.is_success() {
+            return Err(NotificationError::Provider(format!(
+                "responded with {}: {}",
+                status, body
+            )));
+        }
+        Ok(body["sid"].as_str().map(str::to_string))
+    }
+}
+
+// Provider that only records messages, used for local runs and tests.
+#[derive(Clone, Default)]
+pub struct FakeSmsProvider {
+    sent: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+impl FakeSmsProvider {
+    #[cfg(test)]
+    pub async fn sent_messages(&self) -> Vec<(String, String)> {
+        self.sent.lock().await.clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl SmsProvider for FakeSmsProvider {
+    async fn send_sms(&self, to: &str, body: &str) -> Result<Option<String>> {
+        log::info!("Fake SMS to {}: {}", to, body);
+        self.sent
+            .lock()
+            .await
+            .push((to.to_string(), body.to_string()));
+        Ok(None)
+    }
+}
+
+#[derive(Clone)]
+pub enum ImplementedSmsProvider {
+    Twilio(TwilioSmsProvider),
+    Fake(FakeSmsProvider),
+}
+
+#[async_trait::async_trait]
+impl SmsProvider for ImplementedSmsProvider {
+    async fn send_sms(&self, to: &str, body: &str) -> Result<Option<String>> {
+        match &self {
+            ImplementedSmsProvider::Twilio(p) => p.send_sms(to, body).await,
+            ImplementedSmsProvider::Fake(p) => p.send_sms(to, body).await,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SmsConfig {
+    pub provider: String,
+    pub max_length: usize,
+}
+
+impl SmsConfig {
+    // Single GSM-7 encoded message.
+    const DEFAULT_MAX_LENGTH: usize = 160;
+
+    // SMS channel is enabled only when SMS_PROVIDER is set to twilio or fake.
+    pub fn from_env() -> anyhow::Result<Option<SmsConfig>> {
+        let Ok(provider) = env::var("SMS_PROVIDER") else {
+            return Ok(None);
+        };
+        let max_length = match env::var("SMS_MAX_LENGTH") {
+            Ok(max_length) => max_length.parse()?,
+            Err(_) => Self::DEFAULT_MAX_LENGTH,
+        };
+        Ok(Some(SmsConfig {
+            provider,
+            max_length,
+        }))
+    }
+
+    /*
+        Delivery status callbacks are requested only when the acknowledgement link listener,
+        which serves them, is enabled.
+    */
+    pub fn create_provider(
+        &self,
+        ack_link_base_url: Option<&str>,
+    ) -> anyhow::Result<ImplementedSmsProvider> {
+        match self.provider.as_str() {
+            "twilio" => {
+                let required = |name: &str| {
+                    read_secret(name).ok_or_else(|| {
+                        anyhow::anyhow!("{} must be set for the twilio provider", name)
+                    })
+                };
+                Ok(ImplementedSmsProvider::Twilio(TwilioSmsProvider::new(
+                    TwilioConfig {
+                        api_url: env::var("SMS_API_URL")
+                            .unwrap_or_else(|_| "https://api.twilio.com".to_string()),
+                        account_sid: required("TWILIO_ACCOUNT_SID")?,
+                        auth_token: required("TWILIO_AUTH_TOKEN")?,
+                        from: required("SMS_FROM")?,
+                        status_callback_url: ack_link_base_url.map(|base_url| {
+                            format!(
+                                "{}{}",
+                                base_url.trim_end_matches('/'),
+                                SmsNotificationSender::STATUS_CALLBACK_PATH
+                            )
+                        }),
+                    },
+                )))
+            }
+            "fake" => Ok(ImplementedSmsProvider::Fake(FakeSmsProvider::default())),
+            provider => Err(anyhow::anyhow!(
+                "unknown SMS provider {}, expected twilio or fake",
+                provider
+            )),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SmsNotificationSender<D = MyDBQueryExecutor> {
+    provider: ImplementedSmsProvider,
+    max_length: usize,
+    db_executor: D,
+    templates: Templates,
+}
+
+impl SmsNotificationSender {
+    pub const STATUS_CALLBACK_PATH: &'static str = "/sms/status";
+    const ELLIPSIS: &'static str = "...";
+
+    /*
+        Fits the message into max_length characters.
+        The suffix (acknowledgement link) is kept intact when possible, the text is shortened instead.
+    */
+    fn fit_to_length(text: &str, suffix: &str, max_length: usize) -> String {
+        let text_len = text.chars().count();
+        let suffix_len = suffix.chars().count();
+        if text_len + suffix_len <= max_length {
+            return format!("{}{}", text, suffix);
+        }
+        let (suffix, suffix_len) = if suffix_len + Self::ELLIPSIS.len() < max_length {
+            (suffix, suffix_len)
+        } else {
+            ("", 0)
+        };
+        let room = max_length.saturating_sub(suffix_len);
+        if text_len <= room {
+            return format!("{}{}", text, suffix);
+        }
+        let shortened: String = text
+            .chars()
+            .take(room.saturating_sub(Self::ELLIPSIS.len()))
+            .collect();
+        format!("{}{}{}", shortened, Self::ELLIPSIS, suffix)
+    }
+}
+
+impl<D: DBQueryExecutor + Clone + 'static> SmsNotificationSender<D> {
+    pub fn new(
+        provider: ImplementedSmsProvider,
+        max_length: usize,
+        db_executor: D,
+        templates: Templates,
+    ) -> SmsNotificationSender<D> {
+        SmsNotificationSender {
+            provider,
+            max_length,
+            db_executor,
+            templates,
+        }
+    }
+
+    fn render_body(&self, x: &NotificationData) -> Result<String> {
+        let text = self
+            .templates
+            .render(Templates::SMS, &TemplateContext::notification(x))?;
+        let suffix = x
+            .ack_links
+            .as_ref()
+            .map(|links| format!(" Ack: {}", links.acknowledge))
+            .unwrap_or_default();
+        Ok(SmsNotificationSender::fit_to_length(
+            &text,
+            &suffix,
+            self.max_length,
+        ))
+    }
+
+    // Routes receiving delivery status callbacks of providers that support them.
+    pub fn status_callback_router(&self) -> Option<Router> {
+        match &self.provider {
+            ImplementedSmsProvider::Twilio(provider) => Some(
+                Router::new()
+                    .route(
+                        SmsNotificationSender::STATUS_CALLBACK_PATH,
+                        post(Self::twilio_status_callback),
+                    )
+                    .with_state((provider.clone(), self.db_executor.clone())),
+            ),
+            ImplementedSmsProvider::Fake(_) => None,
+        }
+    }
+
+    async fn twilio_status_callback(
+        State((provider, db_executor)): State<(TwilioSmsProvider, D)>,
+        headers: HeaderMap,
+        Form(params): Form<BTreeMap<String, String>>,
+    ) -> StatusCode {
+        let signature = headers
+            .get("X-Twilio-Signature")
+            .and_then(|s| s.to_str().ok())
+            .unwrap_or_default();
+        let url = provider
+            .config
+            .status_callback_url
+            .as_deref()
+            .unwrap_or_default();
+        if !provider.is_valid_callback_signature(url, &params, signature) {
+            log::warn!("Rejected SMS status callback with invalid signature");
+            return StatusCode::FORBIDDEN;
+        }
+        let (Some(message_id), Some(status)) =
+            (params.get("MessageSid"), params.get("MessageStatus"))
+        else {
+            return StatusCode::BAD_REQUEST;
+        };
+        match db_executor
+            .update_sms_delivery_status(
+                message_id.clone(),
+                status.clone(),
+                params.get("ErrorCode").cloned(),
+            )
+            .await
+        {
+            Ok(_) => {
+                log::info!("SMS {} status changed to {}", message_id, status);
+                StatusCode::NO_CONTENT
+            }
+            Err(e) => {
+                log::error!("Error recording status of SMS {}: {}", message_id, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<D: DBQueryExecutor + Clone + 'static> NotificationSender for SmsNotificationSender<D> {
+    async fn send_notification(&self, x: NotificationData) -> Vec<DeliveryResult> {
+        log::info!("Attempting to concat {} by sms {}", x.admin, x.phone_number);
+
+        let body = match self.render_body(&x) {
+            Ok(body) => body,
+            Err(e) => {
+                log::error!("Failed to render sms to {}: {}", x.phone_number, e);
+                return vec![DeliveryResult::failed(Channel::Sms, x.phone_number, e)];
+            }
+        };
+        match self.provider.send_sms(&x.phone_number, &body).await {
+            Ok(message_id) => {
+                log::info!("Message sent successfully");
+                if let Some(message_id) = message_id {
+                    if let Err(e) = self
+                        .db_executor
+                        .record_sms_sent(message_id, x.endpoint, x.outage_id, x.admin)
+                        .await
+                    {
+                        log::error!("Error recording sent SMS: {}", e);
+                    }
+                }
+                vec![DeliveryResult::delivered(Channel::Sms, x.phone_number)]
+            }
+            Err(e) => {
+                log::error!("Failed to send message: {}", e);
+                vec![DeliveryResult::failed(Channel::Sms, x.phone_number, e)]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        ack_link_listener::AckLinks, clock::SystemClock, memory_executor::InMemoryDBQueryExecutor,
+        notification_service::DeliveryStatus,
+    };
+
+    fn sender(
+        provider: FakeSmsProvider,
+        max_length: usize,
+    ) -> SmsNotificationSender<InMemoryDBQueryExecutor<SystemClock>> {
+        SmsNotificationSender::new(
+            ImplementedSmsProvider::Fake(provider),
+            max_length,
+            InMemoryDBQueryExecutor::new(SystemClock, 40, 2, Uuid::new_v4()),
+            Templates::built_in(),
+        )
+    }
+
+    #[test]
+    fn short_message_is_not_changed() {
+        assert_eq!(
+            SmsNotificationSender::fit_to_length("down", " Ack: x", 20),
+            "down Ack: x"
+        );
+        assert_eq!(
+            SmsNotificationSender::fit_to_length("down", " Ack: x", 11),
+            "down Ack: x"
+        );
+    }
+
+    #[test]
+    fn text_is_shortened_to_keep_suffix() {
+        let sms = SmsNotificationSender::fit_to_length("0123456789", " Ack", 10);
+        assert_eq!(sms, "012... Ack");
+    }
+
+    #[test]
+    fn suffix_is_dropped_when_it_leaves_no_room_for_text() {
+        assert_eq!(
+            SmsNotificationSender::fit_to_length("0123456789", " Ack: link", 10),
+            "0123456789"
+        );
+        assert_eq!(
+            SmsNotificationSender::fit_to_length("0123456789ab", " Ack: link", 10),
+            "0123456..."
+        );
+    }
+
+    #[test]
+    fn characters_are_counted_instead_of_bytes() {
+        let sms = SmsNotificationSender::fit_to_length("żółć żółć", " Ack", 10);
+        assert_eq!(sms, "żół... Ack");
+        assert_eq!(sms.chars().count(), 10);
+    }
+
+    #[tokio::test]
+    async fn notification_is_sent_with_ack_link() {
+        let provider = FakeSmsProvider::default();
+        let mut x = NotificationData::example("admin", 1);
+        x.ack_links = Some(AckLinks::new("https://irio.example.com", "token"));
+
+        let results = sender(provider.clone(), 1000)
+            .send_notification(x.clone())
+            .await;
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].channel, Channel::Sms);
+        assert_eq!(results[0].status, DeliveryStatus::Delivered);
+        let sent = provider.sent_messages().await;
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, x.phone_number);
+        assert!(sent[0].1.contains(&x.http_address));
+        assert!(sent[0]
+            .1
+            .ends_with(" Ack: https://irio.example.com/ack?token=token"));
+    }
+
+    #[tokio::test]
+    async fn notification_is_fitted_to_max_length() {
+        let provider = FakeSmsProvider::default();
+        let mut x = NotificationData::example("admin", 1);
+        x.ack_links = Some(AckLinks::new("https://irio.example.com", "token"));
+
+        sender(provider.clone(), 70).send_notification(x).await;
+
+        let sent = provider.sent_messages().await;
+        assert_eq!(sent[0].1.chars().count(), 70);
+        assert!(sent[0]
+            .1
+            .ends_with("... Ack: https://irio.example.com/ack?token=token"));
+    }
+}
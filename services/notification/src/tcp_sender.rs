@@ -0,0 +1,881 @@
+use std::{env, fs, time::Duration};
+
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::{
+    io::{
+        AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, Lines, ReadHalf,
+        WriteHalf,
+    },
+    net::TcpStream,
+    sync::mpsc::{self, error::TrySendError, Receiver, Sender},
+};
+use uuid::Uuid;
+
+use crate::{
+    ack_link_listener::AckLinkResponseListener,
+    ack_token::AckTokenSigner,
+    config::read_secret,
+    error::{NotificationError, Result},
+    notification_service::{
+        Channel, DeliveryResult, NotificationData, NotificationSender, OutageEvent, OutageUpdate,
+        ResponseAction, ResponseData, ResponseListener,
+    },
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AckAction {
+    #[default]
+    Acknowledge,
+    Escalate,
+    Snooze,
+}
+
+/*
+    Frames are JSON objects, one per line, tagged with their type.
+    The connecting side sends hello and waits for welcome before sending anything else.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Frame {
+    Hello {
+        version: u32,
+        nonce: String,
+        timestamp: i64,
+        // HMAC-SHA256 of "hello.<nonce>.<timestamp>", present when a shared secret is configured.
+        mac: Option<String>,
+    },
+    Welcome {
+        // HMAC-SHA256 of "welcome.<nonce from hello>", present when a shared secret is configured.
+        mac: Option<String>,
+    },
+    Notification {
+        id: Uuid,
+        endpoint: i32,
+        outage_id: Uuid,
+        escalation_level: i32,
+        admin: String,
+        http_address: String,
+        ack_token: String,
+        acknowledge_url: Option<String>,
+    },
+    OutageUpdate {
+        endpoint: i32,
+        outage_id: Uuid,
+        http_address: String,
+        // acknowledged or resolved
+        event: String,
+        acknowledged_by: Option<String>,
+    },
+    // Sent by consumers, answered with ack_result carrying the same id.
+    Ack {
+        id: Option<String>,
+        token: String,
+        #[serde(default)]
+        action: AckAction,
+        // Only for snooze, defaults to 30.
+        minutes: Option<u64>,
+    },
+    AckResult {
+        id: Option<String>,
+        // The token was valid and the response was passed on to be executed.
+        accepted: bool,
+        error: Option<String>,
+    },
+}
+
+impl Frame {
+    const PROTOCOL_VERSION: u32 = 1;
+
+    fn encode(&self) -> serde_json::Result<String> {
+        let mut line = serde_json::to_string(self)?;
+        line.push('\n');
+        Ok(line)
+    }
+}
+
+fn sign(secret: Option<&str>, message: &str) -> Option<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret?.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(message.as_bytes());
+    Some(hex::encode(mac.finalize().into_bytes()))
+}
+
+// Without a shared secret every message is considered signed.
+fn is_signed(secret: Option<&str>, message: &str, mac: Option<&str>) -> bool {
+    let Some(secret) = secret else {
+        return true;
+    };
+    let Some(Ok(mac)) = mac.map(hex::decode) else {
+        return false;
+    };
+    let mut expected =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    expected.update(message.as_bytes());
+    expected.verify_slice(&mac).is_ok()
+}
+
+#[derive(Debug, Clone)]
+pub struct TcpConfig {
+    pub address: String,
+    pub tls: bool,
+    // Name the server certificate is checked against, defaults to the host part of the address.
+    pub tls_domain: Option<String>,
+    // Additional root certificate in PEM format, e.g. of a private CA.
+    pub tls_ca_file: Option<String>,
+    pub shared_secret: Option<String>,
+    // Frames kept while disconnected, newest frames are dropped when it is full.
+    pub buffer_size: usize,
+}
+
+impl TcpConfig {
+    const DEFAULT_BUFFER_SIZE: usize = 1024;
+
+    pub fn from_env(address: String) -> anyhow::Result<TcpConfig> {
+        let tls = match env::var("TCP_TLS") {
+            Ok(tls) => tls.parse()?,
+            Err(_) => false,
+        };
+        let buffer_size = match env::var("TCP_BUFFER_SIZE") {
+            Ok(buffer_size) => buffer_size.parse()?,
+            Err(_) => Self::DEFAULT_BUFFER_SIZE,
+        };
+        Ok(TcpConfig {
+            address,
+            tls,
+            tls_domain: env::var("TCP_TLS_DOMAIN").ok(),
+            tls_ca_file: env::var("TCP_TLS_CA_FILE").ok(),
+            shared_secret: read_secret("TCP_SHARED_SECRET"),
+            buffer_size,
+        })
+    }
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+type FrameReader = Lines<BufReader<ReadHalf<Box<dyn Stream>>>>;
+type FrameWriter = WriteHalf<Box<dyn Stream>>;
+
+struct TcpConnection {
+    config: TcpConfig,
+    tls_connector: Option<tokio_native_tls::TlsConnector>,
+    response_handler: TcpResponseHandler,
+}
+
+impl TcpConnection {
+    const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+    const MAX_BACKOFF: Duration = Duration::from_secs(60);
+    const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn new(
+        config: TcpConfig,
+        response_handler: TcpResponseHandler,
+    ) -> anyhow::Result<TcpConnection> {
+        let tls_connector = if config.tls {
+            let mut builder = native_tls::TlsConnector::builder();
+            if let Some(ca_file) = &config.tls_ca_file {
+                builder
+                    .add_root_certificate(native_tls::Certificate::from_pem(&fs::read(ca_file)?)?);
+            }
+            Some(tokio_native_tls::TlsConnector::from(builder.build()?))
+        } else {
+            None
+        };
+        Ok(TcpConnection {
+            config,
+            tls_connector,
+            response_handler,
+        })
+    }
+
+    async fn connect(&self) -> anyhow::Result<Box<dyn Stream>> {
+        let tcp_stream = TcpStream::connect(&self.config.address).await?;
+        let Some(tls_connector) = &self.tls_connector else {
+            return Ok(Box::new(tcp_stream));
+        };
+        let domain = match &self.config.tls_domain {
+            Some(domain) => domain.as_str(),
+            None => self
+                .config
+                .address
+                .rsplit_once(':')
+                .map_or(self.config.address.as_str(), |(host, _)| host),
+        };
+        Ok(Box::new(tls_connector.connect(domain, tcp_stream).await?))
+    }
+
+    async fn handshake(
+        &self,
+        reader: &mut FrameReader,
+        writer: &mut FrameWriter,
+    ) -> anyhow::Result<()> {
+        let mut nonce = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let nonce = hex::encode(nonce);
+        let timestamp = chrono::Utc::now().timestamp();
+        let hello = Frame::Hello {
+            version: Frame::PROTOCOL_VERSION,
+            mac: sign(
+                self.config.shared_secret.as_deref(),
+                &format!("hello.{}.{}", nonce, timestamp),
+            ),
+            nonce: nonce.clone(),
+            timestamp,
+        };
+        writer.write_all(hello.encode()?.as_bytes()).await?;
+        let line = tokio::time::timeout(Self::HANDSHAKE_TIMEOUT, reader.next_line())
+            .await
+            .map_err(|_| anyhow::anyhow!("timed out waiting for welcome"))??
+            .ok_or_else(|| anyhow::anyhow!("connection closed during handshake"))?;
+        match serde_json::from_str(&line)? {
+            Frame::Welcome { mac }
+                if is_signed(
+                    self.config.shared_secret.as_deref(),
+                    &format!("welcome.{}", nonce),
+                    mac.as_deref(),
+                ) =>
+            {
+                Ok(())
+            }
+            Frame::Welcome { .. } => Err(anyhow::anyhow!(
+                "welcome is not signed with the shared secret"
+            )),
+            frame => Err(anyhow::anyhow!("expected welcome, got {:?}", frame)),
+        }
+    }
+
+    async fn open(&self) -> anyhow::Result<(FrameReader, FrameWriter)> {
+        let (reader, mut writer) = tokio::io::split(self.connect().await?);
+        let mut reader = BufReader::new(reader).lines();
+        self.handshake(&mut reader, &mut writer).await?;
+        Ok((reader, writer))
+    }
+
+    // Frame that failed to be written is sent again after reconnecting.
+    async fn run(self, mut frames: Receiver<String>) {
+        let mut pending: Option<String> = None;
+        let mut backoff = Self::INITIAL_BACKOFF;
+        loop {
+            match self.open().await {
+                Ok((mut reader, mut writer)) => {
+                    log::info!("Connected to {}", self.config.address);
+                    backoff = Self::INITIAL_BACKOFF;
+                    loop {
+                        let frame = match pending.take() {
+                            Some(frame) => frame,
+                            None => tokio::select! {
+                                frame = frames.recv() => match frame {
+                                    Some(frame) => frame,
+                                    None => return,
+                                },
+                                line = reader.next_line() => match line {
+                                    Ok(Some(line)) => {
+                                        match self.response_handler.handle_line(&self.config.address, &line).await {
+                                            Some(reply) => reply,
+                                            None => continue,
+                                        }
+                                    }
+                                    Ok(None) => {
+                                        log::warn!("{} closed the connection", self.config.address);
+                                        break;
+                                    }
+                                    Err(e) => {
+                                        log::warn!("Failed to read from {}: {}", self.config.address, e);
+                                        break;
+                                    }
+                                },
+                            },
+                        };
+                        if let Err(e) = writer.write_all(frame.as_bytes()).await {
+                            log::warn!("Failed to write to {}: {}", self.config.address, e);
+                            pending = Some(frame);
+                            break;
+                        }
+                    }
+                }
+                Err(e) => log::warn!("Failed to connect to {}: {}", self.config.address, e),
+            }
+            log::info!("Reconnecting to {} in {:?}", self.config.address, backoff);
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(Self::MAX_BACKOFF);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct TcpNotificationSender {
+    frames: mpsc::Sender<String>,
+    server_address: String,
+}
+
+impl TcpNotificationSender {
+    /*
+        Connection is kept by a background task, so the server doesn't have to be up
+        when the service starts. Frames are buffered while disconnected.
+        Acknowledgements sent back over the connection are passed to the response handler.
+    */
+    pub fn new(
+        config: TcpConfig,
+        response_handler: TcpResponseHandler,
+    ) -> anyhow::Result<TcpNotificationSender> {
+        let (frames, receiver) = mpsc::channel(config.buffer_size.max(1));
+        let server_address = config.address.clone();
+        let connection = TcpConnection::new(config, response_handler)?;
+        tokio::spawn(connection.run(receiver));
+        Ok(TcpNotificationSender {
+            frames,
+            server_address,
+        })
+    }
+
+    fn enqueue(&self, frame: Frame) -> anyhow::Result<()> {
+        let line = frame.encode()?;
+        let result = match self.frames.try_send(line) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(anyhow::anyhow!(
+                "buffer for {} is full",
+                self.server_address
+            )),
+            Err(TrySendError::Closed(_)) => Err(anyhow::anyhow!(
+                "connection to {} is closed",
+                self.server_address
+            )),
+        };
+        match &result {
+            Ok(()) => log::info!("Message queued successfully"),
+            Err(e) => log::error!("Failed to send message: {}", e),
+        }
+        result
+    }
+}
+
+#[async_trait::async_trait]
+impl NotificationSender for TcpNotificationSender {
+    async fn send_notification(&self, x: NotificationData) -> Vec<DeliveryResult> {
+        log::info!(
+            "Attempting to contact {} by tcp {}",
+            x.admin,
+            self.server_address
+        );
+        let result = self.enqueue(Frame::Notification {
+            // Stays the same when the notification is sent again, so that consumers can deduplicate.
+            id: x.idempotency_key(Channel::Tcp),
+            endpoint: x.endpoint,
+            outage_id: x.outage_id,
+            escalation_level: x.escalation_level(),
+            admin: x.admin,
+            http_address: x.http_address,
+            ack_token: x.ack_token,
+            acknowledge_url: x.ack_links.map(|l| l.acknowledge),
+        });
+        vec![match result {
+            // Frame is delivered by the connection task once the server is reachable.
+            Ok(()) => DeliveryResult::queued(Channel::Tcp, self.server_address.clone()),
+            Err(e) => DeliveryResult::failed(Channel::Tcp, self.server_address.clone(), e),
+        }]
+    }
+
+    async fn send_outage_update(&self, x: OutageUpdate) {
+        let (event, acknowledged_by) = match x.event {
+            OutageEvent::Acknowledged(admin) => ("acknowledged", Some(admin)),
+            OutageEvent::Resolved => ("resolved", None),
+        };
+        let _ = self.enqueue(Frame::OutageUpdate {
+            endpoint: x.endpoint,
+            outage_id: x.outage_id,
+            http_address: x.http_address,
+            event: event.to_string(),
+            acknowledged_by,
+        });
+    }
+}
+
+#[derive(Clone)]
+pub struct TcpResponseHandler {
+    sender: Sender<ResponseData>,
+    token_signer: AckTokenSigner,
+}
+
+impl TcpResponseHandler {
+    pub fn new(sender: Sender<ResponseData>, token_signer: AckTokenSigner) -> TcpResponseHandler {
+        TcpResponseHandler {
+            sender,
+            token_signer,
+        }
+    }
+
+    // Identity of the responder is the admin the notification was sent to, as stated by the signed token.
+    async fn forward(&self, token: &str, action: AckAction, minutes: Option<u64>) -> Result<()> {
+        let token = self.token_signer.verify(token).ok_or_else(|| {
+            NotificationError::InvalidResponse(format!(
+                "invalid token {}",
+                AckTokenSigner::fingerprint(token)
+            ))
+        })?;
+        let action = match action {
+            AckAction::Acknowledge => ResponseAction::Acknowledge,
+            AckAction::Escalate => ResponseAction::Escalate,
+            AckAction::Snooze => {
+                let minutes = minutes
+                    .unwrap_or(AckLinkResponseListener::DEFAULT_SNOOZE_MINUTES)
+                    .clamp(1, AckLinkResponseListener::MAX_SNOOZE_MINUTES);
+                ResponseAction::Snooze(Duration::from_secs(minutes * 60))
+            }
+        };
+        log::info!(
+            "Received {:?} tcp response of admin {} to outage {}",
+            action,
+            token.admin,
+            token.outage_id
+        );
+        self.sender
+            .send(ResponseData {
+                admin: token.admin,
+                outage_id: token.outage_id,
+                endpoint: token.endpoint,
+                is_first: token.is_first,
+                action,
+                group: token.group,
+            })
+            .await
+            .map_err(|_| NotificationError::ResponseChannelClosed)
+    }
+
+    // Returns encoded reply to the frame, if it needs one.
+    async fn handle_line(&self, peer: &str, line: &str) -> Option<String> {
+        let reply = match serde_json::from_str::<Frame>(line) {
+            Ok(Frame::Ack {
+                id,
+                token,
+                action,
+                minutes,
+            }) => {
+                let result = self.forward(&token, action, minutes).await;
+                if let Err(e) = &result {
+                    log::warn!("Rejected tcp response from {}: {}", peer, e);
+                }
+                Frame::AckResult {
+                    id,
+                    accepted: result.is_ok(),
+                    error: result.err().map(|e| e.to_string()),
+                }
+            }
+            Ok(frame) => {
+                log::warn!("Ignoring unexpected frame from {}: {:?}", peer, frame);
+                return None;
+            }
+            Err(e) => Frame::AckResult {
+                id: None,
+                accepted: false,
+                error: Some(format!("invalid frame: {}", e)),
+            },
+        };
+        reply.encode().ok()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TcpListenerConfig {
+    pub listen_address: String,
+    pub shared_secret: Option<String>,
+}
+
+impl TcpListenerConfig {
+    // Listening socket is enabled only when TCP_ACK_LISTEN_ADDRESS is set.
+    pub fn from_env() -> Option<TcpListenerConfig> {
+        Some(TcpListenerConfig {
+            listen_address: env::var("TCP_ACK_LISTEN_ADDRESS").ok()?,
+            shared_secret: read_secret("TCP_SHARED_SECRET"),
+        })
+    }
+}
+
+/*
+    Accepts acknowledgements from consumers that connect to irio instead.
+    Connecting consumers send hello and irio answers with welcome.
+*/
+pub struct TcpResponseListener {
+    config: TcpListenerConfig,
+    handler: TcpResponseHandler,
+}
+
+impl TcpResponseListener {
+    const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+    // Hello older than that is considered replayed.
+    const MAX_HELLO_AGE_SECS: i64 = 300;
+
+    pub fn new(config: TcpListenerConfig, handler: TcpResponseHandler) -> TcpResponseListener {
+        TcpResponseListener { config, handler }
+    }
+
+    async fn accept_handshake<R, W>(
+        &self,
+        reader: &mut Lines<BufReader<R>>,
+        writer: &mut W,
+    ) -> anyhow::Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let line = tokio::time::timeout(Self::HANDSHAKE_TIMEOUT, reader.next_line())
+            .await
+            .map_err(|_| anyhow::anyhow!("timed out waiting for hello"))??
+            .ok_or_else(|| anyhow::anyhow!("connection closed during handshake"))?;
+        let Frame::Hello {
+            nonce,
+            timestamp,
+            mac,
+            ..
+        } = serde_json::from_str(&line)?
+        else {
+            return Err(anyhow::anyhow!("expected hello"));
+        };
+        let secret = self.config.shared_secret.as_deref();
+        if !is_signed(
+            secret,
+            &format!("hello.{}.{}", nonce, timestamp),
+            mac.as_deref(),
+        ) {
+            return Err(anyhow::anyhow!(
+                "hello is not signed with the shared secret"
+            ));
+        }
+        if secret.is_some()
+            && (chrono::Utc::now().timestamp() - timestamp).abs() > Self::MAX_HELLO_AGE_SECS
+        {
+            return Err(anyhow::anyhow!("hello is too old"));
+        }
+        let welcome = Frame::Welcome {
+            mac: sign(secret, &format!("welcome.{}", nonce)),
+        };
+        writer.write_all(welcome.encode()?.as_bytes()).await?;
+        Ok(())
+    }
+
+    async fn serve(&self, stream: TcpStream, peer: String) -> anyhow::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader).lines();
+        self.accept_handshake(&mut reader, &mut writer).await?;
+        while let Some(line) = reader.next_line().await? {
+            if let Some(reply) = self.handler.handle_line(&peer, &line).await {
+                writer.write_all(reply.as_bytes()).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl ResponseListener for TcpResponseListener {
+    async fn listen_for_responses(&self) -> Result<()> {
+        let listener = tokio::net::TcpListener::bind(&self.config.listen_address)
+            .await
+            .inspect_err(|e| {
+                log::error!(
+                    "Failed to bind tcp response listener to {}: {}",
+                    self.config.listen_address,
+                    e
+                )
+            })?;
+        log::info!(
+            "Listening for tcp responses on {}",
+            self.config.listen_address
+        );
+        let mut connections = futures::stream::FuturesUnordered::new();
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, peer)) => {
+                        let peer = peer.to_string();
+                        log::info!("Accepted tcp response connection from {}", peer);
+                        connections.push(async move {
+                            if let Err(e) = self.serve(stream, peer.clone()).await {
+                                log::warn!("Tcp response connection from {} failed: {}", peer, e);
+                            }
+                        });
+                    }
+                    Err(e) => log::error!("Failed to accept tcp response connection: {}", e),
+                },
+                Some(()) = futures::StreamExt::next(&mut connections) => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::duplex,
+        net::{
+            tcp::{OwnedReadHalf, OwnedWriteHalf},
+            TcpListener,
+        },
+        sync::mpsc::channel,
+    };
+
+    use super::*;
+    use crate::{ack_token::AckToken, notification_service::DeliveryStatus};
+
+    const SECRET: &str = "shared secret";
+
+    fn handler() -> (TcpResponseHandler, Receiver<ResponseData>, AckTokenSigner) {
+        let (sender, receiver) = channel(8);
+        let token_signer = AckTokenSigner::new(b"secret".to_vec());
+        (
+            TcpResponseHandler::new(sender, token_signer.clone()),
+            receiver,
+            token_signer,
+        )
+    }
+
+    fn token(token_signer: &AckTokenSigner, outage_id: Uuid) -> String {
+        token_signer.sign(&AckToken {
+            endpoint: 1,
+            outage_id,
+            is_first: true,
+            admin: "admin".to_string(),
+            group: None,
+        })
+    }
+
+    fn listener(shared_secret: Option<&str>) -> TcpResponseListener {
+        TcpResponseListener::new(
+            TcpListenerConfig {
+                listen_address: String::new(),
+                shared_secret: shared_secret.map(str::to_string),
+            },
+            handler().0,
+        )
+    }
+
+    fn hello(mac_secret: Option<&str>, timestamp: i64) -> Frame {
+        Frame::Hello {
+            version: Frame::PROTOCOL_VERSION,
+            nonce: "nonce".to_string(),
+            timestamp,
+            mac: sign(mac_secret, &format!("hello.nonce.{}", timestamp)),
+        }
+    }
+
+    // Runs the listener's side of the handshake against the given hello, returns its result and what it wrote.
+    async fn accept(listener: &TcpResponseListener, hello: Frame) -> (anyhow::Result<()>, String) {
+        let (client, server) = duplex(1024);
+        let (server_reader, mut server_writer) = tokio::io::split(server);
+        let (client_reader, mut client_writer) = tokio::io::split(client);
+        client_writer
+            .write_all(hello.encode().unwrap().as_bytes())
+            .await
+            .unwrap();
+        let mut reader = BufReader::new(server_reader).lines();
+        let result = listener
+            .accept_handshake(&mut reader, &mut server_writer)
+            .await;
+        // Client reads until the listener's end is closed.
+        drop((reader, server_writer));
+        let reply = BufReader::new(client_reader)
+            .lines()
+            .next_line()
+            .await
+            .unwrap()
+            .unwrap_or_default();
+        (result, reply)
+    }
+
+    #[tokio::test]
+    async fn signed_hello_is_welcomed() {
+        let listener = listener(Some(SECRET));
+        let (result, reply) = accept(
+            &listener,
+            hello(Some(SECRET), chrono::Utc::now().timestamp()),
+        )
+        .await;
+
+        assert!(result.is_ok());
+        let Frame::Welcome { mac } = serde_json::from_str(&reply).unwrap() else {
+            panic!("expected welcome, got {}", reply);
+        };
+        assert!(is_signed(Some(SECRET), "welcome.nonce", mac.as_deref()));
+    }
+
+    #[tokio::test]
+    async fn hello_with_bad_mac_is_rejected() {
+        let listener = listener(Some(SECRET));
+        let timestamp = chrono::Utc::now().timestamp();
+
+        let (result, reply) = accept(&listener, hello(Some("other secret"), timestamp)).await;
+        assert!(result.is_err());
+        assert!(reply.is_empty());
+
+        let (result, reply) = accept(&listener, hello(None, timestamp)).await;
+        assert!(result.is_err());
+        assert!(reply.is_empty());
+    }
+
+    #[tokio::test]
+    async fn old_hello_is_rejected() {
+        let listener = listener(Some(SECRET));
+        let timestamp =
+            chrono::Utc::now().timestamp() - 2 * TcpResponseListener::MAX_HELLO_AGE_SECS;
+
+        let (result, reply) = accept(&listener, hello(Some(SECRET), timestamp)).await;
+
+        assert!(result.is_err());
+        assert!(reply.is_empty());
+    }
+
+    #[tokio::test]
+    async fn ack_frames_are_read_line_by_line() {
+        let (handler, mut receiver, token_signer) = handler();
+        let (acknowledged, snoozed) = (Uuid::new_v4(), Uuid::new_v4());
+        let frames = [
+            Frame::Ack {
+                id: Some("1".to_string()),
+                token: token(&token_signer, acknowledged),
+                action: AckAction::Acknowledge,
+                minutes: None,
+            },
+            Frame::Ack {
+                id: Some("2".to_string()),
+                token: token(&token_signer, snoozed),
+                action: AckAction::Snooze,
+                minutes: Some(10),
+            },
+        ];
+        let (mut client, server) = duplex(4096);
+        // Both frames arrive in a single write.
+        let ndjson: String = frames.iter().map(|f| f.encode().unwrap()).collect();
+        client.write_all(ndjson.as_bytes()).await.unwrap();
+        drop(client);
+
+        let mut lines = BufReader::new(server).lines();
+        let mut replies = vec![];
+        while let Some(line) = lines.next_line().await.unwrap() {
+            let reply = handler.handle_line("peer", &line).await.unwrap();
+            assert!(reply.ends_with('\n'));
+            replies.push(serde_json::from_str::<Frame>(&reply).unwrap());
+        }
+
+        assert_eq!(
+            replies,
+            vec![
+                Frame::AckResult {
+                    id: Some("1".to_string()),
+                    accepted: true,
+                    error: None,
+                },
+                Frame::AckResult {
+                    id: Some("2".to_string()),
+                    accepted: true,
+                    error: None,
+                },
+            ]
+        );
+        let response = receiver.recv().await.unwrap();
+        assert_eq!(response.outage_id, acknowledged);
+        assert_eq!(response.admin, "admin");
+        assert_eq!(response.endpoint, 1);
+        assert_eq!(response.action, ResponseAction::Acknowledge);
+        let response = receiver.recv().await.unwrap();
+        assert_eq!(response.outage_id, snoozed);
+        assert_eq!(
+            response.action,
+            ResponseAction::Snooze(Duration::from_secs(600))
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_frames_are_answered_with_errors() {
+        let (handler, mut receiver, _) = handler();
+        let forged = Frame::Ack {
+            id: Some("1".to_string()),
+            token: "forged".to_string(),
+            action: AckAction::Escalate,
+            minutes: None,
+        };
+
+        let reply = handler
+            .handle_line("peer", forged.encode().unwrap().trim_end())
+            .await
+            .unwrap();
+        let Frame::AckResult { id, accepted, .. } = serde_json::from_str(&reply).unwrap() else {
+            panic!("expected ack_result, got {}", reply);
+        };
+        assert_eq!(id.as_deref(), Some("1"));
+        assert!(!accepted);
+
+        let reply = handler.handle_line("peer", "not json").await.unwrap();
+        assert!(matches!(
+            serde_json::from_str(&reply).unwrap(),
+            Frame::AckResult {
+                id: None,
+                accepted: false,
+                error: Some(_),
+            }
+        ));
+
+        let welcome = Frame::Welcome { mac: None }.encode().unwrap();
+        assert!(handler.handle_line("peer", &welcome).await.is_none());
+        assert!(receiver.try_recv().is_err());
+    }
+
+    // Accepts a connection of the sender and welcomes it, returns the connection to read its frames from.
+    async fn welcome(server: &TcpListener) -> (Lines<BufReader<OwnedReadHalf>>, OwnedWriteHalf) {
+        let (stream, _) = server.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader).lines();
+        let hello = reader.next_line().await.unwrap().unwrap();
+        assert!(matches!(
+            serde_json::from_str(&hello).unwrap(),
+            Frame::Hello { .. }
+        ));
+        writer
+            .write_all(Frame::Welcome { mac: None }.encode().unwrap().as_bytes())
+            .await
+            .unwrap();
+        (reader, writer)
+    }
+
+    #[tokio::test]
+    async fn frames_buffered_while_disconnected_are_sent_after_reconnecting() {
+        let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let sender = TcpNotificationSender::new(
+            TcpConfig {
+                address: server.local_addr().unwrap().to_string(),
+                tls: false,
+                tls_domain: None,
+                tls_ca_file: None,
+                shared_secret: None,
+                buffer_size: 8,
+            },
+            handler().0,
+        )
+        .unwrap();
+        drop(welcome(&server).await);
+        // Sender notices the closed connection and waits before reconnecting.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let first = NotificationData::example("primary", 1);
+        let second = NotificationData::example("secondary", 2);
+        for x in [&first, &second] {
+            let results = sender.send_notification(x.clone()).await;
+            assert_eq!(results[0].status, DeliveryStatus::Queued);
+        }
+
+        let (mut reader, _writer) = tokio::time::timeout(Duration::from_secs(5), welcome(&server))
+            .await
+            .expect("sender did not reconnect");
+        for x in [&first, &second] {
+            let line = reader.next_line().await.unwrap().unwrap();
+            let Frame::Notification { id, outage_id, .. } = serde_json::from_str(&line).unwrap()
+            else {
+                panic!("expected notification, got {}", line);
+            };
+            assert_eq!(outage_id, x.outage_id);
+            assert_eq!(id, x.idempotency_key(Channel::Tcp));
+        }
+    }
+}
@@ -0,0 +1,414 @@
+use std::{env, fmt, fs, sync::Arc};
+
+use minijinja::Environment;
+use serde::Serialize;
+
+use crate::{
+    domain::{AdminId, EndpointId, MyTime, OutageId},
+    notification_service::{NotificationData, OutageEvent, OutageUpdate},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationEvent {
+    // First notification of an outage, sent to the primary admin.
+    Opened,
+    // Primary admin did not respond, sent to the secondary admin.
+    Escalated,
+    // Secondary admin did not respond either, sent to the secondary admin again every REMINDER_INTERVAL_SECS.
+    Reminder,
+    Acknowledged,
+    Resolved,
+    // Several outages notified to one admin at once.
+    Summary,
+    // Outage can't be notified because of its configuration, sent to the fallback admin.
+    Undeliverable,
+}
+
+impl fmt::Display for NotificationEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            NotificationEvent::Opened => "opened",
+            NotificationEvent::Escalated => "escalated",
+            NotificationEvent::Reminder => "reminder",
+            NotificationEvent::Acknowledged => "acknowledged",
+            NotificationEvent::Resolved => "resolved",
+            NotificationEvent::Summary => "summary",
+            NotificationEvent::Undeliverable => "undeliverable",
+        };
+        f.write_str(name)
+    }
+}
+
+pub fn format_duration(since: Option<MyTime>) -> String {
+    let Some(since) = since else {
+        return "unknown".to_string();
+    };
+    let secs = (chrono::Utc::now().naive_utc() - since)
+        .num_seconds()
+        .max(0);
+    match secs {
+        s if s < 60 => format!("{}s", s),
+        s if s < 3600 => format!("{}m {}s", s / 60, s % 60),
+        s => format!("{}h {}m", s / 3600, s % 3600 / 60),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct EndpointContext {
+    id: EndpointId,
+    http_address: String,
+    // Absent in outage updates.
+    severity: Option<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+struct OutageContext {
+    id: OutageId,
+    level: Option<u8>,
+    reminder: Option<i32>,
+    started_at: Option<String>,
+    duration: String,
+    acknowledged_by: Option<AdminId>,
+}
+
+#[derive(Debug, Serialize)]
+struct AdminContext {
+    id: AdminId,
+    email: String,
+    phone_number: String,
+}
+
+#[derive(Debug, Serialize)]
+struct FailureContext {
+    reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct GroupMemberContext {
+    endpoint: EndpointContext,
+    outage: OutageContext,
+    failure: FailureContext,
+}
+
+#[derive(Debug, Serialize)]
+struct LinksContext {
+    acknowledge: String,
+    escalate: String,
+    snooze: String,
+}
+
+// Variables available to templates, see README for the full list.
+#[derive(Debug, Serialize)]
+pub struct TemplateContext {
+    pub event: NotificationEvent,
+    endpoint: EndpointContext,
+    outage: OutageContext,
+    // Absent in outage updates, which are not addressed to a single admin.
+    admin: Option<AdminContext>,
+    failure: FailureContext,
+    links: Option<LinksContext>,
+    // Outages of a summary, empty in other events.
+    group: Vec<GroupMemberContext>,
+    // Why the outage can't be notified, present in undeliverable events only.
+    undeliverable: Option<String>,
+}
+
+impl TemplateContext {
+    pub fn notification(x: &NotificationData) -> TemplateContext {
+        let member = GroupMemberContext::new(x);
+        TemplateContext {
+            event: if x.undeliverable.is_some() {
+                NotificationEvent::Undeliverable
+            } else if !x.grouped.is_empty() {
+                NotificationEvent::Summary
+            } else if x.reminder.is_some() {
+                NotificationEvent::Reminder
+            } else if x.is_first {
+                NotificationEvent::Opened
+            } else {
+                NotificationEvent::Escalated
+            },
+            endpoint: member.endpoint,
+            outage: member.outage,
+            admin: Some(AdminContext {
+                id: x.admin.clone(),
+                email: x.email.clone(),
+                phone_number: x.phone_number.clone(),
+            }),
+            failure: member.failure,
+            links: x.ack_links.as_ref().map(|l| LinksContext {
+                acknowledge: l.acknowledge.clone(),
+                escalate: l.escalate.clone(),
+                snooze: l.snooze.clone(),
+            }),
+            group: x.grouped.iter().map(GroupMemberContext::new).collect(),
+            undeliverable: x.undeliverable.clone(),
+        }
+    }
+
+    pub fn update(x: &OutageUpdate) -> TemplateContext {
+        let (event, acknowledged_by) = match &x.event {
+            OutageEvent::Acknowledged(admin) => (NotificationEvent::Acknowledged, Some(admin)),
+            OutageEvent::Resolved => (NotificationEvent::Resolved, None),
+        };
+        TemplateContext {
+            event,
+            endpoint: EndpointContext {
+                id: x.endpoint,
+                http_address: x.http_address.clone(),
+                severity: None,
+            },
+            outage: OutageContext {
+                id: x.outage_id,
+                level: None,
+                reminder: None,
+                started_at: x.outage_start.map(|t| t.to_string()),
+                duration: format_duration(x.outage_start),
+                acknowledged_by: acknowledged_by.cloned(),
+            },
+            admin: None,
+            failure: FailureContext {
+                reason: x.failure_reason.clone(),
+            },
+            links: None,
+            group: vec![],
+            undeliverable: None,
+        }
+    }
+}
+
+impl GroupMemberContext {
+    fn new(x: &NotificationData) -> GroupMemberContext {
+        GroupMemberContext {
+            endpoint: EndpointContext {
+                id: x.endpoint,
+                http_address: x.http_address.clone(),
+                severity: Some(x.severity.as_str()),
+            },
+            outage: OutageContext {
+                id: x.outage_id,
+                level: Some(if x.is_first { 1 } else { 2 }),
+                reminder: x.reminder,
+                started_at: x.outage_start.map(|t| t.to_string()),
+                duration: format_duration(x.outage_start),
+                acknowledged_by: None,
+            },
+            failure: FailureContext {
+                reason: x.failure_reason.clone(),
+            },
+        }
+    }
+}
+
+/*
+    Message templates of all channels. Template `<name>.<ext>` can be overridden for all events
+    by a file of the same name in NOTIFICATION_TEMPLATES_DIR, or for one event by `<name>.<event>.<ext>`.
+*/
+#[derive(Clone)]
+pub struct Templates {
+    env: Arc<Environment<'static>>,
+}
+
+impl Templates {
+    pub const TELEGRAM: &'static str = "telegram.txt";
+    pub const EMAIL_SUBJECT: &'static str = "email_subject.txt";
+    pub const EMAIL_TEXT: &'static str = "email.txt";
+    pub const EMAIL_HTML: &'static str = "email.html";
+    pub const SMS: &'static str = "sms.txt";
+    pub const CHAT_TITLE: &'static str = "chat.txt";
+
+    const BUILT_IN: [(&'static str, &'static str); 16] = [
+        (Self::TELEGRAM, include_str!("templates/telegram.txt")),
+        (
+            Self::EMAIL_SUBJECT,
+            include_str!("templates/email_subject.txt"),
+        ),
+        (Self::EMAIL_TEXT, include_str!("templates/email.txt")),
+        (Self::EMAIL_HTML, include_str!("templates/email.html")),
+        (Self::SMS, include_str!("templates/sms.txt")),
+        (Self::CHAT_TITLE, include_str!("templates/chat.txt")),
+        (
+            "telegram.summary.txt",
+            include_str!("templates/telegram.summary.txt"),
+        ),
+        (
+            "email_subject.summary.txt",
+            include_str!("templates/email_subject.summary.txt"),
+        ),
+        (
+            "email.summary.txt",
+            include_str!("templates/email.summary.txt"),
+        ),
+        (
+            "email.summary.html",
+            include_str!("templates/email.summary.html"),
+        ),
+        ("sms.summary.txt", include_str!("templates/sms.summary.txt")),
+        (
+            "telegram.undeliverable.txt",
+            include_str!("templates/telegram.undeliverable.txt"),
+        ),
+        (
+            "email_subject.undeliverable.txt",
+            include_str!("templates/email_subject.undeliverable.txt"),
+        ),
+        (
+            "email.undeliverable.txt",
+            include_str!("templates/email.undeliverable.txt"),
+        ),
+        (
+            "email.undeliverable.html",
+            include_str!("templates/email.undeliverable.html"),
+        ),
+        (
+            "sms.undeliverable.txt",
+            include_str!("templates/sms.undeliverable.txt"),
+        ),
+    ];
+
+    pub fn built_in() -> Templates {
+        Self::with_overrides(vec![]).expect("built-in templates are valid")
+    }
+
+    pub fn from_env() -> anyhow::Result<Templates> {
+        match env::var("NOTIFICATION_TEMPLATES_DIR") {
+            Ok(dir) => Self::from_dir(&dir),
+            Err(_) => Ok(Self::built_in()),
+        }
+    }
+
+    // Every file of the directory overrides the template of the same name.
+    fn from_dir(dir: &str) -> anyhow::Result<Templates> {
+        let mut overrides = vec![];
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if !path.is_file() {
+                continue;
+            }
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            log::info!("Loading template {} from {}", name, dir);
+            overrides.push((name.to_string(), fs::read_to_string(&path)?));
+        }
+        Self::with_overrides(overrides)
+    }
+
+    fn with_overrides(overrides: Vec<(String, String)>) -> anyhow::Result<Templates> {
+        let mut env = Environment::new();
+        env.set_trim_blocks(true);
+        for (name, source) in Self::BUILT_IN {
+            env.add_template(name, source)?;
+        }
+        for (name, source) in overrides {
+            env.add_template_owned(name.clone(), source)
+                .map_err(|e| anyhow::anyhow!("invalid template {}: {}", name, e))?;
+        }
+        Ok(Templates { env: Arc::new(env) })
+    }
+
+    pub fn render(&self, name: &str, context: &TemplateContext) -> anyhow::Result<String> {
+        let event_specific = match name.rsplit_once('.') {
+            Some((stem, ext)) => format!("{}.{}.{}", stem, context.event, ext),
+            None => format!("{}.{}", name, context.event),
+        };
+        let template = self
+            .env
+            .get_template(&event_specific)
+            .or_else(|_| self.env.get_template(name))?;
+        Ok(template.render(context)?.trim().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn context(reminder: Option<i32>) -> TemplateContext {
+        let mut x = NotificationData::example("admin", 1);
+        x.reminder = reminder;
+        TemplateContext::notification(&x)
+    }
+
+    #[test]
+    fn built_in_template_covers_reminders() {
+        let templates = Templates::built_in();
+
+        let opened = templates
+            .render(Templates::TELEGRAM, &context(None))
+            .unwrap();
+        assert!(opened.starts_with("🔴 http://endpoint-1.example.com is down"));
+        let reminder = templates
+            .render(Templates::TELEGRAM, &context(Some(1)))
+            .unwrap();
+        assert!(reminder.contains("is still down"));
+        assert_eq!(context(Some(1)).event, NotificationEvent::Reminder);
+    }
+
+    #[test]
+    fn override_replaces_template_for_all_events() {
+        let templates = Templates::with_overrides(vec![(
+            Templates::SMS.to_string(),
+            "{{ event }} {{ endpoint.http_address }}".to_string(),
+        )])
+        .unwrap();
+
+        assert_eq!(
+            templates.render(Templates::SMS, &context(None)).unwrap(),
+            "opened http://endpoint-1.example.com"
+        );
+        assert_eq!(
+            templates.render(Templates::SMS, &context(Some(2))).unwrap(),
+            "reminder http://endpoint-1.example.com"
+        );
+    }
+
+    #[test]
+    fn event_without_own_template_falls_back_to_base_one() {
+        let templates = Templates::with_overrides(vec![(
+            "sms.reminder.txt".to_string(),
+            "Reminder {{ outage.reminder }}: {{ endpoint.http_address }}".to_string(),
+        )])
+        .unwrap();
+
+        assert_eq!(
+            templates.render(Templates::SMS, &context(Some(2))).unwrap(),
+            "Reminder 2: http://endpoint-1.example.com"
+        );
+        let opened = templates.render(Templates::SMS, &context(None)).unwrap();
+        assert!(opened.starts_with("irio: http://endpoint-1.example.com is DOWN."));
+    }
+
+    #[test]
+    fn overrides_are_loaded_from_directory() {
+        let dir = env::temp_dir().join(format!("irio-templates-{}", Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+        fs::write(
+            dir.join("sms.opened.txt"),
+            "{{ outage.level }} {{ admin.id }}",
+        )
+        .unwrap();
+
+        let templates = Templates::from_dir(dir.to_str().unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            templates
+                .unwrap()
+                .render(Templates::SMS, &context(None))
+                .unwrap(),
+            "1 admin"
+        );
+    }
+
+    #[test]
+    fn invalid_override_is_rejected() {
+        let templates =
+            Templates::with_overrides(vec![(Templates::SMS.to_string(), "{% if %}".to_string())]);
+
+        assert!(templates.is_err());
+    }
+}
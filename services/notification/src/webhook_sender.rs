@@ -0,0 +1,380 @@
+use std::{env, time::Duration};
+
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::{
+    config::read_secret,
+    domain::{AdminId, EndpointId},
+    error::{NotificationError, Result},
+    notification_service::{Channel, DeliveryResult, NotificationData, NotificationSender},
+};
+
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    // Urls every notification is posted to, in addition to the url configured for the endpoint.
+    pub urls: Vec<String>,
+    pub secret: String,
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+}
+
+impl WebhookConfig {
+    const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+    const DEFAULT_INITIAL_BACKOFF_MS: u64 = 1000;
+
+    // Webhook channel is enabled only when WEBHOOK_SECRET is set, as every request is signed.
+    pub fn from_env() -> anyhow::Result<Option<WebhookConfig>> {
+        let Some(secret) = read_secret("WEBHOOK_SECRET") else {
+            return Ok(None);
+        };
+        let urls = env::var("WEBHOOK_URLS")
+            .map(|urls| {
+                urls.split(',')
+                    .map(str::trim)
+                    .filter(|url| !url.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let max_attempts = match env::var("WEBHOOK_MAX_ATTEMPTS") {
+            Ok(max_attempts) => max_attempts.parse()?,
+            Err(_) => Self::DEFAULT_MAX_ATTEMPTS,
+        };
+        let initial_backoff_ms = match env::var("WEBHOOK_INITIAL_BACKOFF_MS") {
+            Ok(backoff) => backoff.parse()?,
+            Err(_) => Self::DEFAULT_INITIAL_BACKOFF_MS,
+        };
+        Ok(Some(WebhookConfig {
+            urls,
+            secret,
+            max_attempts: max_attempts.max(1),
+            initial_backoff: Duration::from_millis(initial_backoff_ms),
+        }))
+    }
+}
+
+#[derive(Serialize)]
+struct WebhookEndpoint<'a> {
+    id: EndpointId,
+    http_address: &'a str,
+}
+
+#[derive(Serialize)]
+struct WebhookTimestamps {
+    sent_at: String,
+    first_notification_sent_at: Option<String>,
+}
+
+#[derive(Serialize)]
+struct WebhookAck<'a> {
+    token: &'a str,
+    acknowledge_url: Option<&'a str>,
+    escalate_url: Option<&'a str>,
+    snooze_url: Option<&'a str>,
+}
+
+// Version 1 of the document posted to webhooks. Fields are only ever added within a version.
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    version: u32,
+    event: &'a str,
+    endpoint: WebhookEndpoint<'a>,
+    outage_id: String,
+    escalation_level: i32,
+    admin: &'a AdminId,
+    timestamps: WebhookTimestamps,
+    ack: WebhookAck<'a>,
+}
+
+#[derive(Clone)]
+pub struct WebhookNotificationSender {
+    client: reqwest::Client,
+    config: WebhookConfig,
+}
+
+impl WebhookNotificationSender {
+    const PAYLOAD_VERSION: u32 = 1;
+    const NOTIFICATION_EVENT: &'static str = "outage.notification";
+    const MAX_BACKOFF: Duration = Duration::from_secs(30);
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn new(config: WebhookConfig) -> WebhookNotificationSender {
+        WebhookNotificationSender {
+            client: reqwest::Client::builder()
+                .timeout(Self::REQUEST_TIMEOUT)
+                .build()
+                .expect("Failed to create HTTP client"),
+            config,
+        }
+    }
+
+    // Configured receivers of every notification and the receiver of the endpoint, if it has one.
+    fn urls<'a>(&'a self, x: &'a NotificationData) -> Vec<&'a str> {
+        self.config
+            .urls
+            .iter()
+            .map(String::as_str)
+            .chain(x.webhook_url.as_deref())
+            .collect()
+    }
+
+    fn payload(x: &NotificationData) -> WebhookPayload<'_> {
+        let links = x.ack_links.as_ref();
+        WebhookPayload {
+            version: Self::PAYLOAD_VERSION,
+            event: Self::NOTIFICATION_EVENT,
+            endpoint: WebhookEndpoint {
+                id: x.endpoint,
+                http_address: &x.http_address,
+            },
+            outage_id: x.outage_id.to_string(),
+            escalation_level: x.escalation_level(),
+            admin: &x.admin,
+            timestamps: WebhookTimestamps {
+                sent_at: chrono::Utc::now().to_rfc3339(),
+                first_notification_sent_at: x
+                    .first_notification_sent_at
+                    .map(|t| t.and_utc().to_rfc3339()),
+            },
+            ack: WebhookAck {
+                token: &x.ack_token,
+                acknowledge_url: links.map(|l| l.acknowledge.as_str()),
+                escalate_url: links.map(|l| l.escalate.as_str()),
+                snooze_url: links.map(|l| l.snooze.as_str()),
+            },
+        }
+    }
+
+    /*
+        Signature is hex encoded HMAC-SHA256 of "<timestamp>.<body>".
+        Including the timestamp lets receivers reject replayed requests.
+    */
+    fn signature(&self, timestamp: i64, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.config.secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(body);
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    async fn post(&self, url: &str, delivery_id: Uuid, body: &[u8]) -> Result<()> {
+        let timestamp = chrono::Utc::now().timestamp();
+        let response = self
+            .client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-Irio-Event", Self::NOTIFICATION_EVENT)
+            .header("X-Irio-Delivery", delivery_id.to_string())
+            .header("X-Irio-Timestamp", timestamp.to_string())
+            .header("X-Irio-Signature", self.signature(timestamp, body))
+            .body(body.to_vec())
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(NotificationError::Provider(format!(
+                "webhook responded with {}",
+                response.status()
+            )));
+        }
+        Ok(())
+    }
+
+    fn next_backoff(backoff: Duration) -> Duration {
+        (backoff * 2).min(Self::MAX_BACKOFF)
+    }
+
+    /*
+        Delivery id is the idempotency key of the notification, so that receivers can deduplicate.
+        It stays the same across retries, also those of another instance after the claim on the endpoint expired.
+    */
+    async fn post_with_retries(&self, url: &str, delivery_id: Uuid, body: &[u8]) -> Result<()> {
+        let mut backoff = self.config.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            match self.post(url, delivery_id, body).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt >= self.config.max_attempts => return Err(e),
+                Err(e) => {
+                    log::warn!(
+                        "Webhook {} attempt {} failed: {}, retrying in {:?}",
+                        url,
+                        attempt,
+                        e,
+                        backoff
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = Self::next_backoff(backoff);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl NotificationSender for WebhookNotificationSender {
+    async fn send_notification(&self, x: NotificationData) -> Vec<DeliveryResult> {
+        let urls = self.urls(&x);
+        if urls.is_empty() {
+            return vec![];
+        }
+        let body = match serde_json::to_vec(&Self::payload(&x)) {
+            Ok(body) => body,
+            Err(e) => {
+                log::error!("Failed to serialize webhook payload: {}", e);
+                return urls
+                    .iter()
+                    .map(|url| DeliveryResult::failed(Channel::Webhook, *url, &e))
+                    .collect();
+            }
+        };
+        let delivery_id = x.idempotency_key(Channel::Webhook);
+        let results = futures::future::join_all(
+            urls.iter()
+                .map(|url| self.post_with_retries(url, delivery_id, body.as_slice())),
+        )
+        .await;
+        urls.iter()
+            .zip(results)
+            .map(|(url, result)| {
+                log::info!("Notifying {} by webhook {}", x.admin, url);
+                match result {
+                    Ok(()) => {
+                        log::info!("Message sent successfully");
+                        DeliveryResult::delivered(Channel::Webhook, *url)
+                    }
+                    Err(e) => {
+                        log::error!("Failed to send message: {}", e);
+                        DeliveryResult::failed(Channel::Webhook, *url, e)
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn channels(&self, x: &NotificationData) -> Vec<Channel> {
+        if self.urls(x).is_empty() {
+            vec![]
+        } else {
+            vec![Channel::Webhook]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{
+        body::Bytes,
+        extract::State,
+        http::{HeaderMap, StatusCode},
+        Router,
+    };
+
+    use super::*;
+    use crate::notification_service::DeliveryStatus;
+
+    // Request received by the stub: delivery id and signature headers and body.
+    type Request = (String, String, String, Vec<u8>);
+
+    // Serves a receiver answering with the given statuses in turn and then with 200, returns its url and the requests it received.
+    async fn serve(statuses: Vec<StatusCode>) -> (String, Arc<Mutex<Vec<Request>>>) {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let app = Router::new()
+            .fallback(
+                move |State(requests): State<Arc<Mutex<Vec<Request>>>>,
+                      headers: HeaderMap,
+                      body: Bytes| {
+                    let header = |name: &str| headers[name].to_str().unwrap().to_string();
+                    let mut requests = requests.lock().unwrap();
+                    requests.push((
+                        header("x-irio-delivery"),
+                        header("x-irio-timestamp"),
+                        header("x-irio-signature"),
+                        body.to_vec(),
+                    ));
+                    let status = statuses
+                        .get(requests.len() - 1)
+                        .copied()
+                        .unwrap_or(StatusCode::OK);
+                    async move { status }
+                },
+            )
+            .with_state(requests.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, requests)
+    }
+
+    fn sender(urls: Vec<String>, max_attempts: u32) -> WebhookNotificationSender {
+        WebhookNotificationSender::new(WebhookConfig {
+            urls,
+            secret: "whsec".to_string(),
+            max_attempts,
+            initial_backoff: Duration::from_millis(1),
+        })
+    }
+
+    #[test]
+    fn signature_is_hmac_of_timestamp_and_body() {
+        let body = br#"{"event":"outage.notification"}"#;
+        assert_eq!(
+            sender(vec![], 1).signature(1700000000, body),
+            "sha256=81cc20a8983b410bc457eec5f829228fa6fe58a167b3e5490a8413542953dfcc"
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_cap() {
+        let backoff = WebhookNotificationSender::next_backoff;
+        assert_eq!(backoff(Duration::from_secs(1)), Duration::from_secs(2));
+        assert_eq!(backoff(Duration::from_secs(20)), Duration::from_secs(30));
+        assert_eq!(backoff(Duration::from_secs(30)), Duration::from_secs(30));
+    }
+
+    #[tokio::test]
+    async fn failed_post_is_retried_with_the_same_delivery_id() {
+        let (url, requests) = serve(vec![
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::SERVICE_UNAVAILABLE,
+        ])
+        .await;
+        let x = NotificationData::example("admin", 1);
+
+        let results = sender(vec![url], 3).send_notification(x.clone()).await;
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].status, DeliveryStatus::Delivered);
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        let delivery_id = x.idempotency_key(Channel::Webhook).to_string();
+        for (id, timestamp, signature, body) in requests.iter() {
+            assert_eq!(id, &delivery_id);
+            assert_eq!(
+                signature,
+                &sender(vec![], 1).signature(timestamp.parse().unwrap(), body)
+            );
+        }
+        let payload: serde_json::Value = serde_json::from_slice(&requests[0].3).unwrap();
+        assert_eq!(payload["escalation_level"], 1);
+        assert_eq!(payload["outage_id"], x.outage_id.to_string());
+    }
+
+    #[tokio::test]
+    async fn post_gives_up_after_max_attempts() {
+        let (url, requests) = serve(vec![StatusCode::INTERNAL_SERVER_ERROR; 5]).await;
+
+        let results = sender(vec![url], 2)
+            .send_notification(NotificationData::example("admin", 1))
+            .await;
+
+        assert!(results[0].status.is_failed());
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+}